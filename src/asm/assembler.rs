@@ -0,0 +1,95 @@
+use crate::asm::instruction::{AddressValue, Instruction};
+use crate::asm::symbol_table::SymbolTable;
+
+const ROM_SIZE: usize = 32768;
+
+pub struct Assembler {
+  symbols: SymbolTable,
+}
+
+impl Assembler {
+  pub fn new() -> Self {
+    Self {
+      symbols: SymbolTable::new(),
+    }
+  }
+
+  /// Two pass translation:
+  ///   1. bind every label to the ROM address of the next real instruction.
+  ///   2. resolve symbols (allocating variables from 16) and encode.
+  pub fn assemble(mut self, instructions: Vec<Instruction>) -> Result<Vec<String>, String> {
+    let mut rom_address = 0;
+    for instruction in &instructions {
+      match instruction {
+        Instruction::Label(label) => self.symbols.add_label(label.clone(), rom_address as u16)?,
+        Instruction::None => (),
+        _ => rom_address += 1,
+      }
+    }
+    if rom_address > ROM_SIZE {
+      return Err(format!(
+        "Program too large: {} instructions, ROM holds {}",
+        rom_address, ROM_SIZE
+      ));
+    }
+
+    let mut ret = Vec::with_capacity(rom_address);
+    for instruction in &instructions {
+      let code = match instruction {
+        Instruction::Address(AddressValue::Symbol(symbol)) => {
+          let address = self.symbols.get_or_alloc_variable(symbol)?;
+          instruction.encode(Some(address))?
+        }
+        Instruction::Address(_) | Instruction::Compute { .. } => instruction.encode(None)?,
+        Instruction::Label(_) | Instruction::None => continue,
+      };
+      ret.push(code);
+    }
+    Ok(ret)
+  }
+
+  /// Assemble textual hack assembly, e.g. the output of `AssembleCodeGenerator`.
+  pub fn assemble_lines<S: AsRef<str>>(self, lines: &[S]) -> Result<Vec<String>, String> {
+    let mut instructions = Vec::with_capacity(lines.len());
+    for (idx, line) in lines.iter().enumerate() {
+      let instruction =
+        Instruction::parse(line.as_ref()).map_err(|e| format!("line {}: {}", idx + 1, e))?;
+      instructions.push(instruction);
+    }
+    self.assemble(instructions)
+  }
+}
+
+impl Default for Assembler {
+  fn default() -> Self {
+    Assembler::new()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn lines(text: &str) -> Vec<&str> {
+    text.lines().collect()
+  }
+
+  #[test]
+  fn assembles_max() {
+    let codes = Assembler::new()
+      .assemble_lines(&lines(include_str!("../../assets/asm/Max.asm")))
+      .unwrap();
+    assert_eq!(codes, lines(include_str!("../../assets/asm/Max.hack")));
+  }
+
+  #[test]
+  fn allocates_variables_from_16() {
+    let codes = Assembler::new()
+      .assemble_lines(&["@i", "M=1", "@sum", "M=0", "@i", "(LOOP)", "@LOOP"])
+      .unwrap();
+    assert_eq!(codes[0], format!("{:016b}", 16));
+    assert_eq!(codes[2], format!("{:016b}", 17));
+    assert_eq!(codes[4], format!("{:016b}", 16));
+    assert_eq!(codes[5], format!("{:016b}", 5));
+  }
+}
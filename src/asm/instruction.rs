@@ -0,0 +1,204 @@
+#[derive(std::cmp::PartialEq, Debug, Clone)]
+pub enum AddressValue {
+  Constant(u16),
+  Symbol(String),
+}
+
+#[derive(std::cmp::PartialEq, Debug, Clone)]
+pub enum Instruction {
+  None,
+  // @value
+  Address(AddressValue),
+  // dest=comp;jump
+  Compute {
+    dest: Option<String>,
+    comp: String,
+    jump: Option<String>,
+  },
+  // (label)
+  Label(String),
+}
+
+fn is_symbol_char(c: char) -> bool {
+  c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '$' | ':')
+}
+
+fn is_valid_symbol(symbol: &str) -> bool {
+  match symbol.chars().next() {
+    Some(first) if !first.is_ascii_digit() => symbol.chars().all(is_symbol_char),
+    _ => false,
+  }
+}
+
+fn comp_bits_without_swap(comp: &str) -> Option<&'static str> {
+  let bits = match comp {
+    "0" => "0101010",
+    "1" => "0111111",
+    "-1" => "0111010",
+    "D" => "0001100",
+    "A" => "0110000",
+    "M" => "1110000",
+    "!D" => "0001101",
+    "!A" => "0110001",
+    "!M" => "1110001",
+    "-D" => "0001111",
+    "-A" => "0110011",
+    "-M" => "1110011",
+    "D+1" => "0011111",
+    "A+1" => "0110111",
+    "M+1" => "1110111",
+    "D-1" => "0001110",
+    "A-1" => "0110010",
+    "M-1" => "1110010",
+    "D+A" => "0000010",
+    "D+M" => "1000010",
+    "D-A" => "0010011",
+    "D-M" => "1010011",
+    "A-D" => "0000111",
+    "M-D" => "1000111",
+    "D&A" => "0000000",
+    "D&M" => "1000000",
+    "D|A" => "0010101",
+    "D|M" => "1010101",
+    _ => return None,
+  };
+  Some(bits)
+}
+
+/// Returns `a c1..c6` bits of a computation, accepting both operand orders of
+/// the commutative operations (e.g. `M+D` as well as `D+M`).
+fn comp_bits(comp: &str) -> Option<&'static str> {
+  if let Some(bits) = comp_bits_without_swap(comp) {
+    return Some(bits);
+  }
+  for op in ['+', '&', '|'] {
+    if let Some((left, right)) = comp.split_once(op) {
+      return comp_bits_without_swap(&format!("{}{}{}", right, op, left));
+    }
+  }
+  None
+}
+
+fn dest_bits(dest: &Option<String>) -> Result<String, String> {
+  let mut bits = ['0', '0', '0'];
+  if let Some(dest) = dest {
+    for c in dest.chars() {
+      let idx = match c {
+        'A' => 0,
+        'D' => 1,
+        'M' => 2,
+        _ => return Err(format!("Invalid dest: {}", dest)),
+      };
+      if bits[idx] == '1' {
+        return Err(format!("Invalid dest: {}", dest));
+      }
+      bits[idx] = '1';
+    }
+  }
+  Ok(bits.iter().collect())
+}
+
+fn jump_bits(jump: &Option<String>) -> Result<&'static str, String> {
+  let bits = match jump.as_deref() {
+    None => "000",
+    Some("JGT") => "001",
+    Some("JEQ") => "010",
+    Some("JGE") => "011",
+    Some("JLT") => "100",
+    Some("JNE") => "101",
+    Some("JLE") => "110",
+    Some("JMP") => "111",
+    Some(jump) => return Err(format!("Invalid jump: {}", jump)),
+  };
+  Ok(bits)
+}
+
+impl Instruction {
+  /// Parse one line of hack assembly, comments and whitespace are ignored.
+  pub fn parse(line: &str) -> Result<Self, String> {
+    let line = match line.find("//") {
+      Some(comment_start) => &line[..comment_start],
+      None => line,
+    };
+    let line: String = line.chars().filter(|c| !c.is_whitespace()).collect();
+    if line.is_empty() {
+      return Ok(Instruction::None);
+    }
+    if let Some(value) = line.strip_prefix('@') {
+      return Instruction::parse_address(value);
+    }
+    if let Some(label) = line.strip_prefix('(') {
+      let label = label
+        .strip_suffix(')')
+        .ok_or(format!("Invalid label: {}", line))?;
+      if !is_valid_symbol(label) {
+        return Err(format!("Invalid label: {}", label));
+      }
+      return Ok(Instruction::Label(label.to_string()));
+    }
+    Instruction::parse_compute(&line)
+  }
+
+  fn parse_address(value: &str) -> Result<Self, String> {
+    if value.starts_with(|c: char| c.is_ascii_digit()) {
+      return match value.parse::<u16>() {
+        Ok(v) if v <= 0x7fff => Ok(Instruction::Address(AddressValue::Constant(v))),
+        _ => Err(format!("Invalid address constant: {}", value)),
+      };
+    }
+    if !is_valid_symbol(value) {
+      return Err(format!("Invalid symbol: {}", value));
+    }
+    Ok(Instruction::Address(AddressValue::Symbol(
+      value.to_string(),
+    )))
+  }
+
+  fn parse_compute(line: &str) -> Result<Self, String> {
+    let (dest, rest) = match line.split_once('=') {
+      Some((dest, rest)) => (Some(dest.to_string()), rest),
+      None => (None, line),
+    };
+    let (comp, jump) = match rest.split_once(';') {
+      Some((comp, jump)) => (comp, Some(jump.to_string())),
+      None => (rest, None),
+    };
+    let instruction = Instruction::Compute {
+      dest,
+      comp: comp.to_string(),
+      jump,
+    };
+    // Validate eagerly so that errors carry the offending line.
+    instruction.encode_compute()?;
+    Ok(instruction)
+  }
+
+  fn encode_compute(&self) -> Result<String, String> {
+    match self {
+      Instruction::Compute { dest, comp, jump } => {
+        let comp_bits = comp_bits(comp).ok_or(format!("Invalid comp: {}", comp))?;
+        Ok(format!(
+          "111{}{}{}",
+          comp_bits,
+          dest_bits(dest)?,
+          jump_bits(jump)?
+        ))
+      }
+      _ => Err(format!("Not a compute instruction: {:?}", self)),
+    }
+  }
+
+  /// Encode a compute instruction, or an address instruction whose value is
+  /// already resolved, into 16 binary digits.
+  pub fn encode(&self, address: Option<u16>) -> Result<String, String> {
+    match self {
+      Instruction::Address(AddressValue::Constant(v)) => Ok(format!("{:016b}", v)),
+      Instruction::Address(AddressValue::Symbol(s)) => match address {
+        Some(v) => Ok(format!("{:016b}", v)),
+        None => Err(format!("Unresolved symbol: {}", s)),
+      },
+      Instruction::Compute { .. } => self.encode_compute(),
+      _ => Err(format!("Instruction {:?} has no machine code", self)),
+    }
+  }
+}
@@ -0,0 +1,3 @@
+pub mod assembler;
+pub mod instruction;
+pub mod symbol_table;
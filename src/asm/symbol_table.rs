@@ -0,0 +1,64 @@
+use std::collections::HashMap;
+
+const VARIABLE_BASE: u16 = 16;
+const SCREEN: u16 = 16384;
+const KBD: u16 = 24576;
+
+pub struct SymbolTable {
+  symbols: HashMap<String, u16>,
+  next_variable: u16,
+}
+
+impl SymbolTable {
+  pub fn new() -> Self {
+    let mut symbols = HashMap::new();
+    for (i, name) in ["SP", "LCL", "ARG", "THIS", "THAT"].iter().enumerate() {
+      symbols.insert(name.to_string(), i as u16);
+    }
+    for i in 0..16 {
+      symbols.insert(format!("R{}", i), i);
+    }
+    symbols.insert("SCREEN".to_string(), SCREEN);
+    symbols.insert("KBD".to_string(), KBD);
+    Self {
+      symbols,
+      next_variable: VARIABLE_BASE,
+    }
+  }
+
+  pub fn contains(&self, symbol: &str) -> bool {
+    self.symbols.contains_key(symbol)
+  }
+
+  pub fn get(&self, symbol: &str) -> Option<u16> {
+    self.symbols.get(symbol).copied()
+  }
+
+  pub fn add_label(&mut self, label: String, address: u16) -> Result<(), String> {
+    if self.symbols.contains_key(&label) {
+      return Err(format!("Duplicate label: {}", label));
+    }
+    self.symbols.insert(label, address);
+    Ok(())
+  }
+
+  /// Find a symbol, allocate a new variable slot from 16 upward if absent.
+  pub fn get_or_alloc_variable(&mut self, symbol: &str) -> Result<u16, String> {
+    if let Some(address) = self.get(symbol) {
+      return Ok(address);
+    }
+    if self.next_variable >= SCREEN {
+      return Err(format!("Out of variable memory when allocating {}", symbol));
+    }
+    let address = self.next_variable;
+    self.symbols.insert(symbol.to_string(), address);
+    self.next_variable += 1;
+    Ok(address)
+  }
+}
+
+impl Default for SymbolTable {
+  fn default() -> Self {
+    SymbolTable::new()
+  }
+}
@@ -0,0 +1,241 @@
+//! Typed syntax tree of a Jack class, built by the
+//! [`Compiler`](crate::compiler::Compiler). Every node knows the span of the
+//! source it was read from.
+
+pub mod visit;
+
+use std::fmt::{Display, Formatter};
+
+use crate::error::Location;
+use crate::token::Span;
+use crate::vm::segment_type::SegmentType;
+
+/// A name, or a type, and where it is written.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Ident {
+  pub name: String,
+  pub span: Span,
+}
+
+impl Ident {
+  pub fn new(name: String, span: Span) -> Self {
+    Self { name, span }
+  }
+
+  /// Whether the type is written with a keyword: int, char, boolean or void.
+  pub fn is_keyword_type(&self) -> bool {
+    matches!(self.name.as_str(), "int" | "char" | "boolean" | "void")
+  }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Class {
+  // Source the class was read from, e.g. "Main.jack".
+  pub file: String,
+  pub name: Ident,
+  // Statics and fields.
+  pub variables: Vec<VarDec>,
+  pub subroutines: Vec<SubroutineDec>,
+  pub span: Span,
+}
+
+impl Class {
+  /// Location of `span` in the source of the class, for error messages.
+  pub fn location(&self, span: Span) -> Location {
+    Location::new(self.file.clone(), span)
+  }
+}
+
+/// Where a variable is declared, which decides the segment it lives in.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum VarScope {
+  // Class variables.
+  Static,
+  Field,
+  // Subroutine variables.
+  Argument,
+  Variable,
+}
+
+impl VarScope {
+  pub fn is_class_scope(&self) -> bool {
+    matches!(self, VarScope::Static | VarScope::Field)
+  }
+}
+
+impl From<VarScope> for SegmentType {
+  fn from(var_scope: VarScope) -> Self {
+    match var_scope {
+      VarScope::Static => SegmentType::Static,
+      VarScope::Field => SegmentType::This,
+      VarScope::Argument => SegmentType::Argument,
+      VarScope::Variable => SegmentType::Local,
+    }
+  }
+}
+
+impl Display for VarScope {
+  fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    match self {
+      VarScope::Static => write!(f, "static"),
+      VarScope::Field => write!(f, "field"),
+      VarScope::Argument => write!(f, "argument"),
+      VarScope::Variable => write!(f, "variable"),
+    }
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SubroutineType {
+  Constructor,
+  Function,
+  Method,
+}
+
+impl Display for SubroutineType {
+  fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    match self {
+      SubroutineType::Constructor => write!(f, "constructor"),
+      SubroutineType::Function => write!(f, "function"),
+      SubroutineType::Method => write!(f, "method"),
+    }
+  }
+}
+
+/// `static`, `field` or `var` declaration of one or more names of a type.
+#[derive(Debug, Clone, PartialEq)]
+pub struct VarDec {
+  pub kind: VarScope,
+  pub var_type: Ident,
+  pub names: Vec<Ident>,
+  pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Parameter {
+  pub var_type: Ident,
+  pub name: Ident,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SubroutineDec {
+  pub kind: SubroutineType,
+  // "void" for subroutines without a return value.
+  pub return_type: Ident,
+  pub name: Ident,
+  pub parameters: Vec<Parameter>,
+  pub locals: Vec<VarDec>,
+  pub statements: Vec<Statement>,
+  pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Statement {
+  Let {
+    target: Ident,
+    // Set for `let a[index] = value;`.
+    index: Option<Expr>,
+    value: Expr,
+    span: Span,
+  },
+  If {
+    condition: Expr,
+    body: Vec<Statement>,
+    else_body: Option<Vec<Statement>>,
+    span: Span,
+  },
+  While {
+    condition: Expr,
+    body: Vec<Statement>,
+    span: Span,
+  },
+  Do {
+    call: Call,
+    span: Span,
+  },
+  Return {
+    value: Option<Expr>,
+    span: Span,
+  },
+}
+
+impl Statement {
+  /// From the leading keyword to the closing `;` or `}`.
+  pub fn span(&self) -> Span {
+    match self {
+      Statement::Let { span, .. }
+      | Statement::If { span, .. }
+      | Statement::While { span, .. }
+      | Statement::Do { span, .. }
+      | Statement::Return { span, .. } => *span,
+    }
+  }
+}
+
+/// `name(arguments)` or `receiver.name(arguments)`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Call {
+  // Variable or class before the dot, none for subroutines of the class
+  // itself.
+  pub receiver: Option<Ident>,
+  pub name: Ident,
+  pub arguments: Vec<Expr>,
+  pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+  Int {
+    value: u16,
+    span: Span,
+  },
+  String {
+    value: String,
+    span: Span,
+  },
+  // true, false, null or this.
+  Keyword {
+    keyword: String,
+    span: Span,
+  },
+  Var(Ident),
+  Index {
+    array: Ident,
+    index: Box<Expr>,
+    span: Span,
+  },
+  Call(Call),
+  // '-' or '~'.
+  Unary {
+    op: char,
+    operand: Box<Expr>,
+    span: Span,
+  },
+  // Jack has no precedence, `a + b * c` is read as `(a + b) * c`.
+  Binary {
+    op: char,
+    op_span: Span,
+    left: Box<Expr>,
+    right: Box<Expr>,
+  },
+  Paren {
+    expr: Box<Expr>,
+    span: Span,
+  },
+}
+
+impl Expr {
+  pub fn span(&self) -> Span {
+    match self {
+      Expr::Int { span, .. }
+      | Expr::String { span, .. }
+      | Expr::Keyword { span, .. }
+      | Expr::Index { span, .. }
+      | Expr::Unary { span, .. }
+      | Expr::Paren { span, .. } => *span,
+      Expr::Var(ident) => ident.span,
+      Expr::Call(call) => call.span,
+      Expr::Binary { left, right, .. } => left.span().to(right.span()),
+    }
+  }
+}
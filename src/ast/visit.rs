@@ -0,0 +1,102 @@
+use crate::ast::*;
+
+/// Walks a class in source order. Every method walks the children of its
+/// node by default, an overriding method calls the matching `walk_*`
+/// function to keep going down.
+pub trait Visitor {
+  fn visit_class(&mut self, class: &Class) {
+    walk_class(self, class);
+  }
+
+  fn visit_var_dec(&mut self, _var_dec: &VarDec) {}
+
+  fn visit_subroutine(&mut self, subroutine: &SubroutineDec) {
+    walk_subroutine(self, subroutine);
+  }
+
+  fn visit_statement(&mut self, statement: &Statement) {
+    walk_statement(self, statement);
+  }
+
+  fn visit_call(&mut self, call: &Call) {
+    walk_call(self, call);
+  }
+
+  fn visit_expr(&mut self, expr: &Expr) {
+    walk_expr(self, expr);
+  }
+}
+
+pub fn walk_class<V: Visitor + ?Sized>(visitor: &mut V, class: &Class) {
+  for var_dec in &class.variables {
+    visitor.visit_var_dec(var_dec);
+  }
+  for subroutine in &class.subroutines {
+    visitor.visit_subroutine(subroutine);
+  }
+}
+
+pub fn walk_subroutine<V: Visitor + ?Sized>(visitor: &mut V, subroutine: &SubroutineDec) {
+  for var_dec in &subroutine.locals {
+    visitor.visit_var_dec(var_dec);
+  }
+  for statement in &subroutine.statements {
+    visitor.visit_statement(statement);
+  }
+}
+
+pub fn walk_statement<V: Visitor + ?Sized>(visitor: &mut V, statement: &Statement) {
+  match statement {
+    Statement::Let { index, value, .. } => {
+      if let Some(index) = index {
+        visitor.visit_expr(index);
+      }
+      visitor.visit_expr(value);
+    }
+    Statement::If {
+      condition,
+      body,
+      else_body,
+      ..
+    } => {
+      visitor.visit_expr(condition);
+      for statement in body.iter().chain(else_body.iter().flatten()) {
+        visitor.visit_statement(statement);
+      }
+    }
+    Statement::While {
+      condition, body, ..
+    } => {
+      visitor.visit_expr(condition);
+      for statement in body {
+        visitor.visit_statement(statement);
+      }
+    }
+    Statement::Do { call, .. } => visitor.visit_call(call),
+    Statement::Return { value, .. } => {
+      if let Some(value) = value {
+        visitor.visit_expr(value);
+      }
+    }
+  }
+}
+
+pub fn walk_call<V: Visitor + ?Sized>(visitor: &mut V, call: &Call) {
+  for argument in &call.arguments {
+    visitor.visit_expr(argument);
+  }
+}
+
+pub fn walk_expr<V: Visitor + ?Sized>(visitor: &mut V, expr: &Expr) {
+  match expr {
+    Expr::Index { index, .. } => visitor.visit_expr(index),
+    Expr::Call(call) => visitor.visit_call(call),
+    Expr::Unary { operand, .. } => visitor.visit_expr(operand),
+    Expr::Binary { left, right, .. } => {
+      visitor.visit_expr(left);
+      visitor.visit_expr(right);
+    }
+    Expr::Paren { expr, .. } => visitor.visit_expr(expr),
+    Expr::Int { .. } | Expr::String { .. } | Expr::Keyword { .. } | Expr::Var(_) => (),
+  }
+}
@@ -0,0 +1,5 @@
+// Jack language server over stdio. Nothing else may be printed on stdout,
+// so the logger is not set up.
+fn main() {
+  std::process::exit(jack_compiler::lsp::server::run_stdio());
+}
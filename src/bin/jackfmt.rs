@@ -0,0 +1,71 @@
+use clap::Parser;
+
+use jack_compiler::diagnostic::Diagnostic;
+use jack_compiler::format_source;
+use jack_compiler::sources::{find_sources, SourceFilter};
+
+#[derive(Parser, Debug)]
+#[clap(about = "Format Jack sources in place", version, author)]
+struct Args {
+  /// Jack files, or directories whose .jack files are formatted.
+  #[clap(required = true)]
+  paths: Vec<String>,
+
+  /// Only report the files that are not formatted, exit with 1 if there are any.
+  #[clap(long)]
+  check: bool,
+}
+
+// Returns whether the file is formatted, or was formatted successfully.
+fn format_file(file: &str, check: bool) -> bool {
+  let source = match std::fs::read_to_string(file) {
+    Ok(source) => source,
+    Err(e) => {
+      eprintln!("error: {}: {}", file, e);
+      return false;
+    }
+  };
+  let formatted = match format_source(file, &source) {
+    Ok(formatted) => formatted,
+    Err(errors) => {
+      for e in &errors {
+        eprintln!("{}", Diagnostic::from(e).render(Some(&source)));
+      }
+      eprintln!("error: {} was not formatted", file);
+      return false;
+    }
+  };
+  if formatted == source {
+    return true;
+  }
+  if check {
+    println!("{}", file);
+    return false;
+  }
+  if let Err(e) = std::fs::write(file, formatted) {
+    eprintln!("error: {}: {}", file, e);
+    return false;
+  }
+  true
+}
+
+fn main() {
+  let args = Args::parse();
+  let mut ok = true;
+  for path in &args.paths {
+    match find_sources(path, ".jack", &SourceFilter::default()) {
+      Ok(files) => {
+        for file in files {
+          ok &= format_file(&file, args.check);
+        }
+      }
+      Err(e) => {
+        eprintln!("error: {}", e);
+        ok = false;
+      }
+    }
+  }
+  if !ok {
+    std::process::exit(1);
+  }
+}
@@ -1,17 +1,21 @@
-use indextree::NodeId;
-use log::error;
+use std::collections::HashMap;
 
-use crate::operation::tree::OperationTree;
-use crate::operation::{BracketType, ConstantType, OperationType, SubroutineType, VarScope};
+use crate::ast::*;
+use crate::common::{new_output, OutputTarget};
+use crate::error::CompileError;
 use crate::symbol_table::*;
+use crate::token::Span;
 use crate::vm::segment_type::SegmentType;
+use crate::vm::source_map::SourceMap;
 use crate::vm::vm_writer::VmWriter;
 
 struct State {
   class_symbols: SymbolTable,
   func_symbols: SymbolTable,
   vm_writer: VmWriter,
-  block_return: bool,
+  // Kind of the subroutine being generated.
+  subroutine_type: SubroutineType,
+  errors: Vec<CompileError>,
 
   if_count: usize,
   while_count: usize,
@@ -19,12 +23,13 @@ struct State {
 
 // Mutable part of a code generation.
 impl State {
-  pub fn new(source: &str) -> Self {
+  pub fn new(output: OutputTarget, source_comments: bool) -> Self {
     Self {
       class_symbols: SymbolTable::new(),
       func_symbols: SymbolTable::new(),
-      vm_writer: VmWriter::new(source),
-      block_return: false,
+      vm_writer: VmWriter::new_with_output(output).with_source_comments(source_comments),
+      subroutine_type: SubroutineType::Function,
+      errors: vec![],
       if_count: 0,
       while_count: 0,
     }
@@ -47,32 +52,57 @@ impl State {
   }
 }
 
-pub struct CodeWriter {
-  op_tree: OperationTree,
-  source: String,
-  class_name: Option<String>,
+pub struct CodeWriter<'a> {
+  class: &'a Class,
+  output: OutputTarget,
+  // Kind of every subroutine of the class, by name.
+  subroutines: HashMap<String, SubroutineType>,
+  source_comments: bool,
 }
 
-impl CodeWriter {
-  pub fn new(source: &str, op_tree: OperationTree) -> Self {
+impl<'a> CodeWriter<'a> {
+  pub fn new(source: &str, class: &'a Class) -> Self {
+    CodeWriter::new_with_output(new_output(source), class)
+  }
+
+  pub fn new_with_output(output: OutputTarget, class: &'a Class) -> Self {
+    let subroutines = class
+      .subroutines
+      .iter()
+      .map(|subroutine| (subroutine.name.name.clone(), subroutine.kind))
+      .collect();
     Self {
-      op_tree,
-      source: String::from(source),
-      class_name: None,
+      class,
+      output,
+      subroutines,
+      source_comments: false,
     }
   }
 
-  pub fn generate_vm_code(mut self) {
-    let root = self.op_tree.root();
-    match self.get_node_data(root) {
-      OperationType::Class(class) => self.class_name = Some(class.to_string()),
-      _ => panic!(""),
+  /// Precede the commands of every source line with a `// Main.jack:14`
+  /// comment.
+  pub fn with_source_comments(mut self, enabled: bool) -> Self {
+    self.source_comments = enabled;
+    self
+  }
+
+  /// Write the vm code of the class, returns the source of every written
+  /// line. The output is incomplete when errors are returned.
+  pub fn generate_vm_code(self) -> Result<SourceMap, Vec<CompileError>> {
+    let mut state = State::new(self.output.clone(), self.source_comments);
+    self.handle_class(&mut state);
+    if state.errors.is_empty() {
+      Ok(state.vm_writer.source_map().clone())
+    } else {
+      Err(state.errors)
     }
-    let mut children = self.op_tree.get_children(root);
-    let class = children.next().unwrap();
-    let mut state = State::new(&self.source[..]);
+  }
 
-    self.handle_tree(class, &mut state);
+  fn error(&self, span: Span, message: String, state: &mut State) {
+    let location = self.class.location(span);
+    state
+      .errors
+      .push(CompileError::semantic(&message, location));
   }
 
   /**
@@ -80,19 +110,12 @@ impl CodeWriter {
    *    class level var declaration *
    *    subroutine declaration      *
    */
-  fn handle_tree(self, root: NodeId, state: &mut State) {
-    for child_id in self.op_tree.get_children(root) {
-      let child_data = self.get_node_data(child_id);
-      match child_data {
-        OperationType::ClassVarDec(scope) => {
-          self.handle_var_dec(child_id, *scope, state);
-        }
-        OperationType::SubroutineDec(subroutine_type) => {
-          self.handle_subroutine(child_id, *subroutine_type, state);
-        }
-        OperationType::Bracket(_) => (),
-        _ => panic!("Failed to compile class at {:#?}", child_data),
-      }
+  fn handle_class(&self, state: &mut State) {
+    for var_dec in &self.class.variables {
+      self.handle_var_dec(var_dec, state);
+    }
+    for subroutine in &self.class.subroutines {
+      self.handle_subroutine(subroutine, state);
     }
   }
 
@@ -103,54 +126,31 @@ impl CodeWriter {
    *    parameter list
    *    subroutine body
    */
-  fn handle_subroutine(&self, root: NodeId, subroutine_t: SubroutineType, state: &mut State) {
-    let mut children = self.op_tree.get_children(root).clone();
-    // TODO: Add function name table for class.
-    let _ret_type = match self.get_node_data(children.next().unwrap()) {
-      OperationType::Type(type_name, _) => type_name,
-      OperationType::Void => "void",
-      _ => panic!(""),
-    };
-    let func_name = match self.get_node_data(children.next().unwrap()) {
-      OperationType::VarName(func_name) => func_name,
-      _ => panic!(""),
-    };
-    let func_name = format!("{}.{}", self.class_name.as_ref().unwrap(), func_name);
-    children.next();
-    let parameter_list_id = children.next().unwrap();
-    match self.get_node_data(parameter_list_id) {
-      OperationType::ParameterList => true,
-      _ => panic!(""),
-    };
-    let func_body = children.next().unwrap();
-
-    if subroutine_t == SubroutineType::Method {
+  fn handle_subroutine(&self, subroutine: &SubroutineDec, state: &mut State) {
+    let func_name = format!("{}.{}", self.class.name.name, subroutine.name.name);
+    if subroutine.kind == SubroutineType::Method {
       state.func_symbols.push_item(
         String::from("this"),
         String::from("this"),
         VarScope::Argument,
       );
     }
-    let _argc = self.handle_parameter_list(parameter_list_id, state);
+    for parameter in &subroutine.parameters {
+      state.func_symbols.push_item(
+        parameter.name.name.clone(),
+        parameter.var_type.name.clone(),
+        VarScope::Argument,
+      );
+    }
 
-    match subroutine_t {
-      SubroutineType::Constructor => {
-        state.class_symbols.enable_field();
-        state.block_return = true;
-        self.handle_subroutine_body(func_body, func_name, subroutine_t, state);
-        state.block_return = false;
-        state.vm_writer.generate_return_this();
-      }
-      SubroutineType::Function => {
-        // Function don't have access to field.
-        state.class_symbols.disable_field();
-        self.handle_subroutine_body(func_body, func_name, subroutine_t, state);
-      }
-      SubroutineType::Method => {
-        state.class_symbols.enable_field();
-        self.handle_subroutine_body(func_body, func_name, subroutine_t, state);
-      }
-    };
+    state.subroutine_type = subroutine.kind;
+    if subroutine.kind == SubroutineType::Function {
+      // Function don't have access to field.
+      state.class_symbols.disable_field();
+    } else {
+      state.class_symbols.enable_field();
+    }
+    self.handle_subroutine_body(subroutine, func_name, state);
 
     state.func_symbols.clear();
   }
@@ -162,42 +162,29 @@ impl CodeWriter {
    */
   fn handle_subroutine_body(
     &self,
-    root: NodeId,
+    subroutine: &SubroutineDec,
     func_name: String,
-    subroutine_type: SubroutineType,
     state: &mut State,
   ) {
     let mut var_cnt = 0;
-    let mut has_statements = false;
-    // Skip Bracket
-    for child_id in self.op_tree.get_children(root).skip(1) {
-      match self.get_node_data(child_id) {
-        OperationType::VarDec => {
-          if has_statements {
-            panic!("Local var declaration after statements.");
-          }
-          var_cnt += self.handle_var_dec(child_id, VarScope::Variable, state);
-        }
-        OperationType::Statements => {
-          if has_statements {
-            panic!("multiple statements in one function.");
-          }
-          state.vm_writer.write_func(func_name.clone(), var_cnt);
-          if subroutine_type == SubroutineType::Constructor {
-            state
-              .vm_writer
-              .generate_alloc_this(state.class_symbols.scope_item_count(VarScope::Field));
-          } else if subroutine_type == SubroutineType::Method {
-            // Set this pointer.
-            state.vm_writer.write_push(SegmentType::Argument, 0);
-            state.vm_writer.write_pop(SegmentType::Pointer, 0);
-          }
-          has_statements = true;
-          self.handle_statements(child_id, state)
-        }
-        _ => panic!(""),
-      }
+    for var_dec in &subroutine.locals {
+      var_cnt += self.handle_var_dec(var_dec, state);
     }
+    let file = self.class.file.rsplit('/').next().unwrap_or_default();
+    state
+      .vm_writer
+      .set_subroutine(file, &func_name, subroutine.span.start_line);
+    state.vm_writer.write_func(func_name, var_cnt);
+    if subroutine.kind == SubroutineType::Constructor {
+      state
+        .vm_writer
+        .generate_alloc_this(state.class_symbols.scope_item_count(VarScope::Field));
+    } else if subroutine.kind == SubroutineType::Method {
+      // Set this pointer.
+      state.vm_writer.write_push(SegmentType::Argument, 0);
+      state.vm_writer.write_pop(SegmentType::Pointer, 0);
+    }
+    self.handle_statements(&subroutine.statements, state)
   }
 
   /**
@@ -205,126 +192,54 @@ impl CodeWriter {
    *    var type
    *    var name *
    */
-  fn handle_var_dec(&self, root: NodeId, scope: VarScope, state: &mut State) -> usize {
-    let mut children = self.op_tree.get_children(root);
-    let var_type_node = children.next().unwrap();
-    let var_type = match self.get_node_data(var_type_node) {
-      OperationType::Type(var_type, _) => var_type.clone(),
-      _ => panic!(""),
-    };
-    let var_name_list = children.next().unwrap();
-    let var_name_list = self.get_node_data(var_name_list);
-    match var_name_list {
-      OperationType::VarNameList(names) => {
-        for name in names {
-          state.insert_symbol(name.clone(), var_type.clone(), scope.clone());
-        }
-        names.len()
-      }
-      _ => panic!(""),
+  fn handle_var_dec(&self, var_dec: &VarDec, state: &mut State) -> usize {
+    for name in &var_dec.names {
+      state.insert_symbol(
+        name.name.clone(),
+        var_dec.var_type.name.clone(),
+        var_dec.kind,
+      );
     }
+    var_dec.names.len()
   }
 
   /**
    *  statements
    *    Let|If|While|Do|Return *
    */
-  fn handle_statements(&self, root: NodeId, state: &mut State) {
-    let mut children = self.op_tree.get_children(root);
-    let mut child_node = children.next();
-    loop {
-      if let Some(child_id) = child_node {
-        let (next_child, child_data) = {
-          let child = self.op_tree.get_node(child_id);
-          (child.next_sibling(), child.get())
-        };
-        match child_data.clone() {
-          OperationType::LetStatement(var_name) => {
-            self.handle_let_statement(var_name.clone(), child_id, state)
-          }
-          OperationType::IfStatement => self.handle_if_statement(child_id, state),
-          OperationType::WhileStatement => self.handle_while_statement(child_id, state),
-          OperationType::DoStatement => self.handle_do_statement(child_id, state),
-          OperationType::ReturnStatement => self.handle_return_statement(child_id, state),
-          _ => panic!(""),
-        };
-        child_node = next_child;
-      } else {
-        break;
+  fn handle_statements(&self, statements: &[Statement], state: &mut State) {
+    for statement in statements {
+      state.vm_writer.set_line(statement.span().start_line);
+      match statement {
+        Statement::Let {
+          target,
+          index,
+          value,
+          ..
+        } => self.handle_let_statement(target, index.as_ref(), value, state),
+        Statement::If {
+          condition,
+          body,
+          else_body,
+          span,
+        } => self.handle_if_statement(condition, body, else_body.as_deref(), *span, state),
+        Statement::While {
+          condition,
+          body,
+          span,
+        } => self.handle_while_statement(condition, body, *span, state),
+        Statement::Do { call, .. } => self.handle_do_statement(call, state),
+        Statement::Return { value, .. } => self.handle_return_statement(value.as_ref(), state),
       }
     }
   }
 
-  /**
-   *  parameter list
-   *    [type name]
-   *    [concat type name]*
-   */
-  fn handle_parameter_list(&self, root: NodeId, state: &mut State) -> usize {
-    let mut children = self.op_tree.get_children(root);
-    let mut argc = 0;
-
-    let mut may_child_node = children.next();
-    if let None = may_child_node {
-      return argc;
-    }
-    loop {
-      let child_node_id = may_child_node.unwrap();
-      argc += 1;
-      let var_type = match self.get_node_data(child_node_id) {
-        OperationType::Type(type_name, _) => type_name.clone(),
-        _ => panic!(""),
-      };
-      let var_name_id = children.next().unwrap();
-      let var_name = match self.get_node_data(var_name_id) {
-        OperationType::VarName(name) => name.clone(),
-        _ => panic!(""),
-      };
-      may_child_node = children.next();
-      state
-        .func_symbols
-        .push_item(var_name, var_type, VarScope::Argument);
-      if let Some(_) = may_child_node {
-        may_child_node = children.next();
-      } else {
-        break;
-      }
-    }
-    argc
-  }
-
-  /**
-   *   expression list
-   *    expression
-   *    [concat expression]*
-   */
-  fn handle_expression_list(&self, root: NodeId, state: &mut State) -> usize {
-    let mut children = self.op_tree.get_children(root);
-    let mut may_expression = children.next();
-    let mut argc = 0;
-    loop {
-      if may_expression.is_none() {
-        break;
-      }
-      let expression = may_expression.unwrap();
-      argc += 1;
-      self.generate_expression(expression, state);
-      let may_next = children.next();
-      if let Some(_) = may_next {
-        may_expression = children.next();
-      } else {
-        may_expression = may_next;
-      }
-    }
-    argc
-  }
-
   /**
    *  do statement
    *    subroutine call
    */
-  fn handle_do_statement(&self, root: NodeId, state: &mut State) {
-    self.generate_subroutine_call(root, state);
+  fn handle_do_statement(&self, call: &Call, state: &mut State) {
+    self.generate_subroutine_call(call, state);
     state.vm_writer.write_pop(SegmentType::Temp, 0);
   }
 
@@ -351,36 +266,36 @@ impl CodeWriter {
    *      else statements
    *      end-label
    */
-  fn handle_if_statement(&self, root: NodeId, state: &mut State) {
-    let mut children = self.op_tree.get_children(root);
-    // Bracket
-    children.next();
-    // Expression
-    let condition = children.next().unwrap();
-    // Bracket
-    children.next();
-    // Statements
-    let if_body = children.next().unwrap();
+  fn handle_if_statement(
+    &self,
+    condition: &Expr,
+    if_body: &[Statement],
+    else_body: Option<&[Statement]>,
+    span: Span,
+    state: &mut State,
+  ) {
     let if_failed_label = format!("IFFAILEDLABEL{}", state.if_count);
     let if_end_label = format!("IFENDLABEL{}", state.if_count);
     state.if_count += 1;
 
-    if let Some(_) = children.next() {
-      children.next();
-      let else_body = children.next().unwrap();
+    if let Some(else_body) = else_body {
       self.generate_expression(condition, state);
       state.vm_writer.write_arithmetic('~');
       state.vm_writer.write_if(if_failed_label.clone());
       self.handle_statements(if_body, state);
+      // The jumps around the bodies belong to the if.
+      state.vm_writer.set_line(span.start_line);
       state.vm_writer.write_goto(if_end_label.clone());
       state.vm_writer.write_label(if_failed_label);
       self.handle_statements(else_body, state);
+      state.vm_writer.set_line(span.start_line);
       state.vm_writer.write_label(if_end_label);
     } else {
       self.generate_expression(condition, state);
       state.vm_writer.write_arithmetic('~');
       state.vm_writer.write_if(if_failed_label.clone());
       self.handle_statements(if_body, state);
+      state.vm_writer.set_line(span.start_line);
       state.vm_writer.write_label(if_failed_label);
     }
   }
@@ -399,14 +314,13 @@ impl CodeWriter {
    *    goto start-label
    *    end-label
    */
-  fn handle_while_statement(&self, root: NodeId, state: &mut State) {
-    let mut children = self.op_tree.get_children(root);
-    // Bracket (
-    children.next();
-    let condition = children.next().unwrap();
-    // Bracket {
-    children.next();
-    let statement = children.next().unwrap();
+  fn handle_while_statement(
+    &self,
+    condition: &Expr,
+    body: &[Statement],
+    span: Span,
+    state: &mut State,
+  ) {
     let while_start_label = format!("WHILESTART{}", state.while_count);
     let while_end_label = format!("WHILEEND{}", state.while_count);
     state.while_count += 1;
@@ -416,7 +330,8 @@ impl CodeWriter {
     state.vm_writer.write_arithmetic('~');
     state.vm_writer.write_if(while_end_label.clone());
     // statements
-    self.handle_statements(statement, state);
+    self.handle_statements(body, state);
+    state.vm_writer.set_line(span.start_line);
     state.vm_writer.write_goto(while_start_label);
     state.vm_writer.write_label(while_end_label);
   }
@@ -428,20 +343,10 @@ impl CodeWriter {
    *  impl:
    *    if no expression, push constant 0 into stack
    */
-  fn handle_return_statement(&self, root: NodeId, state: &mut State) {
-    if state.block_return {
-      return;
-    }
-    let mut children = self.op_tree.get_children(root);
-    if let Some(first_child) = children.next() {
-      match self.get_node_data(first_child) {
-        OperationType::Expression => {
-          self.generate_expression(first_child, state);
-        }
-        _ => panic!(""),
-      };
-    } else {
-      state.vm_writer.write_push(SegmentType::Constant, 0);
+  fn handle_return_statement(&self, value: Option<&Expr>, state: &mut State) {
+    match value {
+      Some(value) => self.generate_expression(value, state),
+      None => state.vm_writer.write_push(SegmentType::Constant, 0),
     }
     state.vm_writer.write_return();
   }
@@ -452,23 +357,24 @@ impl CodeWriter {
    *    1. let var = expression;
    *    2. let var[idx] = expression;
    */
-  fn handle_let_statement(&self, var_name: String, root: NodeId, state: &mut State) {
-    let mut children = self.op_tree.get_children(root);
-    let var_name_item = state.get_variable(&var_name);
-    if var_name_item.is_none() {
-      error!("error when compile, var {} not found", var_name);
-      return;
-    }
-    let var_name_item = var_name_item.unwrap().clone();
-    let first_child_id = children.next().unwrap();
-    let first_child_data = self.get_node_data(first_child_id).clone();
-    match first_child_data {
-      OperationType::Bracket(BracketType::Square) => {
-        let array_index_node = children.next().unwrap();
-        children.next();
-        let right_expression_node = children.next().unwrap();
-        self.generate_expression(right_expression_node, state);
-        self.generate_expression(array_index_node, state);
+  fn handle_let_statement(
+    &self,
+    target: &Ident,
+    index: Option<&Expr>,
+    value: &Expr,
+    state: &mut State,
+  ) {
+    let var_name_item = match state.get_variable(&target.name) {
+      Some(var) => var.clone(),
+      None => {
+        let message = format!("undefined variable '{}'", target.name);
+        return self.error(target.span, message, state);
+      }
+    };
+    match index {
+      Some(index) => {
+        self.generate_expression(value, state);
+        self.generate_expression(index, state);
         state.vm_writer.write_push(
           SegmentType::from(var_name_item.get_kind()),
           var_name_item.get_idx(),
@@ -477,173 +383,155 @@ impl CodeWriter {
         state.vm_writer.write_pop(SegmentType::Pointer, 1);
         state.vm_writer.write_pop(SegmentType::That, 0);
       }
-      OperationType::Op('=') => {
-        let expression_node = children.next().unwrap();
-        self.generate_expression(expression_node, state);
+      None => {
+        self.generate_expression(value, state);
         state.vm_writer.write_pop(
           SegmentType::from(var_name_item.get_kind()),
           var_name_item.get_idx(),
         );
       }
-      _ => panic!(""),
     }
   }
 
   /**
    *  expression
    *  syntax:
-   *    term
-   *    (op term)*
+   *    1. constant
+   *    2. var name
+   *    3. var name [ index ]
+   *    4. ( expression )
+   *    5. op term
+   *    6. subroutine call
+   *    7. expression op term, from left to right
    */
-  fn generate_expression(&self, root: NodeId, state: &mut State) {
-    let mut children = self.op_tree.get_children(root);
-    assert_eq!(*self.get_node_data(root), OperationType::Expression);
-    let mut left = children.next().unwrap();
-    self.generate_term(left, state);
-    loop {
-      let op = children.next();
-      if let Some(op) = op {
-        left = children.next().unwrap();
-        self.generate_term(left, state);
-        match self.get_node_data(op) {
-          OperationType::Op(c) => state.vm_writer.write_arithmetic(*c),
-          _ => panic!(""),
-        }
-      } else {
-        break;
-      }
-    }
-  }
-
-  fn generate_constant(&self, const_t: &ConstantType, vm_writer: &mut VmWriter) {
-    match const_t {
-      ConstantType::Integer(i) => {
-        let constant = *i as usize;
-        vm_writer.write_push(SegmentType::Constant, constant);
+  fn generate_expression(&self, expr: &Expr, state: &mut State) {
+    match expr {
+      Expr::Int { value, .. } => {
+        state
+          .vm_writer
+          .write_push(SegmentType::Constant, *value as usize);
       }
-      ConstantType::String(s) => {
-        let s_const = s.clone();
-        vm_writer.write_push(SegmentType::Constant, s_const.len());
+      Expr::String { value, .. } => {
+        // String.appendChar returns the string itself, which stays on the
+        // stack as `this` of the next call and as the value of the constant.
+        let vm_writer = &mut state.vm_writer;
+        vm_writer.write_push(SegmentType::Constant, value.len());
         vm_writer.write_call("String.new".to_string(), 1);
-        vm_writer.write_pop(SegmentType::Temp, 2);
-        for c in s_const.as_bytes() {
-          vm_writer.write_push(SegmentType::Temp, 2);
+        for c in value.as_bytes() {
           vm_writer.write_push(SegmentType::Constant, *c as usize);
           vm_writer.write_call("String.appendChar".to_string(), 2);
         }
       }
-      ConstantType::KeyWord(k) => {
-        if k == "true" {
+      Expr::Keyword { keyword, .. } => {
+        let vm_writer = &mut state.vm_writer;
+        if keyword == "true" {
           vm_writer.write_push(SegmentType::Constant, 1);
           vm_writer.write_arithmetic('^');
-        } else if k == "false" || k == "null" {
+        } else if keyword == "false" || keyword == "null" {
           vm_writer.write_push(SegmentType::Constant, 0);
-        } else if k == "this" {
+        } else if keyword == "this" {
           vm_writer.write_push(SegmentType::Pointer, 0);
         }
       }
-    };
-  }
-  /**
-   *  term
-   *  syntax:
-   *    1. constant
-   *    2. var name
-   *    3. var name [ index ]
-   *    4. ( expression )
-   *    5. op term
-   */
-  fn generate_term(&self, term_node: NodeId, state: &mut State) {
-    match self.get_node_data(term_node) {
-      OperationType::Term => true,
-      _ => panic!(""),
-    };
-    let mut children = self.op_tree.get_children(term_node);
-    let first_child = children.next().unwrap();
-    let may_second_child = children.next();
-    match self.get_node_data(first_child) {
-      OperationType::Constant(const_t) => {
-        self.generate_constant(const_t, &mut state.vm_writer);
-      }
-      OperationType::VarName(var_name) => {
-        let may_var = state.get_variable(var_name);
-        if may_var.is_none() {
-          error!("error when compile, var {} not found", var_name);
-          return;
+      Expr::Var(name) => {
+        if let Some(var) = self.variable(name, state) {
+          state
+            .vm_writer
+            .write_push(var.get_kind().into(), var.get_idx());
         }
-        let var = may_var.unwrap().clone();
-        state
-          .vm_writer
-          .write_push(var.get_kind().into(), var.get_idx());
-        if may_second_child.is_some() {
-          // array index
-          let next_child = children.next().unwrap();
-          self.generate_expression(next_child, state);
+      }
+      Expr::Index { array, index, .. } => {
+        if let Some(var) = self.variable(array, state) {
+          state
+            .vm_writer
+            .write_push(var.get_kind().into(), var.get_idx());
+          self.generate_expression(index, state);
           state.vm_writer.write_arithmetic('+');
           state.vm_writer.write_pop(SegmentType::Pointer, 1);
           state.vm_writer.write_push(SegmentType::That, 0);
         }
       }
-      OperationType::Op(op) => {
-        self.generate_term(may_second_child.unwrap(), state);
-        state.vm_writer.write_arithmetic(*op);
-      }
-      OperationType::Bracket(_) => {
-        self.generate_expression(may_second_child.unwrap(), state);
-      }
-      OperationType::SubroutineCall(_, _) => {
-        self.generate_subroutine_call(term_node, state);
+      Expr::Call(call) => self.generate_subroutine_call(call, state),
+      Expr::Unary { op, operand, .. } => {
+        self.generate_expression(operand, state);
+        // The vm writer marks negation as '^'.
+        state
+          .vm_writer
+          .write_arithmetic(if *op == '-' { '^' } else { *op });
       }
-      _ => {
-        panic!(
-          "Failed to compile term at {:?}",
-          self.get_node_data(first_child)
-        )
+      Expr::Binary {
+        op, left, right, ..
+      } => {
+        self.generate_expression(left, state);
+        self.generate_expression(right, state);
+        state.vm_writer.write_arithmetic(*op);
       }
+      Expr::Paren { expr, .. } => self.generate_expression(expr, state),
     }
   }
 
+  // Variable used at `name`, reports undefined variables.
+  fn variable(&self, name: &Ident, state: &mut State) -> Option<VariableSymbolItem> {
+    let var = state.get_variable(&name.name).cloned();
+    if var.is_none() {
+      let message = format!("undefined variable '{}'", name.name);
+      self.error(name.span, message, state);
+    }
+    var
+  }
+
   /**
    *  subroutine call
+   *  impl:
+   *    methods get the object as argument 0,
+   *    functions and constructors are called without it.
    *  syntax:
-   *    1. class name.function name
-   *    2. function name
-   *    ( expression list )
+   *    1. var name.method name ( expression list )
+   *    2. class name.function name ( expression list )
+   *    3. subroutine name ( expression list ), of the current class
    */
-  fn generate_subroutine_call(&self, root: NodeId, state: &mut State) {
-    let mut children = self.op_tree.get_children(root);
-    let subroutine_call_node = children.next().unwrap();
-    let expressions = children.skip(1).next().unwrap();
+  fn generate_subroutine_call(&self, call: &Call, state: &mut State) {
     let mut has_this = true;
-    let subroutine_call = match self.get_node_data(subroutine_call_node) {
-      OperationType::SubroutineCall(first_name, second_name) => match first_name {
-        Some(first_name) => {
-          let tmp_may_var = state.get_variable(&first_name);
-          if tmp_may_var.is_some() {
-            let var = tmp_may_var.unwrap().clone();
-            // Push var into argument list as first parameter.
-            state
-              .vm_writer
-              .write_push(var.get_kind().into(), var.get_idx());
-            format!("{}.{}", var.get_type(), second_name)
-          } else {
+    let second_name = &call.name.name;
+    let subroutine_call = match &call.receiver {
+      Some(first_name) => {
+        if let Some(var) = state.get_variable(&first_name.name) {
+          let var = var.clone();
+          // Push var into argument list as first parameter.
+          state
+            .vm_writer
+            .write_push(var.get_kind().into(), var.get_idx());
+          format!("{}.{}", var.get_type(), second_name)
+        } else {
+          has_this = false;
+          format!("{}.{}", first_name.name, second_name)
+        }
+      }
+      None => {
+        match self.subroutines.get(second_name) {
+          Some(SubroutineType::Function) | Some(SubroutineType::Constructor) => {
             has_this = false;
-            format!("{}.{}", first_name, second_name)
+          }
+          _ if state.subroutine_type == SubroutineType::Function => {
+            let message = format!(
+              "method '{}' called from a function without an object",
+              second_name
+            );
+            self.error(call.span, message, state);
+            has_this = false;
+          }
+          _ => {
+            // Push this into argument list as first parameter.
+            state.vm_writer.write_push(SegmentType::Pointer, 0);
           }
         }
-        None => {
-          // Push var into argument list as first parameter.
-          state.vm_writer.write_push(SegmentType::Pointer, 0);
-          format!("{}.{}", self.class_name.as_ref().unwrap(), second_name)
-        }
-      },
-      _ => panic!(""),
+        format!("{}.{}", self.class.name.name, second_name)
+      }
     };
-    let argc = self.handle_expression_list(expressions, state) + if has_this { 1 } else { 0 };
+    for argument in &call.arguments {
+      self.generate_expression(argument, state);
+    }
+    let argc = call.arguments.len() + if has_this { 1 } else { 0 };
     state.vm_writer.write_call(subroutine_call, argc);
   }
-
-  fn get_node_data(&self, node_id: NodeId) -> &OperationType {
-    self.op_tree.get_node(node_id).get()
-  }
 }
@@ -3,13 +3,49 @@ use std::fs::File;
 use std::io::{BufWriter, Write};
 use std::rc::Rc;
 
-pub type OutputTarget = Rc<RefCell<BufWriter<File>>>;
+pub type OutputTarget = Rc<RefCell<Box<dyn Write>>>;
 
 pub fn new_output(source: &str) -> OutputTarget {
-  let file = File::create(source).expect(format!("{} file open failed", source).as_str());
-  Rc::new(RefCell::new(BufWriter::new(file)))
+  let file = File::create(source).unwrap_or_else(|_| panic!("{} file open failed", source));
+  new_output_with(BufWriter::new(file))
 }
 
-pub fn panic_writer(data: String, mut writer: RefMut<BufWriter<File>>) {
-  (*writer).write(data.as_bytes()).unwrap();
+pub fn new_output_with<W: Write + 'static>(writer: W) -> OutputTarget {
+  Rc::new(RefCell::new(Box::new(writer)))
+}
+
+/// Output kept in memory, cloned handles share the same buffer so the text can
+/// be read back once a generator is done with its target.
+#[derive(Clone, Default)]
+pub struct MemoryOutput {
+  buffer: Rc<RefCell<Vec<u8>>>,
+}
+
+impl MemoryOutput {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  pub fn target(&self) -> OutputTarget {
+    new_output_with(self.clone())
+  }
+
+  pub fn contents(&self) -> String {
+    String::from_utf8_lossy(&self.buffer.borrow()).to_string()
+  }
+}
+
+impl Write for MemoryOutput {
+  fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+    self.buffer.borrow_mut().extend_from_slice(buf);
+    Ok(buf.len())
+  }
+
+  fn flush(&mut self) -> std::io::Result<()> {
+    Ok(())
+  }
+}
+
+pub fn panic_writer(data: String, mut writer: RefMut<Box<dyn Write>>) {
+  writer.write_all(data.as_bytes()).unwrap();
 }
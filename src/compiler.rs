@@ -1,37 +1,45 @@
-use std::cell::RefCell;
-use std::rc::Rc;
-
-use crate::operation::*;
+use crate::ast::*;
+use crate::error::{CompileError, Location};
 use crate::parser::jack::Parser;
-use crate::token::{is_keyword_constant, is_unary_operation, Token, TokenDescriptor};
-use crate::xml::operation_xml_generator::{OperationXMLGenerator, RAIIWriter};
-
-pub trait WritableStack {
-  fn push(&mut self, node_type: OperationType);
-  fn pop(&mut self);
-}
-
-pub type WriteTarget = Rc<RefCell<dyn WritableStack>>;
+use crate::token::{is_keyword_constant, is_unary_operation, Span, Token};
 
 struct TokenReader {
   parser: Parser,
 }
 
+type CompileResult<T> = Result<T, CompileError>;
+
 impl TokenReader {
   pub(crate) fn new(parser: Parser) -> Self {
     Self { parser }
   }
 
-  pub(crate) fn take_keyword(&mut self, keyword: String) -> bool {
-    let class_token = self.parser.next();
-    let class_token = class_token.unwrap_or(Token::None);
-    if let Token::KeyWord(actual_keyword) = class_token {
-      if actual_keyword == keyword {
-        return true;
-      }
-      return false;
+  pub(crate) fn location(&self) -> Location {
+    match self.parser.get_last_token_descriptor() {
+      Some(descriptor) => Location::from(&descriptor),
+      // Nothing was read, the file is empty.
+      None => Location::new(self.parser.source().clone(), Span::on_line(1, 1, 1)),
+    }
+  }
+
+  /// Error for the token just consumed.
+  fn unexpected(&self, expected: &str, found: Option<Token>) -> CompileError {
+    CompileError::expected(expected, found.as_ref(), self.location())
+  }
+
+  /// Error for the next token, without consuming it.
+  pub(crate) fn expected(&mut self, expected: &str) -> CompileError {
+    let found = self.parser.peek();
+    self.unexpected(expected, found)
+  }
+
+  // The take_* helpers leave a mismatched token in place for error recovery.
+  pub(crate) fn take_keyword(&mut self, keyword: String) -> CompileResult<()> {
+    let expected = format!("'{}'", keyword);
+    if self.try_take_keyword(keyword) {
+      return Ok(());
     }
-    return false;
+    Err(self.expected(&expected))
   }
 
   pub(crate) fn try_take_keyword(&mut self, keyword: String) -> bool {
@@ -41,22 +49,14 @@ impl TokenReader {
       }
       false
     });
-    if let Some(_) = token {
-      return true;
-    }
-    return false;
+    token.is_some()
   }
 
-  pub(crate) fn take_symbol(&mut self, symbol: char) -> bool {
-    let class_token = self.parser.next();
-    let class_token = class_token.unwrap_or(Token::None);
-    if let Token::Symbol(actual_symbol) = class_token {
-      if actual_symbol == symbol {
-        return true;
-      }
-      return false;
+  pub(crate) fn take_symbol(&mut self, symbol: char) -> CompileResult<()> {
+    if self.try_take_symbol(symbol) {
+      return Ok(());
     }
-    return false;
+    Err(self.expected(&format!("'{}'", symbol)))
   }
 
   pub(crate) fn try_take_symbol(&mut self, symbol: char) -> bool {
@@ -66,18 +66,14 @@ impl TokenReader {
       }
       false
     });
-    if let Some(_) = token {
-      return true;
-    }
-    return false;
+    token.is_some()
   }
 
-  pub(crate) fn take_identifier(&mut self) -> Option<String> {
-    let token = self.parser.next().unwrap_or(Token::None);
-    if let Token::Identifier(identifier) = token {
-      return Some(identifier);
+  pub(crate) fn take_identifier(&mut self) -> CompileResult<String> {
+    match self.try_take_identifier() {
+      Some(identifier) => Ok(identifier),
+      None => Err(self.expected("identifier")),
     }
-    None
   }
 
   pub(crate) fn try_take_identifier(&mut self) -> Option<String> {
@@ -88,30 +84,87 @@ impl TokenReader {
     None
   }
 
+  /// The identifier just taken with its span.
+  pub(crate) fn take_ident(&mut self) -> CompileResult<Ident> {
+    let name = self.take_identifier()?;
+    Ok(Ident::new(name, self.last_span()))
+  }
+
+  pub(crate) fn take_type_ident(&mut self) -> CompileResult<Ident> {
+    let (name, _) = self.take_type()?;
+    Ok(Ident::new(name, self.last_span()))
+  }
+
   pub(crate) fn try_take_type(&mut self) -> Option<(String, bool)> {
     if self.try_take_keyword("int".to_string()) {
-      return Some(("int".to_string(), true));
+      Some(("int".to_string(), true))
     } else if self.try_take_keyword("char".to_string()) {
-      return Some(("char".to_string(), true));
+      Some(("char".to_string(), true))
     } else if self.try_take_keyword("boolean".to_string()) {
-      return Some(("boolean".to_string(), true));
+      Some(("boolean".to_string(), true))
     } else {
-      if let Some(id) = self.try_take_identifier() {
-        return Some((id, false));
-      } else {
-        None
-      }
+      self.try_take_identifier().map(|id| (id, false))
+    }
+  }
+
+  pub(crate) fn take_type(&mut self) -> CompileResult<(String, bool)> {
+    match self.try_take_type() {
+      Some(var_type) => Ok(var_type),
+      None => Err(self.expected("type")),
     }
   }
 
   pub(crate) fn try_take_op(&mut self) -> Option<char> {
-    static OP_LIST: &'static [char] = &['+', '-', '*', '/', '&', '|', '<', '>', '='];
+    static OP_LIST: &[char] = &['+', '-', '*', '/', '&', '|', '<', '>', '='];
     for op in OP_LIST {
       if self.try_take_symbol(*op) {
         return Some(*op);
       }
     }
-    return None;
+    None
+  }
+
+  /// Panic mode recovery, skip tokens up to a point where parsing can resume.
+  /// Stops after a `;`, or before one of `keywords` or an unmatched `}`,
+  /// skipping `{ }` blocks whole.
+  /// Returns false if parsing can not resume, at an unmatched `}` or end of file.
+  pub(crate) fn synchronize(&mut self, keywords: &[&str]) -> bool {
+    let mut depth = 0;
+    while let Some(token) = self.parser.peek() {
+      match token {
+        Token::Symbol(';') if depth == 0 => {
+          self.parser.next();
+          return true;
+        }
+        Token::Symbol('{') => depth += 1,
+        Token::Symbol('}') if depth == 0 => return false,
+        Token::Symbol('}') => depth -= 1,
+        Token::KeyWord(keyword) if depth == 0 && keywords.contains(&keyword.as_str()) => {
+          return true;
+        }
+        _ => (),
+      }
+      self.parser.next();
+    }
+    false
+  }
+
+  /// Span of the last token taken.
+  pub(crate) fn last_span(&self) -> Span {
+    self.parser.last_span()
+  }
+
+  /// From `start` to the end of the last token taken.
+  pub(crate) fn span_from(&self, start: Span) -> Span {
+    start.to(self.last_span())
+  }
+
+  pub(crate) fn file(&self) -> String {
+    self.parser.source().clone()
+  }
+
+  pub(crate) fn take_errors(&mut self) -> Vec<CompileError> {
+    self.parser.take_errors()
   }
 
   pub(crate) fn next_token(&mut self) -> Option<Token> {
@@ -121,442 +174,612 @@ impl TokenReader {
   pub(crate) fn peek_token(&mut self) -> Option<Token> {
     self.parser.peek()
   }
-
-  pub(crate) fn get_last_token_descriptor(&self) -> TokenDescriptor {
-    self.parser.get_last_token_descriptor()
-  }
 }
 
+static CLASS_MEMBER_KEYWORDS: &[&str] = &["static", "field", "constructor", "function", "method"];
+static STATEMENT_KEYWORDS: &[&str] = &["let", "if", "while", "do", "return"];
+static VAR_DEC_KEYWORDS: &[&str] = &["var", "let", "if", "while", "do", "return"];
+
 pub struct Compiler {
-  generator: WriteTarget,
   token_reader: TokenReader,
+  // Syntax errors recovered from so far.
+  errors: Vec<CompileError>,
 }
 
 impl Compiler {
-  pub fn new(source: &str, parser: Parser) -> Self {
-    Compiler::new_with_generator(
-      Rc::new(RefCell::new(OperationXMLGenerator::new(source))),
-      parser,
-    )
-  }
-
-  pub fn new_with_generator(generator: WriteTarget, parser: Parser) -> Self {
+  pub fn new(parser: Parser) -> Self {
     Self {
-      generator,
       token_reader: TokenReader::new(parser),
+      errors: vec![],
     }
   }
 
-  pub fn run(mut self) -> Option<String> {
+  /// Parse the class, or report every syntax and lexer error in source order.
+  pub fn run(self) -> Result<Class, Vec<CompileError>> {
+    match self.parse() {
+      (Some(class), errors) if errors.is_empty() => Ok(class),
+      (_, errors) => Err(errors),
+    }
+  }
+
+  /// Parse the class as far as it can be read, with the syntax and lexer
+  /// errors met in source order. Statements that failed to parse are left
+  /// out, declarations are kept when their names were read.
+  pub fn parse(mut self) -> (Option<Class>, Vec<CompileError>) {
     // CompileClass
     //  CompileClassVarDec
     //  CompileSubroutine
-    if !self.compile_class() {
-      let last_token_info = self.token_reader.get_last_token_descriptor();
-      return Some(format!("{:?}", last_token_info));
-    }
-    return None;
+    let class = match self.compile_class() {
+      Ok(class) => Some(class),
+      Err(e) => {
+        self.report(e);
+        None
+      }
+    };
+    let mut errors = self.token_reader.take_errors();
+    errors.append(&mut self.errors);
+    errors.sort_by_key(|e| (e.location().line(), e.location().column()));
+    (class, errors)
   }
 
-  fn compile_class(&mut self) -> bool {
-    if !self.token_reader.take_keyword("class".to_string()) {
-      return false;
-    }
-
-    if let Some(class_name) = self.token_reader.take_identifier() {
-      let _w = self.create_writer(OperationType::Class(class_name));
-      return self.compile_symbol_wrapper('{', '}', |compiler: &mut Compiler| {
-        // compile_class_content
-        return compiler.compile_class_var_dec() && compiler.compile_subroutine();
-      });
+  fn report(&mut self, error: CompileError) {
+    // Recovery may hit the same token twice, keep the first message.
+    if self.errors.last().map(|e| e.location()) != Some(error.location()) {
+      self.errors.push(error);
     }
-    false
   }
 
-  fn compile_class_var_dec(&mut self) -> bool {
-    let class_var_type: VarScope = if self.token_reader.try_take_keyword("static".to_string()) {
-      VarScope::Static
-    } else if self.token_reader.try_take_keyword("field".to_string()) {
-      VarScope::Field
-    } else {
-      return true;
+  fn compile_class(&mut self) -> CompileResult<Class> {
+    self.token_reader.take_keyword("class".to_string())?;
+    let start = self.token_reader.last_span();
+    let name = self
+      .token_reader
+      .take_ident()
+      .map_err(|e| e.with_context("after 'class'"))?;
+    let mut class = Class {
+      file: self.token_reader.file(),
+      name,
+      variables: vec![],
+      subroutines: vec![],
+      span: start,
     };
-    {
-      let _w = self.create_writer(OperationType::ClassVarDec(class_var_type));
-      if !self.compile_var_type_and_name(true, true) {
-        return false;
+    self.token_reader.take_symbol('{')?;
+    // compile_class_content
+    loop {
+      let res = self
+        .compile_class_var_dec(&mut class.variables)
+        .and_then(|_| self.compile_subroutine(&mut class.subroutines));
+      let error = match res {
+        Ok(()) if self.token_reader.peek_token() == Some(Token::Symbol('}')) => break,
+        Ok(()) => self
+          .token_reader
+          .expected("class variable or subroutine declaration"),
+        Err(e) => e,
+      };
+      self.report(error);
+      if !self.token_reader.synchronize(CLASS_MEMBER_KEYWORDS) {
+        break;
       }
     }
-    self.compile_class_var_dec()
+    // The members read so far are kept without the closing brace.
+    match self.token_reader.take_symbol('}') {
+      // Nothing may follow the class, e.g. a second class.
+      Ok(()) => {
+        if let Some(token) = self.token_reader.next_token() {
+          let error = self.token_reader.unexpected("end of file", Some(token));
+          self.report(error);
+        }
+      }
+      Err(e) => self.report(e),
+    }
+    class.span = self.token_reader.span_from(start);
+    Ok(class)
   }
 
-  fn compile_subroutine(&mut self) -> bool {
-    let subroutine_type = if self
-      .token_reader
-      .try_take_keyword("constructor".to_string())
-    {
-      SubroutineType::Constructor
-    } else if self.token_reader.try_take_keyword("function".to_string()) {
-      SubroutineType::Function
-    } else if self.token_reader.try_take_keyword("method".to_string()) {
-      SubroutineType::Method
-    } else {
-      return true;
-    };
-    {
-      let _w = self.create_writer(OperationType::SubroutineDec(subroutine_type));
-      if self.token_reader.try_take_keyword("void".to_string()) {
-        let _w2 = self.create_writer(OperationType::Void);
-      } else if let Some((ret_type, is_keyword)) = self.token_reader.try_take_type() {
-        let _w2 = self.create_writer(OperationType::Type(ret_type, is_keyword));
+  fn compile_class_var_dec(&mut self, variables: &mut Vec<VarDec>) -> CompileResult<()> {
+    loop {
+      let kind = if self.token_reader.try_take_keyword("static".to_string()) {
+        VarScope::Static
+      } else if self.token_reader.try_take_keyword("field".to_string()) {
+        VarScope::Field
       } else {
-        return false;
-      }
-      let func_name = match self.token_reader.take_identifier() {
-        None => return false,
-        Some(func_name) => func_name,
+        return Ok(());
       };
-      let _w3 = self.create_writer(OperationType::VarName(func_name));
-      if !self.compile_symbol_wrapper('(', ')', Compiler::compile_parameter_list) {
-        return false;
-      }
-      let _w4 = self.create_writer(OperationType::SubroutineBody);
-      if !self.compile_symbol_wrapper('{', '}', |compiler: &mut Compiler| {
-        // compile_func_content
-        return compiler.compile_var_dec() && compiler.compile_statements();
-      }) {
-        return false;
-      }
+      self
+        .compile_var_type_and_names(kind, variables)
+        .map_err(|e| e.with_context("in class variable declaration"))?;
     }
-    // Repeating compile
-    self.compile_subroutine()
   }
 
-  fn compile_var_dec(&mut self) -> bool {
-    if !self.token_reader.try_take_keyword("var".to_string()) {
-      return true;
+  fn compile_subroutine(&mut self, subroutines: &mut Vec<SubroutineDec>) -> CompileResult<()> {
+    loop {
+      let kind = if self
+        .token_reader
+        .try_take_keyword("constructor".to_string())
+      {
+        SubroutineType::Constructor
+      } else if self.token_reader.try_take_keyword("function".to_string()) {
+        SubroutineType::Function
+      } else if self.token_reader.try_take_keyword("method".to_string()) {
+        SubroutineType::Method
+      } else {
+        return Ok(());
+      };
+      let start = self.token_reader.last_span();
+      let return_type = if self.token_reader.try_take_keyword("void".to_string()) {
+        Ident::new("void".to_string(), self.token_reader.last_span())
+      } else {
+        self
+          .token_reader
+          .take_type_ident()
+          .map_err(|e| e.with_context(&format!("after '{}'", kind)))?
+      };
+      let name = self
+        .token_reader
+        .take_ident()
+        .map_err(|e| e.with_context("for subroutine name"))?;
+      let mut subroutine = SubroutineDec {
+        kind,
+        return_type,
+        name,
+        parameters: vec![],
+        locals: vec![],
+        statements: vec![],
+        span: start,
+      };
+      let res = self.compile_subroutine_rest(&mut subroutine);
+      // Kept on errors, so that calls to it are still known.
+      subroutine.span = self.token_reader.span_from(start);
+      subroutines.push(subroutine);
+      res?;
+    }
+  }
+
+  // After the name: ( parameter list ) { var declarations statements }
+  fn compile_subroutine_rest(&mut self, subroutine: &mut SubroutineDec) -> CompileResult<()> {
+    self.token_reader.take_symbol('(')?;
+    self
+      .compile_parameter_list(&mut subroutine.parameters)
+      .map_err(|e| e.with_context("in parameter list"))?;
+    self.token_reader.take_symbol(')')?;
+    self.token_reader.take_symbol('{')?;
+    // compile_func_content
+    while let Err(e) = self.compile_var_dec(&mut subroutine.locals) {
+      self.report(e);
+      if !self.token_reader.synchronize(VAR_DEC_KEYWORDS) {
+        break;
+      }
     }
-    {
-      let _w = self.create_writer(OperationType::VarDec);
-      if !self.compile_var_type_and_name(true, true) {
-        return false;
+    loop {
+      subroutine.statements.append(&mut self.compile_statements());
+      match self.token_reader.peek_token() {
+        None | Some(Token::Symbol('}')) => break,
+        _ => {
+          let error = self.token_reader.expected("statement");
+          self.report(error);
+          if !self.token_reader.synchronize(STATEMENT_KEYWORDS) {
+            break;
+          }
+        }
       }
     }
-    return self.compile_var_dec();
+    self.token_reader.take_symbol('}')
   }
 
-  fn compile_parameter_list(&mut self) -> bool {
-    {
-      let _w = self.create_writer(OperationType::ParameterList);
-
-      self.compile_var_type_and_name(false, false)
+  fn compile_var_dec(&mut self, locals: &mut Vec<VarDec>) -> CompileResult<()> {
+    while self.token_reader.try_take_keyword("var".to_string()) {
+      self
+        .compile_var_type_and_names(VarScope::Variable, locals)
+        .map_err(|e| e.with_context("in variable declaration"))?;
     }
+    Ok(())
   }
 
-  fn compile_statement(&mut self) -> (bool, bool) {
-    if self.token_reader.try_take_keyword("let".to_string()) {
-      if !self.compile_let_statement() {
-        return (false, false);
+  fn compile_parameter_list(&mut self, parameters: &mut Vec<Parameter>) -> CompileResult<()> {
+    let mut var_type = match self.token_reader.try_take_type() {
+      Some((var_type, _)) => Ident::new(var_type, self.token_reader.last_span()),
+      None => return Ok(()),
+    };
+    loop {
+      let name = self.token_reader.take_ident()?;
+      parameters.push(Parameter { var_type, name });
+      if !self.token_reader.try_take_symbol(',') {
+        return Ok(());
       }
+      var_type = self
+        .token_reader
+        .take_type_ident()
+        .map_err(|e| e.with_context("after ','"))?;
+    }
+  }
+
+  // Returns the statement compiled, if the next token starts one.
+  fn compile_statement(&mut self) -> CompileResult<Option<Statement>> {
+    let statement = if self.token_reader.try_take_keyword("let".to_string()) {
+      self.compile_let_statement()?
     } else if self.token_reader.try_take_keyword("if".to_string()) {
-      if !self.compile_if_statement() {
-        return (false, false);
-      }
+      self.compile_if_statement()?
     } else if self.token_reader.try_take_keyword("while".to_string()) {
-      if !self.compile_while_statement() {
-        return (false, false);
-      }
+      self.compile_while_statement()?
     } else if self.token_reader.try_take_keyword("do".to_string()) {
-      if !self.compile_do_statement() {
-        return (false, false);
-      }
+      self.compile_do_statement()?
     } else if self.token_reader.try_take_keyword("return".to_string()) {
-      let _w = self.create_writer(OperationType::ReturnStatement);
-      if !self.try_compile_expression().0 {
-        return (false, false);
-      }
-      if !self.token_reader.take_symbol(';') {
-        return (false, false);
+      let start = self.token_reader.last_span();
+      let value = self.try_compile_expression()?;
+      self
+        .token_reader
+        .take_symbol(';')
+        .map_err(|e| e.with_context("after return statement"))?;
+      Statement::Return {
+        value,
+        span: self.token_reader.span_from(start),
       }
     } else {
-      return (true, false);
-    }
-    (true, true)
+      return Ok(None);
+    };
+    Ok(Some(statement))
   }
 
-  fn compile_statements(&mut self) -> bool {
-    let _w = self.create_writer(OperationType::Statements);
-    // println!("DEBUG start statements");
+  // Recovers from errors in single statements by itself.
+  fn compile_statements(&mut self) -> Vec<Statement> {
+    let mut statements = vec![];
     loop {
-      let (success, has) = self.compile_statement();
-      if !success {
-        return false;
-      }
-      if !has {
-        break;
-      }
-    }
-    // println!("DEBUG end statements");
-    true
-  }
-
-  fn compile_let_statement(&mut self) -> bool {
-    if let Some(val) = self.token_reader.take_identifier() {
-      let _w = self.create_writer(OperationType::LetStatement(val));
-      if self.token_reader.try_take_symbol('[') {
-        let _w2 = self.create_writer(OperationType::Bracket(BracketType::from_char('[')));
-        if !self.compile_expression() || !self.token_reader.take_symbol(']') {
-          return false;
+      match self.compile_statement() {
+        Ok(Some(statement)) => statements.push(statement),
+        Ok(None) => break,
+        Err(e) => {
+          self.report(e);
+          if !self.token_reader.synchronize(STATEMENT_KEYWORDS) {
+            break;
+          }
         }
       }
-      {
-        let _w3 = self.create_writer(OperationType::Op('='));
-      }
-      if !self.token_reader.take_symbol('=')
-        || !self.compile_expression()
-        || !self.token_reader.take_symbol(';')
-      {
-        return false;
-      }
-      return true;
     }
-    false
+    statements
   }
 
-  fn compile_if_statement(&mut self) -> bool {
-    let _w = self.create_writer(OperationType::IfStatement);
-    if !self.compile_symbol_wrapper('(', ')', Compiler::compile_expression) {
-      return false;
-    }
-    if !self.compile_symbol_wrapper('{', '}', Compiler::compile_statements) {
-      return false;
-    }
-    if self.token_reader.try_take_keyword("else".to_string()) {
-      let _w2 = self.create_writer(OperationType::Else);
-      if !self.compile_symbol_wrapper('{', '}', Compiler::compile_statements) {
-        return false;
-      }
-    }
-    true
+  // { statements }
+  fn compile_block(&mut self) -> CompileResult<Vec<Statement>> {
+    self.token_reader.take_symbol('{')?;
+    let statements = self.compile_statements();
+    self.token_reader.take_symbol('}')?;
+    Ok(statements)
   }
 
-  fn compile_while_statement(&mut self) -> bool {
-    let _w = self.create_writer(OperationType::WhileStatement);
-    if !self.compile_symbol_wrapper('(', ')', Compiler::compile_expression) {
-      return false;
-    }
-    if !self.compile_symbol_wrapper('{', '}', Compiler::compile_statements) {
-      return false;
-    }
-    true
+  // ( expression )
+  fn compile_condition(&mut self) -> CompileResult<Expr> {
+    self.token_reader.take_symbol('(')?;
+    let condition = self.compile_expression()?;
+    self.token_reader.take_symbol(')')?;
+    Ok(condition)
   }
 
-  fn compile_do_statement(&mut self) -> bool {
-    let _w = self.create_writer(OperationType::DoStatement);
-    let some_name = self.token_reader.take_identifier();
-    if some_name.is_none() {
-      return false;
-    }
-    let some_name = some_name.unwrap();
-    if self.token_reader.try_take_symbol('.') {
-      if let Some(func_name) = self.token_reader.take_identifier() {
-        let _w2 = self.create_writer(OperationType::SubroutineCall(Some(some_name), func_name));
-        if !self.compile_symbol_wrapper('(', ')', Compiler::compile_expression_list) {
-          return false;
-        }
-      } else {
-        return false;
-      }
+  fn compile_let_statement(&mut self) -> CompileResult<Statement> {
+    let start = self.token_reader.last_span();
+    let target = self
+      .token_reader
+      .take_ident()
+      .map_err(|e| e.with_context("after 'let'"))?;
+    let index = if self.token_reader.try_take_symbol('[') {
+      let index = self.compile_expression()?;
+      self
+        .token_reader
+        .take_symbol(']')
+        .map_err(|e| e.with_context("after array index"))?;
+      Some(index)
     } else {
-      let _w2 = self.create_writer(OperationType::SubroutineCall(None, some_name));
-      if !self.compile_symbol_wrapper('(', ')', Compiler::compile_expression_list) {
-        return false;
-      }
-    }
-    if !self.token_reader.take_symbol(';') {
-      return false;
-    }
-    true
-  }
-
-  fn compile_expression_list(&mut self) -> bool {
-    let _w = self.create_writer(OperationType::ExpressionList);
-    let (success, has) = self.try_compile_expression();
-    if !success {
-      return false;
-    }
-    if !has {
-      return true;
-    }
-    while self.token_reader.try_take_symbol(',') {
-      {
-        let _w2 = self.create_writer(OperationType::ListConcat);
-      }
-      if !self.compile_expression() {
-        return false;
-      }
+      None
+    };
+    self
+      .token_reader
+      .take_symbol('=')
+      .map_err(|e| e.with_context("in let statement"))?;
+    let value = self.compile_expression()?;
+    self
+      .token_reader
+      .take_symbol(';')
+      .map_err(|e| e.with_context("after let statement"))?;
+    Ok(Statement::Let {
+      target,
+      index,
+      value,
+      span: self.token_reader.span_from(start),
+    })
+  }
+
+  fn compile_if_statement(&mut self) -> CompileResult<Statement> {
+    let start = self.token_reader.last_span();
+    let condition = self.compile_condition()?;
+    let body = self.compile_block()?;
+    let else_body = if self.token_reader.try_take_keyword("else".to_string()) {
+      Some(self.compile_block()?)
+    } else {
+      None
+    };
+    Ok(Statement::If {
+      condition,
+      body,
+      else_body,
+      span: self.token_reader.span_from(start),
+    })
+  }
+
+  fn compile_while_statement(&mut self) -> CompileResult<Statement> {
+    let start = self.token_reader.last_span();
+    let condition = self.compile_condition()?;
+    let body = self.compile_block()?;
+    Ok(Statement::While {
+      condition,
+      body,
+      span: self.token_reader.span_from(start),
+    })
+  }
+
+  fn compile_do_statement(&mut self) -> CompileResult<Statement> {
+    let start = self.token_reader.last_span();
+    let some_name = self
+      .token_reader
+      .take_ident()
+      .map_err(|e| e.with_context("after 'do'"))?;
+    let (receiver, name) = if self.token_reader.try_take_symbol('.') {
+      let func_name = self
+        .token_reader
+        .take_ident()
+        .map_err(|e| e.with_context("after '.'"))?;
+      (Some(some_name), func_name)
+    } else {
+      (None, some_name)
+    };
+    let call = self.compile_call(receiver, name)?;
+    self
+      .token_reader
+      .take_symbol(';')
+      .map_err(|e| e.with_context("after do statement"))?;
+    Ok(Statement::Do {
+      call,
+      span: self.token_reader.span_from(start),
+    })
+  }
+
+  // The ( expression list ) of a call after its names.
+  fn compile_call(&mut self, receiver: Option<Ident>, name: Ident) -> CompileResult<Call> {
+    let start = receiver.as_ref().unwrap_or(&name).span;
+    self.token_reader.take_symbol('(')?;
+    let arguments = self.compile_expression_list()?;
+    self.token_reader.take_symbol(')')?;
+    Ok(Call {
+      receiver,
+      name,
+      arguments,
+      span: self.token_reader.span_from(start),
+    })
+  }
+
+  fn compile_expression_list(&mut self) -> CompileResult<Vec<Expr>> {
+    let mut expressions = vec![];
+    if let Some(expression) = self.try_compile_expression()? {
+      expressions.push(expression);
+      while self.token_reader.try_take_symbol(',') {
+        expressions.push(self.compile_expression()?);
+      }
+    }
+    Ok(expressions)
+  }
+
+  fn compile_expression(&mut self) -> CompileResult<Expr> {
+    let mut left = self.compile_term()?;
+    while let Some(op) = self.token_reader.try_take_op() {
+      let op_span = self.token_reader.last_span();
+      let right = self.compile_term()?;
+      left = Expr::Binary {
+        op,
+        op_span,
+        left: Box::new(left),
+        right: Box::new(right),
+      };
     }
-    return true;
+    Ok(left)
   }
 
-  fn compile_expression(&mut self) -> bool {
-    let _w = self.create_writer(OperationType::Expression);
-    if !self.compile_term() {
-      return false;
+  fn compile_term(&mut self) -> CompileResult<Expr> {
+    if !self.starts_term() {
+      return Err(self.token_reader.expected("expression"));
     }
-    loop {
-      if let Some(op) = self.token_reader.try_take_op() {
-        let _w2 = self.create_writer(OperationType::Op(op));
-        if !self.compile_term() {
-          return false;
-        }
-      } else {
-        break;
-      }
-    }
-    true
-  }
-
-  fn compile_term(&mut self) -> bool {
-    let _w = self.create_writer(OperationType::Term);
     let token = self.token_reader.next_token();
     let token = token.unwrap_or(Token::None);
+    let span = self.token_reader.last_span();
     match token {
-      Token::IntVal(v) => {
-        let _w2 = self.create_writer(OperationType::Constant(ConstantType::Integer(v)));
-        true
-      }
-      Token::StringVal(v) => {
-        let _w2 = self.create_writer(OperationType::Constant(ConstantType::String(v)));
-        true
-      }
+      Token::IntVal(value) => Ok(Expr::Int { value, span }),
+      Token::StringVal(value) => Ok(Expr::String { value, span }),
       Token::KeyWord(keyword) => {
         if !is_keyword_constant(&keyword) {
-          return false;
+          return Err(
+            self
+              .token_reader
+              .unexpected("expression", Some(Token::KeyWord(keyword))),
+          );
         }
-        let _w2 = self.create_writer(OperationType::Constant(ConstantType::KeyWord(keyword)));
-        true
+        Ok(Expr::Keyword { keyword, span })
       }
       Token::Symbol(s) => {
         if is_unary_operation(s) {
-          let op = if s == '-' {
-            // NOTE turn negative '-' to '^'
-            '^'
-          } else {
-            s
-          };
-          {
-            let _w2 = self.create_writer(OperationType::Op(op));
-          }
-          self.compile_term()
+          let operand = self.compile_term()?;
+          Ok(Expr::Unary {
+            op: s,
+            operand: Box::new(operand),
+            span: self.token_reader.span_from(span),
+          })
         } else if s == '(' {
-          let _w2 = self.create_writer(OperationType::Bracket(BracketType::from_char('(')));
-          self.compile_expression() && self.token_reader.take_symbol(')')
+          let expr = self.compile_expression()?;
+          self
+            .token_reader
+            .take_symbol(')')
+            .map_err(|e| e.with_context("to close expression"))?;
+          Ok(Expr::Paren {
+            expr: Box::new(expr),
+            span: self.token_reader.span_from(span),
+          })
         } else {
-          false
+          Err(
+            self
+              .token_reader
+              .unexpected("expression", Some(Token::Symbol(s))),
+          )
         }
       }
       Token::Identifier(identifier) => {
+        let identifier = Ident::new(identifier, span);
         if self.token_reader.try_take_symbol('[') {
-          let _w2 = self.create_writer(OperationType::VarName(identifier));
-          let _w3 = self.create_writer(OperationType::Bracket(BracketType::from_char('[')));
-          self.compile_expression() && self.token_reader.take_symbol(']')
+          let index = self.compile_expression()?;
+          self
+            .token_reader
+            .take_symbol(']')
+            .map_err(|e| e.with_context("after array index"))?;
+          Ok(Expr::Index {
+            array: identifier,
+            index: Box::new(index),
+            span: self.token_reader.span_from(span),
+          })
         } else if self.token_reader.try_take_symbol('.') {
-          let func_name = match self.token_reader.take_identifier() {
-            None => return false,
-            Some(func_name) => func_name,
-          };
-          {
-            let _w2 =
-              self.create_writer(OperationType::SubroutineCall(Some(identifier), func_name));
-          }
-          self.compile_symbol_wrapper('(', ')', Compiler::compile_expression_list)
+          let func_name = self
+            .token_reader
+            .take_ident()
+            .map_err(|e| e.with_context("after '.'"))?;
+          Ok(Expr::Call(self.compile_call(Some(identifier), func_name)?))
+        } else if self.token_reader.try_take_symbol('(') {
+          let arguments = self.compile_expression_list()?;
+          self
+            .token_reader
+            .take_symbol(')')
+            .map_err(|e| e.with_context("after argument list"))?;
+          Ok(Expr::Call(Call {
+            receiver: None,
+            name: identifier,
+            arguments,
+            span: self.token_reader.span_from(span),
+          }))
         } else {
-          if self.token_reader.try_take_symbol('(') {
-            let _w2 = self.create_writer(OperationType::SubroutineCall(None, identifier));
-            let _w3 = self.create_writer(OperationType::Bracket(BracketType::from_char('(')));
-            self.compile_expression_list() && self.token_reader.take_symbol(')')
-          } else {
-            let _w2 = self.create_writer(OperationType::VarName(identifier));
-            true
-          }
+          Ok(Expr::Var(identifier))
         }
       }
-      Token::None => false,
+      Token::None => Err(self.token_reader.unexpected("expression", None)),
     }
   }
 
-  // compile error, has expression
-  fn try_compile_expression(&mut self) -> (bool, bool) {
-    if let Some(token) = self.token_reader.peek_token() {
-      if let Token::Symbol(s) = token {
-        if s == ')' || s == ';' {
-          return (true, false);
-        }
-      }
+  // Returns whether the next token starts an expression.
+  fn starts_term(&mut self) -> bool {
+    match self.token_reader.peek_token() {
+      Some(Token::Symbol(s)) => s == '(' || is_unary_operation(s),
+      Some(Token::KeyWord(keyword)) => is_keyword_constant(&keyword),
+      Some(_) => true,
+      None => false,
     }
-    let r = self.compile_expression();
-    (r, r)
   }
 
-  // Compile [start, func, end] like ( expression list )
-  fn compile_symbol_wrapper<P>(&mut self, start: char, end: char, mut func: P) -> bool
-  where
-    P: FnMut(&mut Compiler) -> bool,
-  {
-    let _w2 = self.create_writer(OperationType::Bracket(BracketType::from_char(start)));
-    return self.token_reader.take_symbol(start)
-      && func(self)
-      && self.token_reader.take_symbol(end);
+  fn try_compile_expression(&mut self) -> CompileResult<Option<Expr>> {
+    if !self.starts_term() {
+      return Ok(None);
+    }
+    Ok(Some(self.compile_expression()?))
   }
 
-  fn compile_var_type_and_name(&mut self, need_semicolons: bool, type_once: bool) -> bool {
-    let res = self.token_reader.try_take_type();
-    if res.is_none() {
-      return true;
-    }
-    let (var_type, is_keyword) = res.unwrap();
-    {
-      let _w = self.create_writer(OperationType::Type(var_type, is_keyword));
-      if let Some(var_name) = self.token_reader.take_identifier() {
-        if type_once {
-          let mut var_names = vec![var_name];
-          while self.token_reader.try_take_symbol(',') {
-            if let Some(next_var_name) = self.token_reader.take_identifier() {
-              var_names.push(next_var_name);
-            } else {
-              return false;
-            }
-          }
-          let _w2 = self.create_writer(OperationType::VarNameList(var_names));
-        } else {
-          {
-            let _w2 = self.create_writer(OperationType::VarName(var_name));
-          }
-          while self.token_reader.try_take_symbol(',') {
-            {
-              let _w3 = self.create_writer(OperationType::ListConcat);
-            }
-            let next_type = self.token_reader.try_take_type();
-            let next_var = self.token_reader.take_identifier();
-            if next_type.is_none() || next_var.is_none() {
-              return false;
-            }
-            let next_type = next_type.unwrap();
-            let next_var = next_var.unwrap();
-            let _w4 = self.create_writer(OperationType::Type(next_type.0, next_type.1));
-            let _w5 = self.create_writer(OperationType::VarName(next_var));
-          }
-        }
-      } else {
-        return false;
-      }
+  // Declared names after their keyword: type name (, name)* ;
+  fn compile_var_type_and_names(
+    &mut self,
+    kind: VarScope,
+    declarations: &mut Vec<VarDec>,
+  ) -> CompileResult<()> {
+    let start = self.token_reader.last_span();
+    // A declaration always has a type.
+    let var_type = self.token_reader.take_type_ident()?;
+    let mut names = vec![self.token_reader.take_ident()?];
+    while self.token_reader.try_take_symbol(',') {
+      let next_var_name = self
+        .token_reader
+        .take_ident()
+        .map_err(|e| e.with_context("after ','"))?;
+      names.push(next_var_name);
     }
-
-    !need_semicolons || self.token_reader.take_symbol(';')
+    let res = self
+      .token_reader
+      .take_symbol(';')
+      .map_err(|e| e.with_context("after variable declaration"));
+    // Kept without its ';', so that the names are still declared.
+    declarations.push(VarDec {
+      kind,
+      var_type,
+      names,
+      span: self.token_reader.span_from(start),
+    });
+    res
   }
+}
 
-  fn create_writer(&self, node: OperationType) -> RAIIWriter {
-    RAIIWriter::new(self.generator.clone(), node)
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  // Class parsed from `source`, with every error as `file:line:column: message`.
+  fn parse(source: &str) -> (Option<Class>, Vec<String>) {
+    let (class, errors) = Compiler::new(Parser::new_from_source("Main.jack", source)).parse();
+    (class, errors.iter().map(|e| e.to_string()).collect())
+  }
+
+  #[test]
+  fn reports_every_error_of_a_file() {
+    let source = "class Main {
+  function void main() {
+    var int x;
+    let x = ;
+    do Output.printInt(x);
+    let = 2;
+    return;
+  }
+  method int f() { return 1 }
+  function void g() {
+    return;
+  }
+}";
+    let (class, errors) = parse(source);
+    assert_eq!(
+      errors,
+      vec![
+        "Main.jack:4:13: expected expression, found ';'",
+        "Main.jack:6:9: expected identifier after 'let', found '='",
+        "Main.jack:9:29: expected ';' after return statement, found '}'",
+      ]
+    );
+    // Parsing resumes after each bad statement, only those are left out.
+    let class = class.unwrap();
+    let names: Vec<_> = class.subroutines.iter().map(|s| &s.name.name[..]).collect();
+    assert_eq!(names, vec!["main", "f", "g"]);
+    let main = &class.subroutines[0].statements;
+    assert!(matches!(
+      main[..],
+      [Statement::Do { .. }, Statement::Return { .. }]
+    ));
+    assert_eq!(class.subroutines[2].statements.len(), 1);
+  }
+
+  #[test]
+  fn lexer_and_syntax_errors_in_source_order() {
+    let source = "class Main {
+  function void main() {
+    do f(#);
+    let s = \"abc;
+    return;
+  }
+}
+}";
+    let (_, errors) = parse(source);
+    assert_eq!(
+      errors,
+      vec![
+        "Main.jack:3:10: Invalid symbol: #",
+        "Main.jack:4:13: Unterminated string",
+        "Main.jack:5:5: expected expression, found 'return'",
+        "Main.jack:8:1: expected end of file, found '}'",
+      ]
+    );
   }
 }
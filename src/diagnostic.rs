@@ -0,0 +1,212 @@
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+use crate::error::{CompileError, Location};
+use crate::token::Span;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Severity {
+  Error,
+  Warning,
+}
+
+impl Display for Severity {
+  fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    match self {
+      Severity::Error => write!(f, "error"),
+      Severity::Warning => write!(f, "warning"),
+    }
+  }
+}
+
+/// How diagnostics are printed.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum MessageFormat {
+  // Source line with a caret underline, for terminals.
+  #[default]
+  Human,
+  // One JSON object per line, for editors and CI.
+  Json,
+}
+
+impl FromStr for MessageFormat {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s {
+      "human" => Ok(MessageFormat::Human),
+      "json" => Ok(MessageFormat::Json),
+      _ => Err(format!("unknown message format '{}', use human or json", s)),
+    }
+  }
+}
+
+/// A problem in a source file, ready to be shown to the user.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+  pub severity: Severity,
+  pub code: String,
+  pub message: String,
+  pub location: Location,
+  pub notes: Vec<String>,
+}
+
+impl From<&CompileError> for Diagnostic {
+  fn from(error: &CompileError) -> Self {
+    Self {
+      severity: Severity::Error,
+      code: error.code().to_string(),
+      message: error.message(),
+      location: error.location().clone(),
+      notes: error.notes().to_vec(),
+    }
+  }
+}
+
+impl Diagnostic {
+  /// Multi-line text for a terminal. `source` is the content of the file of
+  /// the diagnostic, the offending line is shown when it is given.
+  ///
+  /// ```text
+  /// error[E0003]: undefined variable 'b'
+  ///  --> Main.jack:6:9
+  ///   |
+  /// 6 |     let b = 1;
+  ///   |         ^
+  ///   = note: ...
+  /// ```
+  pub fn render(&self, source: Option<&str>) -> String {
+    let location = &self.location;
+    let gutter = " ".repeat(location.line().to_string().len());
+    let mut ret = format!("{}[{}]: {}\n", self.severity, self.code, self.message);
+    ret += &format!("{}--> {}\n", gutter, location);
+    if let Some(line) = source_line(source, location.line()) {
+      let width = underline_width(line, &location.span);
+      // Keep tabs so that the caret lines up with the source.
+      let padding: String = line
+        .chars()
+        .take(location.column().saturating_sub(1))
+        .map(|c| if c == '\t' { '\t' } else { ' ' })
+        .collect();
+      ret += &format!("{} |\n", gutter);
+      ret += &format!("{} | {}\n", location.line(), line);
+      ret += &format!("{} | {}{}\n", gutter, padding, "^".repeat(width));
+    }
+    for note in &self.notes {
+      ret += &format!("{} = note: {}\n", gutter, note);
+    }
+    ret
+  }
+
+  /// A single line JSON object. Columns are 1-based, `end_column` is
+  /// exclusive.
+  pub fn to_json(&self) -> String {
+    let span = &self.location.span;
+    let notes: Vec<String> = self.notes.iter().map(|note| json_string(note)).collect();
+    format!(
+      "{{\"severity\":{},\"code\":{},\"message\":{},\"file\":{},\"line\":{},\"column\":{},\"end_line\":{},\"end_column\":{},\"notes\":[{}]}}",
+      json_string(&self.severity.to_string()),
+      json_string(&self.code),
+      json_string(&self.message),
+      json_string(&self.location.file),
+      span.start_line,
+      span.start_column,
+      span.end_line,
+      span.end_column,
+      notes.join(",")
+    )
+  }
+}
+
+fn source_line(source: Option<&str>, line: usize) -> Option<&str> {
+  source?.lines().nth(line.checked_sub(1)?)
+}
+
+// Characters of `span` on `line`, its first line, at least 1.
+fn underline_width(line: &str, span: &Span) -> usize {
+  let end_column = if span.end_line == span.start_line {
+    span.end_column
+  } else {
+    line.chars().count() + 1
+  };
+  end_column.saturating_sub(span.start_column).max(1)
+}
+
+fn json_string(s: &str) -> String {
+  let mut ret = String::from("\"");
+  for c in s.chars() {
+    match c {
+      '"' => ret += "\\\"",
+      '\\' => ret += "\\\\",
+      '\n' => ret += "\\n",
+      '\r' => ret += "\\r",
+      '\t' => ret += "\\t",
+      c if (c as u32) < 0x20 => ret += &format!("\\u{:04x}", c as u32),
+      c => ret.push(c),
+    }
+  }
+  ret.push('"');
+  ret
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn diagnostic(span: Span) -> Diagnostic {
+    let location = Location::new("Main.jack".to_string(), span);
+    Diagnostic::from(
+      &CompileError::semantic("undefined variable 'b'", location).with_note("declare it with var"),
+    )
+  }
+
+  #[test]
+  fn render_underlines_the_span() {
+    let source = "class Main {\n\tlet b = 1;\n}\n";
+    let rendered = diagnostic(Span::new(2, 6, 2, 7)).render(Some(source));
+    assert_eq!(
+      rendered,
+      "error[E0003]: undefined variable 'b'
+ --> Main.jack:2:6
+  |
+2 | \tlet b = 1;
+  | \t    ^
+  = note: declare it with var
+"
+    );
+  }
+
+  #[test]
+  fn render_spans_over_lines_and_without_source() {
+    let source = "let s = \"ab\nc\";";
+    let rendered = diagnostic(Span::new(1, 9, 2, 3)).render(Some(source));
+    assert!(rendered.contains("1 | let s = \"ab\n  |         ^^^\n"));
+
+    let rendered = diagnostic(Span::new(12, 1, 12, 2)).render(None);
+    assert_eq!(
+      rendered,
+      "error[E0003]: undefined variable 'b'
+  --> Main.jack:12:1
+   = note: declare it with var
+"
+    );
+  }
+
+  #[test]
+  fn render_column_zero() {
+    let rendered = diagnostic(Span::new(1, 0, 1, 0)).render(Some("do f();"));
+    assert!(rendered.contains("1 | do f();\n  | ^\n"));
+  }
+
+  #[test]
+  fn to_json_is_one_object() {
+    let mut diagnostic = diagnostic(Span::new(2, 6, 2, 7));
+    diagnostic.message = "bad \"name\"\there\\".to_string();
+    assert_eq!(
+      diagnostic.to_json(),
+      "{\"severity\":\"error\",\"code\":\"E0003\",\"message\":\"bad \\\"name\\\"\\there\\\\\",\
+\"file\":\"Main.jack\",\"line\":2,\"column\":6,\"end_line\":2,\"end_column\":7,\
+\"notes\":[\"declare it with var\"]}"
+    );
+  }
+}
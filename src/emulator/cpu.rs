@@ -0,0 +1,398 @@
+use crate::asm::assembler::Assembler;
+use crate::emulator::{HaltDetector, RunResult, KBD, RAM_SIZE, SCREEN, SCREEN_SIZE};
+
+const ROM_SIZE: usize = 32768;
+
+pub struct Cpu {
+  rom: Vec<u16>,
+  program_size: usize,
+  ram: Vec<u16>,
+  a: u16,
+  d: u16,
+  pc: u16,
+  cycles: u64,
+  halt_detector: HaltDetector,
+}
+
+impl Cpu {
+  pub fn new() -> Self {
+    Self {
+      rom: vec![0; ROM_SIZE],
+      program_size: 0,
+      ram: vec![0; RAM_SIZE],
+      a: 0,
+      d: 0,
+      pc: 0,
+      cycles: 0,
+      halt_detector: HaltDetector::new(),
+    }
+  }
+
+  /// Load machine code, one 16 digit binary instruction per line.
+  pub fn load_hack<S: AsRef<str>>(&mut self, lines: &[S]) -> Result<(), String> {
+    let mut program = vec![];
+    for (idx, line) in lines.iter().enumerate() {
+      let line = line.as_ref().trim();
+      if line.is_empty() {
+        continue;
+      }
+      if line.len() != 16 {
+        return Err(format!("line {}: invalid instruction {}", idx + 1, line));
+      }
+      let code = u16::from_str_radix(line, 2)
+        .map_err(|_| format!("line {}: invalid instruction {}", idx + 1, line))?;
+      program.push(code);
+    }
+    self.load_rom(program)
+  }
+
+  /// Assemble hack assembly then load it.
+  pub fn load_asm<S: AsRef<str>>(&mut self, lines: &[S]) -> Result<(), String> {
+    let codes = Assembler::new().assemble_lines(lines)?;
+    self.load_hack(&codes)
+  }
+
+  pub fn load_rom(&mut self, program: Vec<u16>) -> Result<(), String> {
+    if program.len() > ROM_SIZE {
+      return Err(format!("Program too large: {} instructions", program.len()));
+    }
+    self.rom = vec![0; ROM_SIZE];
+    self.rom[..program.len()].copy_from_slice(&program);
+    self.program_size = program.len();
+    self.reset();
+    Ok(())
+  }
+
+  pub fn reset(&mut self) {
+    self.pc = 0;
+    self.cycles = 0;
+    self.halt_detector = HaltDetector::new();
+  }
+
+  pub fn ram(&self, address: usize) -> i16 {
+    self.ram[address] as i16
+  }
+
+  pub fn set_ram(&mut self, address: usize, value: i16) {
+    self.ram[address] = value as u16;
+  }
+
+  pub fn screen(&self) -> &[u16] {
+    &self.ram[SCREEN..SCREEN + SCREEN_SIZE]
+  }
+
+  /// Simulate a key held on the keyboard, 0 means no key.
+  pub fn set_key(&mut self, key: u16) {
+    self.ram[KBD] = key;
+  }
+
+  pub fn a(&self) -> u16 {
+    self.a
+  }
+
+  pub fn d(&self) -> u16 {
+    self.d
+  }
+
+  pub fn pc(&self) -> u16 {
+    self.pc
+  }
+
+  pub fn cycles(&self) -> u64 {
+    self.cycles
+  }
+
+  fn alu(x: u16, y: u16, control: u16) -> u16 {
+    let (zx, nx, zy, ny, f, no) = (
+      control & 0b100000 != 0,
+      control & 0b010000 != 0,
+      control & 0b001000 != 0,
+      control & 0b000100 != 0,
+      control & 0b000010 != 0,
+      control & 0b000001 != 0,
+    );
+    let mut x = if zx { 0 } else { x };
+    if nx {
+      x = !x;
+    }
+    let mut y = if zy { 0 } else { y };
+    if ny {
+      y = !y;
+    }
+    let out = if f { x.wrapping_add(y) } else { x & y };
+    if no {
+      !out
+    } else {
+      out
+    }
+  }
+
+  fn write_memory(&mut self, address: usize, value: u16) {
+    // Writes to the keyboard register or beyond are ignored like in hardware.
+    if address < KBD {
+      self.halt_detector.record_write(address, self.ram[address]);
+      self.ram[address] = value;
+    }
+  }
+
+  /// Execute one instruction, return false when a halt loop is detected.
+  pub fn step(&mut self) -> bool {
+    let instruction = self.rom[self.pc as usize];
+    let pc = self.pc;
+    self.cycles += 1;
+    if instruction & 0x8000 == 0 {
+      self.a = instruction;
+      self.pc = self.pc.wrapping_add(1);
+      return true;
+    }
+    let address = (self.a & 0x7fff) as usize;
+    let y = if instruction & 0x1000 != 0 {
+      self.ram[address]
+    } else {
+      self.a
+    };
+    let out = Cpu::alu(self.d, y, (instruction >> 6) & 0b111111);
+    if instruction & 0b001000 != 0 {
+      self.write_memory(address, out);
+    }
+    let jump_target = self.a;
+    if instruction & 0b100000 != 0 {
+      self.a = out;
+    }
+    if instruction & 0b010000 != 0 {
+      self.d = out;
+    }
+    let out = out as i16;
+    let jump = (instruction & 0b100 != 0 && out < 0)
+      || (instruction & 0b010 != 0 && out == 0)
+      || (instruction & 0b001 != 0 && out > 0);
+    if !jump {
+      self.pc = self.pc.wrapping_add(1);
+      return true;
+    }
+    self.pc = jump_target;
+    if jump_target <= pc {
+      let registers = [self.a, self.d];
+      return !self
+        .halt_detector
+        .on_backward_jump(pc as usize, &registers, &self.ram);
+    }
+    true
+  }
+
+  /// Run until a halt loop, the end of the program or `max_cycles`.
+  pub fn run(&mut self, max_cycles: u64) -> RunResult {
+    let start = self.cycles;
+    while self.cycles - start < max_cycles {
+      if self.pc as usize >= self.program_size {
+        return RunResult::ProgramEnd(self.cycles);
+      }
+      if !self.step() {
+        return RunResult::Halted(self.cycles);
+      }
+    }
+    RunResult::CycleLimit(self.cycles)
+  }
+}
+
+impl Default for Cpu {
+  fn default() -> Self {
+    Cpu::new()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::vm::commands::Command;
+  use crate::vm::vm_translator::AssembleCodeGenerator;
+
+  // Translate `source`, one vm command per line, and run it from `ram`.
+  fn run_translation(
+    source: &str,
+    ram: &[(usize, i16)],
+    bootstrap: bool,
+    shared_routines: bool,
+  ) -> Cpu {
+    let mut writer = AssembleCodeGenerator::new().with_shared_routines(shared_routines);
+    let mut asm = if bootstrap {
+      writer.bootstrap()
+    } else {
+      vec![]
+    };
+    writer.start_file("Test");
+    for line in source.lines() {
+      asm.append(&mut writer.get_asm(Command::from_str(line)));
+    }
+    asm.append(&mut writer.finish());
+    let mut cpu = Cpu::new();
+    cpu.load_asm(&asm).unwrap();
+    for &(address, value) in ram {
+      cpu.set_ram(address, value);
+    }
+    assert!(!matches!(cpu.run(100_000), RunResult::CycleLimit(_)));
+    cpu
+  }
+
+  // Both translations leave the same memory outside the stack, whose frames
+  // hold return addresses, R13-R15 aside.
+  fn run_vm(source: &str, ram: &[(usize, i16)], bootstrap: bool) -> Cpu {
+    let shared = run_translation(source, ram, bootstrap, true);
+    let inline = run_translation(source, ram, bootstrap, false);
+    for address in (0..13).chain(16..256).chain(2048..4096) {
+      assert_eq!(shared.ram(address), inline.ram(address), "RAM[{}]", address);
+    }
+    shared
+  }
+
+  #[test]
+  fn memory_segments() {
+    let source = "push constant 10
+      pop local 0
+      push constant 21
+      push constant 22
+      pop argument 2
+      pop argument 1
+      push constant 36
+      pop this 6
+      push constant 42
+      push constant 45
+      pop that 5
+      pop that 2
+      push constant 510
+      pop temp 6
+      push local 0
+      push that 5
+      add
+      push argument 1
+      sub
+      push this 6
+      push this 6
+      add
+      sub
+      push temp 6
+      add";
+    let ram = [(0, 256), (1, 300), (2, 400), (3, 3000), (4, 3010)];
+    let cpu = run_vm(source, &ram, false);
+    assert_eq!(cpu.ram(0), 257);
+    assert_eq!(cpu.ram(256), 472);
+    assert_eq!(cpu.ram(300), 10);
+    assert_eq!(cpu.ram(401), 21);
+    assert_eq!(cpu.ram(402), 22);
+    assert_eq!(cpu.ram(3006), 36);
+    assert_eq!(cpu.ram(3012), 42);
+    assert_eq!(cpu.ram(3015), 45);
+    assert_eq!(cpu.ram(11), 510);
+  }
+
+  #[test]
+  fn pointer_and_static_segments() {
+    let source = "push constant 3030
+      pop pointer 0
+      push constant 3040
+      pop pointer 1
+      push constant 32
+      pop this 2
+      push constant 46
+      pop that 6
+      push pointer 0
+      push pointer 1
+      add
+      push this 2
+      sub
+      push that 6
+      add
+      push constant 111
+      pop static 0
+      push static 0";
+    let cpu = run_vm(source, &[(0, 256)], false);
+    assert_eq!(cpu.ram(3), 3030);
+    assert_eq!(cpu.ram(4), 3040);
+    assert_eq!(cpu.ram(3032), 32);
+    assert_eq!(cpu.ram(3046), 46);
+    assert_eq!(cpu.ram(256), 6084);
+    // Test.0 is the first variable of the assembler.
+    assert_eq!(cpu.ram(16), 111);
+    assert_eq!(cpu.ram(257), 111);
+  }
+
+  #[test]
+  fn arithmetic_and_comparisons() {
+    let source = "push constant 17
+      push constant 17
+      eq
+      push constant 17
+      push constant 16
+      eq
+      push constant 891
+      push constant 892
+      lt
+      push constant 892
+      push constant 891
+      lt
+      push constant 32767
+      push constant 32766
+      gt
+      push constant 57
+      push constant 31
+      push constant 53
+      add
+      push constant 112
+      sub
+      neg
+      and
+      push constant 82
+      or
+      not";
+    let cpu = run_vm(source, &[(0, 256)], false);
+    assert_eq!(cpu.ram(0), 262);
+    let stack: Vec<_> = (256..262).map(|address| cpu.ram(address)).collect();
+    assert_eq!(stack, [-1, 0, -1, 0, -1, -91]);
+  }
+
+  #[test]
+  fn call_and_return() {
+    let source = "function Sys.init 0
+      push constant 10
+      call Main.fib 1
+      pop temp 0
+      push constant 3
+      push constant 4
+      call Main.add 2
+      pop temp 1
+      label END
+      goto END
+      function Main.add 1
+      push argument 0
+      push argument 1
+      add
+      pop local 0
+      push local 0
+      return
+      function Main.fib 0
+      push argument 0
+      push constant 2
+      lt
+      if-goto BASE
+      push argument 0
+      push constant 1
+      sub
+      call Main.fib 1
+      push argument 0
+      push constant 2
+      sub
+      call Main.fib 1
+      add
+      return
+      label BASE
+      push argument 0
+      return";
+    let cpu = run_vm(source, &[], true);
+    assert_eq!(cpu.ram(5), 55);
+    assert_eq!(cpu.ram(6), 7);
+    // The bootstrap frame of Sys.init: ARG at 256, then 5 saved words.
+    assert_eq!(cpu.ram(0), 261);
+    assert_eq!(cpu.ram(1), 261);
+    assert_eq!(cpu.ram(2), 256);
+  }
+}
@@ -0,0 +1,82 @@
+pub mod cpu;
+pub mod native_os;
+pub mod vm;
+
+use std::collections::HashMap;
+
+pub const RAM_SIZE: usize = 32768;
+pub const SCREEN: usize = 16384;
+pub const SCREEN_SIZE: usize = 8192;
+pub const KBD: usize = 24576;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RunResult {
+  // Stuck in a loop that can no longer change the machine state.
+  Halted(u64),
+  // Control flow left the loaded program.
+  ProgramEnd(u64),
+  // Cycle budget exhausted.
+  CycleLimit(u64),
+}
+
+/// Detects a halt loop, a backward jump whose loop body leaves registers and
+/// memory exactly as they were on the previous iteration.
+/// Since the machine is deterministic (under an unchanged keyboard) such a loop
+/// never terminates.
+#[derive(Default)]
+pub struct HaltDetector {
+  jump_from: Option<usize>,
+  registers: Vec<u16>,
+  // Address -> value before the first write since the last snapshot.
+  dirty: HashMap<usize, u16>,
+}
+
+impl HaltDetector {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  pub fn record_write(&mut self, address: usize, old_value: u16) {
+    if self.jump_from.is_some() {
+      self.dirty.entry(address).or_insert(old_value);
+    }
+  }
+
+  /// Returns true when the same backward jump is taken again with no state change.
+  pub fn on_backward_jump(&mut self, from: usize, registers: &[u16], ram: &[u16]) -> bool {
+    if self.jump_from == Some(from)
+      && self.registers == registers
+      && self.dirty.iter().all(|(&addr, &old)| ram[addr] == old)
+    {
+      return true;
+    }
+    self.jump_from = Some(from);
+    self.registers = registers.to_vec();
+    self.dirty.clear();
+    false
+  }
+}
+
+/// Parse a RAM dump specification like `0,256-260`.
+pub fn parse_addresses(spec: &str) -> Result<Vec<usize>, String> {
+  let mut ret = vec![];
+  for part in spec.split(',').map(|s| s.trim()).filter(|s| !s.is_empty()) {
+    let parse = |s: &str| {
+      s.trim()
+        .parse::<usize>()
+        .ok()
+        .filter(|&v| v < RAM_SIZE)
+        .ok_or(format!("Invalid RAM address: {}", s))
+    };
+    if let Some((start, end)) = part.split_once('-') {
+      let (start, end) = (parse(start)?, parse(end)?);
+      if start > end {
+        return Err(format!("Invalid RAM range: {}", part));
+      }
+      ret.extend(start..=end);
+    } else {
+      ret.push(parse(part)?);
+    }
+  }
+  Ok(ret)
+}
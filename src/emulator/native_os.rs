@@ -0,0 +1,382 @@
+use std::collections::{BTreeMap, HashMap, VecDeque};
+
+const HEAP_BASE: usize = 2048;
+const HEAP_END: usize = 16384;
+const KBD: usize = 24576;
+
+const NEW_LINE: u16 = 128;
+const BACKSPACE: u16 = 129;
+const DOUBLE_QUOTE: u16 = 34;
+
+// Field offsets of a String object, same layout as the bundled String.jack.
+const STRING_CHARS: usize = 0;
+const STRING_LENGTH: usize = 1;
+const STRING_CAPACITY: usize = 2;
+
+pub enum NativeResult {
+  Return(u16),
+  Halt,
+}
+
+type NativeCall = Result<NativeResult, String>;
+
+/// Rust implementations of the Jack OS, used by the vm emulator for calls to
+/// functions that are not defined by the loaded vm files.
+/// Objects live in the emulated RAM so compiled code can access them as usual,
+/// while printed text is captured as plain text.
+pub struct NativeOs {
+  // Free heap blocks, address -> length.
+  free_blocks: BTreeMap<usize, usize>,
+  // Allocated heap blocks, address -> length.
+  allocated: HashMap<usize, usize>,
+  output: String,
+  input: VecDeque<char>,
+}
+
+fn int_value(text: &str) -> i16 {
+  let (negative, digits) = match text.strip_prefix('-') {
+    Some(digits) => (true, digits),
+    None => (false, text),
+  };
+  let mut value: i16 = 0;
+  for c in digits.chars().take_while(|c| c.is_ascii_digit()) {
+    value = value
+      .wrapping_mul(10)
+      .wrapping_add(c.to_digit(10).unwrap() as i16);
+  }
+  if negative {
+    value.wrapping_neg()
+  } else {
+    value
+  }
+}
+
+fn to_char(c: u16) -> char {
+  match c {
+    NEW_LINE => '\n',
+    c => char::from_u32(c as u32).unwrap_or('?'),
+  }
+}
+
+impl NativeOs {
+  /// `input` is consumed by the Keyboard functions, as if typed.
+  pub fn new(input: &str) -> Self {
+    let mut free_blocks = BTreeMap::new();
+    free_blocks.insert(HEAP_BASE, HEAP_END - HEAP_BASE);
+    Self {
+      free_blocks,
+      allocated: HashMap::new(),
+      output: String::new(),
+      input: input.chars().collect(),
+    }
+  }
+
+  /// Text printed through Output so far.
+  pub fn output(&self) -> &str {
+    &self.output
+  }
+
+  /// Run the native implementation of `name`, None if there is none.
+  pub fn call(&mut self, name: &str, args: &[u16], ram: &mut [u16]) -> Option<NativeCall> {
+    self.dispatch(name, args, ram)
+  }
+
+  fn dispatch(&mut self, name: &str, args: &[u16], ram: &mut [u16]) -> Option<NativeCall> {
+    let (class, function) = name.split_once('.')?;
+    let arg = |i: usize| -> Result<u16, String> {
+      args
+        .get(i)
+        .copied()
+        .ok_or(format!("{} expects at least {} arguments", name, i + 1))
+    };
+    let ret = |v: u16| Ok(NativeResult::Return(v));
+    let result = match (class, function) {
+      ("Keyboard" | "Math" | "Memory" | "Output" | "Screen", "init") => ret(0),
+      ("Math", _) => self.math(function, args, name),
+      ("Memory", "peek") => arg(0).and_then(|a| match ram.get(a as usize) {
+        Some(v) => ret(*v),
+        None => Err(format!("Memory.peek: address {} out of range", a)),
+      }),
+      ("Memory", "poke") => arg(0).and_then(|a| {
+        let value = arg(1)?;
+        match ram.get_mut(a as usize) {
+          Some(v) => *v = value,
+          None => return Err(format!("Memory.poke: address {} out of range", a)),
+        }
+        ret(0)
+      }),
+      ("Memory", "alloc") | ("Array", "new") => arg(0).and_then(|size| {
+        let address = self.alloc(size as i16)?;
+        ret(address as u16)
+      }),
+      ("Memory", "deAlloc") | ("Array", "dispose") => arg(0).and_then(|a| {
+        self.de_alloc(a as usize)?;
+        ret(0)
+      }),
+      ("String", _) => self.string(function, args, ram, name),
+      ("Output", "printString") => arg(0).and_then(|s| {
+        let text = self.read_string(s as usize, ram)?;
+        self.output.push_str(&text);
+        ret(0)
+      }),
+      ("Output", "printInt") => arg(0).and_then(|i| {
+        self.output.push_str(&(i as i16).to_string());
+        ret(0)
+      }),
+      ("Output", "printChar") => arg(0).and_then(|c| {
+        self.print_char(c);
+        ret(0)
+      }),
+      ("Output", "println") => {
+        self.output.push('\n');
+        ret(0)
+      }
+      ("Output", "backSpace") => {
+        self.output.pop();
+        ret(0)
+      }
+      ("Keyboard", "keyPressed") => ret(ram.get(KBD).copied().unwrap_or(0)),
+      ("Keyboard", "readChar") => {
+        let c = self.read_char();
+        self.print_char(c);
+        ret(c)
+      }
+      ("Keyboard", "readLine") => arg(0).and_then(|message| {
+        let line = self.read_line(message as usize, ram)?;
+        let s = self.new_string(line.len(), ram)?;
+        for c in line.chars() {
+          self.append_char(s, c as u16, ram)?;
+        }
+        ret(s as u16)
+      }),
+      ("Keyboard", "readInt") => arg(0).and_then(|message| {
+        let line = self.read_line(message as usize, ram)?;
+        ret(int_value(line.trim()) as u16)
+      }),
+      ("Sys", "halt") => Ok(NativeResult::Halt),
+      ("Sys", "error") => arg(0).map(|code| {
+        self.output.push_str(&format!("ERR{}", code as i16));
+        NativeResult::Halt
+      }),
+      ("Sys", "wait") => ret(0),
+      _ => return None,
+    };
+    Some(result)
+  }
+
+  fn math(&mut self, function: &str, args: &[u16], name: &str) -> NativeCall {
+    let argc = if matches!(function, "abs" | "sqrt") {
+      1
+    } else {
+      2
+    };
+    if args.len() < argc {
+      return Err(format!("{} called with too few arguments", name));
+    }
+    let x = args[0] as i16;
+    let y = args.get(1).map(|y| *y as i16).unwrap_or(0);
+    let value = match function {
+      "multiply" => x.wrapping_mul(y),
+      "divide" => {
+        if y == 0 {
+          return Err("Division by zero".to_string());
+        }
+        x.wrapping_div(y)
+      }
+      "min" => x.min(y),
+      "max" => x.max(y),
+      "abs" => x.wrapping_abs(),
+      "sqrt" => {
+        if x < 0 {
+          return Err(format!("Square root of negative number {}", x));
+        }
+        (x as f64).sqrt() as i16
+      }
+      _ => return Err(format!("Unknown native function {}", name)),
+    };
+    Ok(NativeResult::Return(value as u16))
+  }
+
+  fn string(&mut self, function: &str, args: &[u16], ram: &mut [u16], name: &str) -> NativeCall {
+    let ret = |v: u16| Ok(NativeResult::Return(v));
+    match function {
+      "newLine" => return ret(NEW_LINE),
+      "backSpace" => return ret(BACKSPACE),
+      "doubleQuote" => return ret(DOUBLE_QUOTE),
+      _ => (),
+    }
+    let this = *args
+      .first()
+      .ok_or(format!("{} called without arguments", name))? as usize;
+    if function == "new" {
+      return ret(self.new_string(this, ram)? as u16);
+    }
+    let (chars, length) = NativeOs::string_fields(this, ram)?;
+    let index = |i: usize| -> Result<usize, String> {
+      let j = *args.get(i).ok_or(format!("{} expects an index", name))? as i16;
+      if j < 0 || j as usize >= length {
+        return Err(format!("{}: index {} out of bounds", name, j));
+      }
+      Ok(j as usize)
+    };
+    match function {
+      "dispose" => {
+        self.de_alloc(chars)?;
+        self.de_alloc(this)?;
+        ret(0)
+      }
+      "length" => ret(length as u16),
+      "charAt" => ret(ram[chars + index(1)?]),
+      "setCharAt" => {
+        let j = index(1)?;
+        ram[chars + j] = *args.get(2).ok_or(format!("{} expects a char", name))?;
+        ret(0)
+      }
+      "appendChar" => {
+        let c = *args.get(1).ok_or(format!("{} expects a char", name))?;
+        self.append_char(this, c, ram)?;
+        ret(this as u16)
+      }
+      "eraseLastChar" => {
+        if length > 0 {
+          ram[this + STRING_LENGTH] = (length - 1) as u16;
+        }
+        ret(0)
+      }
+      "intValue" => {
+        let text = self.read_string(this, ram)?;
+        ret(int_value(&text) as u16)
+      }
+      "setInt" => {
+        let value = *args.get(1).ok_or(format!("{} expects a value", name))? as i16;
+        ram[this + STRING_LENGTH] = 0;
+        for c in value.to_string().chars() {
+          self.append_char(this, c as u16, ram)?;
+        }
+        ret(0)
+      }
+      _ => Err(format!("Unknown native function {}", name)),
+    }
+  }
+
+  fn new_string(&mut self, capacity: usize, ram: &mut [u16]) -> Result<usize, String> {
+    let this = self.alloc(3)?;
+    ram[this + STRING_CHARS] = self.alloc(capacity.max(1) as i16)? as u16;
+    ram[this + STRING_LENGTH] = 0;
+    ram[this + STRING_CAPACITY] = capacity as u16;
+    Ok(this)
+  }
+
+  fn append_char(&mut self, this: usize, c: u16, ram: &mut [u16]) -> Result<(), String> {
+    let (chars, length) = NativeOs::string_fields(this, ram)?;
+    if length >= ram[this + STRING_CAPACITY] as usize || chars + length >= ram.len() {
+      return Err("String is full".to_string());
+    }
+    ram[chars + length] = c;
+    ram[this + STRING_LENGTH] = (length + 1) as u16;
+    Ok(())
+  }
+
+  /// Returns (address of chars, length) of a String object.
+  fn string_fields(this: usize, ram: &[u16]) -> Result<(usize, usize), String> {
+    if this + STRING_CAPACITY >= ram.len() {
+      return Err(format!("Invalid String object at {}", this));
+    }
+    let chars = ram[this + STRING_CHARS] as usize;
+    let length = ram[this + STRING_LENGTH] as usize;
+    if chars + length > ram.len() {
+      return Err(format!("Invalid String object at {}", this));
+    }
+    Ok((chars, length))
+  }
+
+  fn read_string(&self, this: usize, ram: &[u16]) -> Result<String, String> {
+    let (chars, length) = NativeOs::string_fields(this, ram)?;
+    Ok(
+      ram[chars..chars + length]
+        .iter()
+        .map(|c| to_char(*c))
+        .collect(),
+    )
+  }
+
+  fn print_char(&mut self, c: u16) {
+    match c {
+      BACKSPACE => {
+        self.output.pop();
+      }
+      c => self.output.push(to_char(c)),
+    }
+  }
+
+  fn read_char(&mut self) -> u16 {
+    match self.input.pop_front() {
+      Some('\n') | None => NEW_LINE,
+      Some(c) => c as u16,
+    }
+  }
+
+  fn read_line(&mut self, message: usize, ram: &[u16]) -> Result<String, String> {
+    let message = self.read_string(message, ram)?;
+    self.output.push_str(&message);
+    let mut line = String::new();
+    loop {
+      let c = self.read_char();
+      self.print_char(c);
+      match c {
+        NEW_LINE => return Ok(line),
+        BACKSPACE => {
+          line.pop();
+        }
+        c => line.push(to_char(c)),
+      }
+    }
+  }
+
+  fn alloc(&mut self, size: i16) -> Result<usize, String> {
+    if size < 0 {
+      return Err(format!(
+        "Allocated memory size must be positive, got {}",
+        size
+      ));
+    }
+    let size = (size as usize).max(1);
+    let found = self
+      .free_blocks
+      .iter()
+      .find(|(_, &length)| length >= size)
+      .map(|(&address, &length)| (address, length));
+    let (address, length) = found.ok_or(format!("Heap overflow allocating {} words", size))?;
+    self.free_blocks.remove(&address);
+    if length > size {
+      self.free_blocks.insert(address + size, length - size);
+    }
+    self.allocated.insert(address, size);
+    Ok(address)
+  }
+
+  fn de_alloc(&mut self, address: usize) -> Result<(), String> {
+    let mut length = self
+      .allocated
+      .remove(&address)
+      .ok_or(format!("deAlloc of unallocated address {}", address))?;
+    let mut address = address;
+    // Coalesce with the neighbouring free blocks.
+    if let Some(next_length) = self.free_blocks.remove(&(address + length)) {
+      length += next_length;
+    }
+    let previous = self
+      .free_blocks
+      .range(..address)
+      .next_back()
+      .map(|(&a, &l)| (a, l));
+    if let Some((previous, previous_length)) = previous {
+      if previous + previous_length == address {
+        address = previous;
+        length += previous_length;
+      }
+    }
+    self.free_blocks.insert(address, length);
+    Ok(())
+  }
+}
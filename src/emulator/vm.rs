@@ -0,0 +1,510 @@
+use std::collections::HashMap;
+
+use crate::emulator::native_os::{NativeOs, NativeResult};
+use crate::emulator::{HaltDetector, RunResult, RAM_SIZE};
+use crate::vm::commands::{Command, CommandType};
+use crate::vm::segment_type::SegmentType;
+
+const SP: usize = 0;
+const LCL: usize = 1;
+const ARG: usize = 2;
+const THIS: usize = 3;
+const THAT: usize = 4;
+const TEMP_BASE: usize = 5;
+const STATIC_BASE: usize = 16;
+const STATIC_END: usize = 256;
+const STACK_BASE: usize = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ArithmeticOp {
+  Add,
+  Sub,
+  Neg,
+  Eq,
+  Gt,
+  Lt,
+  And,
+  Or,
+  Not,
+}
+
+impl ArithmeticOp {
+  fn parse(op: &str) -> Option<Self> {
+    let op = match op {
+      "add" => ArithmeticOp::Add,
+      "sub" => ArithmeticOp::Sub,
+      "neg" => ArithmeticOp::Neg,
+      "eq" => ArithmeticOp::Eq,
+      "gt" => ArithmeticOp::Gt,
+      "lt" => ArithmeticOp::Lt,
+      "and" => ArithmeticOp::And,
+      "or" => ArithmeticOp::Or,
+      "not" => ArithmeticOp::Not,
+      _ => return None,
+    };
+    Some(op)
+  }
+}
+
+#[derive(Debug, Clone)]
+enum CallTarget {
+  Function(usize),
+  Unresolved(String),
+}
+
+/// A command with every name resolved to an address.
+#[derive(Debug, Clone)]
+enum Instruction {
+  Push(SegmentType, usize),
+  Pop(SegmentType, usize),
+  Arithmetic(ArithmeticOp),
+  Goto(usize),
+  IfGoto(usize),
+  Function(usize),
+  Call(CallTarget, usize),
+  Return,
+  Nop,
+}
+
+struct Frame {
+  function: String,
+  return_address: usize,
+}
+
+/// Interpreter of vm commands over the standard Hack memory layout, so RAM dumps
+/// can be compared with the CPU-level translation.
+pub struct VmEmulator {
+  program: Vec<Instruction>,
+  // Function name of every instruction, used in error messages.
+  owners: Vec<String>,
+  // `File.vm:line` of every instruction, the lines of the .vm.map files.
+  lines: Vec<String>,
+  functions: HashMap<String, usize>,
+  ram: Vec<u16>,
+  pc: usize,
+  // Instruction being executed, errors are reported at it.
+  current: usize,
+  call_stack: Vec<Frame>,
+  steps: u64,
+  halt_detector: HaltDetector,
+  native_os: Option<NativeOs>,
+}
+
+fn arg_index(cmd: &Command) -> Result<usize, String> {
+  if cmd.arg2() < 0 {
+    return Err(format!("negative argument in {:?}", cmd));
+  }
+  Ok(cmd.arg2() as usize)
+}
+
+impl VmEmulator {
+  /// Link vm files, each given as (file name without extension, commands).
+  pub fn new(files: Vec<(String, Vec<Command>)>) -> Result<Self, String> {
+    let mut functions = HashMap::new();
+    let mut labels = HashMap::new();
+    let mut owners = vec![];
+    let mut lines = vec![];
+    let mut commands = vec![];
+    let mut current_function = String::new();
+    for (file_name, file_commands) in files {
+      for (line, cmd) in file_commands.into_iter().enumerate() {
+        match cmd.cmd_type() {
+          CommandType::None => continue,
+          CommandType::Function => {
+            current_function = cmd.arg1().unwrap();
+            if functions
+              .insert(current_function.clone(), commands.len())
+              .is_some()
+            {
+              return Err(format!("Duplicate function {}", current_function));
+            }
+          }
+          CommandType::Label => {
+            let key = (current_function.clone(), cmd.arg1().unwrap());
+            if labels.insert(key, commands.len()).is_some() {
+              return Err(format!(
+                "Duplicate label {} in {}",
+                cmd.arg1().unwrap(),
+                current_function
+              ));
+            }
+          }
+          _ => (),
+        }
+        owners.push(current_function.clone());
+        lines.push(format!("{}.vm:{}", file_name, line + 1));
+        commands.push((file_name.clone(), cmd));
+      }
+    }
+
+    let mut statics: HashMap<(String, usize), usize> = HashMap::new();
+    let mut program = Vec::with_capacity(commands.len());
+    for (idx, (file_name, cmd)) in commands.iter().enumerate() {
+      let resolve_label = |label: String| {
+        labels
+          .get(&(owners[idx].clone(), label.clone()))
+          .copied()
+          .ok_or(format!("Undefined label {} in {}", label, owners[idx]))
+      };
+      let instruction = match cmd.cmd_type() {
+        CommandType::Push | CommandType::Pop => {
+          let seg = SegmentType::new(&cmd.arg1().unwrap());
+          let mut idx = arg_index(cmd)?;
+          if seg == SegmentType::Static {
+            // Statics are named `File.i` and allocated in order of appearance.
+            let next = STATIC_BASE + statics.len();
+            idx = *statics.entry((file_name.clone(), idx)).or_insert(next);
+            if idx >= STATIC_END {
+              return Err("Too many static variables".to_string());
+            }
+          }
+          if seg == SegmentType::Invalid {
+            return Err(format!("Invalid segment in {:?}", cmd));
+          }
+          if cmd.cmd_type() == CommandType::Push {
+            Instruction::Push(seg, idx)
+          } else {
+            Instruction::Pop(seg, idx)
+          }
+        }
+        CommandType::Arithmetic(_) => Instruction::Arithmetic(
+          ArithmeticOp::parse(&cmd.arg1().unwrap())
+            .ok_or(format!("Invalid arithmetic {:?}", cmd))?,
+        ),
+        CommandType::Label => Instruction::Nop,
+        CommandType::Goto => Instruction::Goto(resolve_label(cmd.arg1().unwrap())?),
+        CommandType::If => Instruction::IfGoto(resolve_label(cmd.arg1().unwrap())?),
+        CommandType::Function => Instruction::Function(arg_index(cmd)?),
+        CommandType::Call => {
+          let name = cmd.arg1().unwrap();
+          let target = match functions.get(&name) {
+            Some(address) => CallTarget::Function(*address),
+            None => CallTarget::Unresolved(name),
+          };
+          Instruction::Call(target, arg_index(cmd)?)
+        }
+        CommandType::Return => Instruction::Return,
+        CommandType::None => Instruction::Nop,
+      };
+      program.push(instruction);
+    }
+
+    let mut emulator = Self {
+      program,
+      owners,
+      lines,
+      functions,
+      ram: vec![0; RAM_SIZE],
+      pc: 0,
+      current: 0,
+      call_stack: vec![],
+      steps: 0,
+      halt_detector: HaltDetector::new(),
+      native_os: None,
+    };
+    emulator.ram[SP] = STACK_BASE as u16;
+    Ok(emulator)
+  }
+
+  /// Serve calls to functions missing from the loaded files with `native_os`.
+  pub fn enable_native_os(&mut self, native_os: NativeOs) {
+    self.native_os = Some(native_os);
+  }
+
+  pub fn native_os(&self) -> Option<&NativeOs> {
+    self.native_os.as_ref()
+  }
+
+  /// Start from `Sys.init` with a proper frame if it is defined, otherwise from
+  /// `Main.main` when the native OS stands in for Sys, otherwise from the first
+  /// command.
+  pub fn bootstrap(&mut self) -> Result<(), String> {
+    self.ram[SP] = STACK_BASE as u16;
+    let end = self.program.len();
+    if let Some(&address) = self.functions.get("Sys.init") {
+      self.call("Sys.init".to_string(), address, 0, end)?;
+    } else if let (Some(&address), Some(_)) = (self.functions.get("Main.main"), &self.native_os) {
+      self.call("Main.main".to_string(), address, 0, end)?;
+    } else {
+      self.pc = 0;
+    }
+    Ok(())
+  }
+
+  pub fn ram(&self, address: usize) -> i16 {
+    self.ram[address] as i16
+  }
+
+  pub fn set_ram(&mut self, address: usize, value: i16) {
+    self.ram[address] = value as u16;
+  }
+
+  pub fn steps(&self) -> u64 {
+    self.steps
+  }
+
+  /// Name of the function currently executing.
+  pub fn current_function(&self) -> &str {
+    match self.call_stack.last() {
+      Some(frame) => &frame.function,
+      None => self.owners.get(self.pc).map(|s| &s[..]).unwrap_or(""),
+    }
+  }
+
+  fn error(&self, msg: String) -> String {
+    let line = self.lines.get(self.current).map(|s| &s[..]).unwrap_or("");
+    format!("{} (in {}, {})", msg, self.current_function(), line)
+  }
+
+  fn write(&mut self, address: usize, value: u16) -> Result<(), String> {
+    if address >= RAM_SIZE {
+      return Err(self.error(format!("RAM address {} out of range", address)));
+    }
+    self.halt_detector.record_write(address, self.ram[address]);
+    self.ram[address] = value;
+    Ok(())
+  }
+
+  fn read(&self, address: usize) -> Result<u16, String> {
+    if address >= RAM_SIZE {
+      return Err(self.error(format!("RAM address {} out of range", address)));
+    }
+    Ok(self.ram[address])
+  }
+
+  fn push(&mut self, value: u16) -> Result<(), String> {
+    let sp = self.ram[SP] as usize;
+    self.write(sp, value)?;
+    self.write(SP, (sp + 1) as u16)
+  }
+
+  fn pop(&mut self) -> Result<u16, String> {
+    let sp = self.ram[SP] as usize;
+    if sp <= STACK_BASE {
+      return Err(self.error("Stack underflow".to_string()));
+    }
+    self.write(SP, (sp - 1) as u16)?;
+    self.read(sp - 1)
+  }
+
+  fn segment_address(&self, seg: &SegmentType, idx: usize) -> Result<usize, String> {
+    let address = match seg {
+      SegmentType::Local => self.ram[LCL] as usize + idx,
+      SegmentType::Argument => self.ram[ARG] as usize + idx,
+      SegmentType::This => self.ram[THIS] as usize + idx,
+      SegmentType::That => self.ram[THAT] as usize + idx,
+      SegmentType::Temp if idx < 8 => TEMP_BASE + idx,
+      SegmentType::Pointer if idx < 2 => THIS + idx,
+      SegmentType::Static => idx,
+      _ => {
+        return Err(self.error(format!(
+          "Invalid access to {} {}",
+          seg.to_vm_string(),
+          idx
+        )))
+      }
+    };
+    Ok(address)
+  }
+
+  fn call(
+    &mut self,
+    function: String,
+    address: usize,
+    argc: usize,
+    return_address: usize,
+  ) -> Result<(), String> {
+    let arg = (self.ram[SP] as usize)
+      .checked_sub(argc)
+      .ok_or_else(|| self.error(format!("Not enough arguments on stack for {}", function)))?;
+    self.push(return_address as u16)?;
+    for segment in [LCL, ARG, THIS, THAT] {
+      self.push(self.ram[segment])?;
+    }
+    let sp = self.ram[SP] as usize;
+    self.write(ARG, arg as u16)?;
+    self.write(LCL, sp as u16)?;
+    self.call_stack.push(Frame {
+      function,
+      return_address,
+    });
+    self.pc = address;
+    Ok(())
+  }
+
+  fn do_return(&mut self) -> Result<(), String> {
+    let frame = self.ram[LCL] as usize;
+    if frame < 5 {
+      return Err(self.error("Return without a caller frame".to_string()));
+    }
+    let return_address = self.read(frame - 5)? as usize;
+    let value = self.pop()?;
+    let arg = self.ram[ARG] as usize;
+    self.write(arg, value)?;
+    self.write(SP, (arg + 1) as u16)?;
+    for (offset, segment) in [THAT, THIS, ARG, LCL].iter().enumerate() {
+      let saved = self.read(frame - 1 - offset)?;
+      self.write(*segment, saved)?;
+    }
+    if let Some(frame) = self.call_stack.pop() {
+      debug_assert_eq!(frame.return_address, return_address);
+    }
+    self.pc = return_address;
+    Ok(())
+  }
+
+  fn call_native(&mut self, name: &str, argc: usize) -> Result<bool, String> {
+    let sp = self.ram[SP] as usize;
+    if sp < STACK_BASE + argc {
+      return Err(self.error(format!("Not enough arguments on stack for {}", name)));
+    }
+    let args = self.ram[sp - argc..sp].to_vec();
+    let result = match self.native_os.as_mut() {
+      Some(native_os) => native_os.call(name, &args, &mut self.ram),
+      None => None,
+    };
+    match result {
+      Some(Ok(NativeResult::Return(value))) => {
+        // Native code may have side effects outside RAM, e.g. printing.
+        self.halt_detector = HaltDetector::new();
+        self.write(SP, (sp - argc) as u16)?;
+        self.push(value)?;
+        Ok(true)
+      }
+      Some(Ok(NativeResult::Halt)) => Ok(false),
+      Some(Err(e)) => Err(self.error(format!("{}: {}", name, e))),
+      None => Err(self.error(format!("Call to undefined function {}", name))),
+    }
+  }
+
+  fn arithmetic(&mut self, op: ArithmeticOp) -> Result<(), String> {
+    let bool_value = |b: bool| if b { 0xffff } else { 0 };
+    let y = self.pop()?;
+    let value = match op {
+      ArithmeticOp::Neg => (y as i16).wrapping_neg() as u16,
+      ArithmeticOp::Not => !y,
+      _ => {
+        let x = self.pop()?;
+        match op {
+          ArithmeticOp::Add => x.wrapping_add(y),
+          ArithmeticOp::Sub => x.wrapping_sub(y),
+          ArithmeticOp::And => x & y,
+          ArithmeticOp::Or => x | y,
+          ArithmeticOp::Eq => bool_value(x == y),
+          ArithmeticOp::Gt => bool_value((x as i16) > (y as i16)),
+          ArithmeticOp::Lt => bool_value((x as i16) < (y as i16)),
+          _ => unreachable!(),
+        }
+      }
+    };
+    self.push(value)
+  }
+
+  fn jump(&mut self, from: usize, target: usize) -> bool {
+    self.pc = target;
+    if target <= from {
+      return !self.halt_detector.on_backward_jump(from, &[], &self.ram);
+    }
+    true
+  }
+
+  /// Execute one command, return false when a halt loop is detected.
+  pub fn step(&mut self) -> Result<bool, String> {
+    let pc = self.pc;
+    self.current = pc;
+    self.steps += 1;
+    self.pc += 1;
+    match self.program[pc].clone() {
+      Instruction::Push(SegmentType::Constant, idx) => self.push(idx as u16)?,
+      Instruction::Push(seg, idx) => {
+        let address = self.segment_address(&seg, idx)?;
+        let value = self.read(address)?;
+        self.push(value)?;
+      }
+      Instruction::Pop(seg, idx) => {
+        let address = self.segment_address(&seg, idx)?;
+        let value = self.pop()?;
+        self.write(address, value)?;
+      }
+      Instruction::Arithmetic(op) => self.arithmetic(op)?,
+      Instruction::Goto(target) => return Ok(self.jump(pc, target)),
+      Instruction::IfGoto(target) => {
+        if self.pop()? != 0 {
+          return Ok(self.jump(pc, target));
+        }
+      }
+      Instruction::Function(locals) => {
+        for _ in 0..locals {
+          self.push(0)?;
+        }
+      }
+      Instruction::Call(CallTarget::Function(address), argc) => {
+        let function = self.owners[address].clone();
+        self.call(function, address, argc, pc + 1)?;
+      }
+      Instruction::Call(CallTarget::Unresolved(name), argc) => {
+        return self.call_native(&name, argc);
+      }
+      Instruction::Return => self.do_return()?,
+      Instruction::Nop => (),
+    }
+    Ok(true)
+  }
+
+  /// Run until a halt loop, the end of the program or `max_steps`.
+  pub fn run(&mut self, max_steps: u64) -> Result<RunResult, String> {
+    let start = self.steps;
+    while self.steps - start < max_steps {
+      if self.pc >= self.program.len() {
+        return Ok(RunResult::ProgramEnd(self.steps));
+      }
+      if !self.step()? {
+        return Ok(RunResult::Halted(self.steps));
+      }
+    }
+    Ok(RunResult::CycleLimit(self.steps))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  // Commands of a vm file like the hack parser reads them, one per line.
+  fn commands(source: &str) -> Vec<Command> {
+    source
+      .lines()
+      .map(str::trim)
+      .map(|line| {
+        if line.is_empty() || line.starts_with("//") {
+          Command::new(CommandType::None)
+        } else {
+          Command::from_str(line)
+        }
+      })
+      .collect()
+  }
+
+  #[test]
+  fn errors_at_vm_line() {
+    let sys = "function Sys.init 0
+      call Main.main 0
+      return";
+    let main = "// Calls an undefined function.
+      function Main.main 0
+
+      push constant 1
+      call Foo.bar 1
+      return";
+    let files = vec![
+      ("Sys".to_string(), commands(sys)),
+      ("Main".to_string(), commands(main)),
+    ];
+    let mut vm = VmEmulator::new(files).unwrap();
+    vm.bootstrap().unwrap();
+    assert_eq!(
+      vm.run(100).unwrap_err(),
+      "Call to undefined function Foo.bar (in Main.main, Main.vm:5)"
+    );
+  }
+}
@@ -0,0 +1,154 @@
+use std::fmt::{Display, Formatter};
+
+use crate::token::{Span, Token, TokenDescriptor};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Location {
+  pub file: String,
+  // The token or construct in error, lines and columns start at 1.
+  pub span: Span,
+}
+
+impl Location {
+  pub fn new(file: String, span: Span) -> Self {
+    Self { file, span }
+  }
+
+  pub fn line(&self) -> usize {
+    self.span.start_line
+  }
+
+  pub fn column(&self) -> usize {
+    self.span.start_column
+  }
+}
+
+impl From<&TokenDescriptor> for Location {
+  fn from(descriptor: &TokenDescriptor) -> Self {
+    Location::new(descriptor.file().clone(), descriptor.span())
+  }
+}
+
+impl Display for Location {
+  fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    write!(f, "{}:{}:{}", self.file, self.line(), self.column())
+  }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum CompileErrorKind {
+  // What the grammar expected, e.g. "';'", "identifier", "type" and the token found.
+  Expected { expected: String, found: String },
+  // Malformed input found by the lexer, e.g. an unterminated string.
+  Lexical(String),
+  // Valid syntax that breaks a rule of the program, e.g. a call to an unknown subroutine.
+  Semantic(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompileError {
+  kind: CompileErrorKind,
+  // Boxed like context and notes below.
+  location: Box<Location>,
+  // Where in the construct the error happened, e.g. "after let statement".
+  context: Option<Box<str>>,
+  // Hints on how to fix the error. Boxed like context to keep results small.
+  notes: Box<[String]>,
+}
+
+/// Describe a found token as `'do'`, `"text"` or `end of file`.
+pub fn describe_token(token: Option<&Token>) -> String {
+  match token {
+    None | Some(Token::None) => "end of file".to_string(),
+    Some(Token::StringVal(s)) => format!("\"{}\"", s),
+    Some(token) => format!("'{}'", token.lexeme()),
+  }
+}
+
+impl CompileError {
+  pub fn new(kind: CompileErrorKind, location: Location) -> Self {
+    Self {
+      kind,
+      location: Box::new(location),
+      context: None,
+      notes: Box::default(),
+    }
+  }
+
+  pub fn expected(expected: &str, found: Option<&Token>, location: Location) -> Self {
+    CompileError::new(
+      CompileErrorKind::Expected {
+        expected: expected.to_string(),
+        found: describe_token(found),
+      },
+      location,
+    )
+  }
+
+  pub fn lexical(message: &str, location: Location) -> Self {
+    CompileError::new(CompileErrorKind::Lexical(message.to_string()), location)
+  }
+
+  pub fn semantic(message: &str, location: Location) -> Self {
+    CompileError::new(CompileErrorKind::Semantic(message.to_string()), location)
+  }
+
+  /// Attach a context phrase unless a more specific one is already set.
+  pub fn with_context(mut self, context: &str) -> Self {
+    if self.context.is_none() {
+      self.context = Some(context.into());
+    }
+    self
+  }
+
+  pub fn with_note(mut self, note: &str) -> Self {
+    let mut notes = self.notes.into_vec();
+    notes.push(note.to_string());
+    self.notes = notes.into_boxed_slice();
+    self
+  }
+
+  pub fn kind(&self) -> &CompileErrorKind {
+    &self.kind
+  }
+
+  pub fn location(&self) -> &Location {
+    &self.location
+  }
+
+  pub fn context(&self) -> Option<&str> {
+    self.context.as_deref()
+  }
+
+  pub fn notes(&self) -> &[String] {
+    &self.notes
+  }
+
+  /// Stable identifier of the kind of error, e.g. `E0001` for syntax errors.
+  pub fn code(&self) -> &'static str {
+    match self.kind {
+      CompileErrorKind::Expected { .. } => "E0001",
+      CompileErrorKind::Lexical(_) => "E0002",
+      CompileErrorKind::Semantic(_) => "E0003",
+    }
+  }
+
+  /// The error without its location.
+  pub fn message(&self) -> String {
+    match &self.kind {
+      CompileErrorKind::Expected { expected, found } => match &self.context {
+        Some(context) => format!("expected {} {}, found {}", expected, context, found),
+        None => format!("expected {}, found {}", expected, found),
+      },
+      CompileErrorKind::Lexical(message) | CompileErrorKind::Semantic(message) => message.clone(),
+    }
+  }
+}
+
+impl Display for CompileError {
+  fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    write!(f, "{}: {}", self.location, self.message())
+  }
+}
+
+impl std::error::Error for CompileError {}
@@ -0,0 +1,431 @@
+use crate::ast::*;
+use crate::token::{Comment, Lexer, Span, Token};
+
+const INDENT: &str = "    ";
+
+/**
+ * Canonical text of a parsed class: four space indentation, one declaration
+ * or statement per line, spaces around binary operators and at most one blank
+ * line in a row. `source` is the text the class was parsed from, its comments
+ * are written before the token that followed them, or at the end of the line
+ * they trailed.
+ */
+pub fn format_class(class: &Class, source: &str) -> String {
+  let mut lexer = Lexer::new(source.to_string());
+  let tokens = lexer.by_ref().filter_map(Result::ok).collect();
+  let comments = lexer.comments().to_vec();
+  let mut printer = Printer {
+    tokens,
+    comments,
+    next_token: 0,
+    next_comment: 0,
+    out: String::new(),
+    indent: 0,
+    line_start: true,
+    last_line: 0,
+    after_open: false,
+    closing: false,
+    blank_line: false,
+  };
+  printer.class(class);
+  printer.finish()
+}
+
+struct Printer {
+  tokens: Vec<(Token, Span)>,
+  comments: Vec<Comment>,
+  next_token: usize,
+  next_comment: usize,
+  out: String,
+  indent: usize,
+  // Nothing is written on the current line yet.
+  line_start: bool,
+  // Source line where the last written token or comment ends.
+  last_line: usize,
+  // Blank lines of the source are dropped after an opening and before a
+  // closing brace.
+  after_open: bool,
+  closing: bool,
+  // A blank line is written before the next line whatever the source has.
+  blank_line: bool,
+}
+
+impl Printer {
+  fn class(&mut self, class: &Class) {
+    self.begin_line();
+    self.token("class");
+    self.space();
+    self.token(&class.name.name);
+    self.open_block();
+    for var_dec in &class.variables {
+      self.declaration(var_dec);
+    }
+    for (i, subroutine) in class.subroutines.iter().enumerate() {
+      // Subroutines are always apart from each other and the variables.
+      self.blank_line = i > 0 || !class.variables.is_empty();
+      self.subroutine(subroutine);
+    }
+    self.close_block();
+  }
+
+  // static, field or var declaration: keyword type name, name;
+  fn declaration(&mut self, var_dec: &VarDec) {
+    let keyword = match var_dec.kind {
+      VarScope::Variable => "var".to_string(),
+      scope => scope.to_string(),
+    };
+    self.begin_line();
+    self.token(&keyword);
+    self.space();
+    self.token(&var_dec.var_type.name);
+    self.space();
+    for (i, name) in var_dec.names.iter().enumerate() {
+      if i > 0 {
+        self.token(",");
+        self.space();
+      }
+      self.token(&name.name);
+    }
+    self.token(";");
+  }
+
+  fn subroutine(&mut self, subroutine: &SubroutineDec) {
+    self.begin_line();
+    self.token(&subroutine.kind.to_string());
+    self.space();
+    self.token(&subroutine.return_type.name);
+    self.space();
+    self.token(&subroutine.name.name);
+    self.token("(");
+    for (i, parameter) in subroutine.parameters.iter().enumerate() {
+      if i > 0 {
+        self.token(",");
+        self.space();
+      }
+      self.token(&parameter.var_type.name);
+      self.space();
+      self.token(&parameter.name.name);
+    }
+    self.token(")");
+    self.open_block();
+    for var_dec in &subroutine.locals {
+      self.declaration(var_dec);
+    }
+    self.statements(&subroutine.statements);
+    self.close_block();
+  }
+
+  fn statements(&mut self, statements: &[Statement]) {
+    for statement in statements {
+      self.begin_line();
+      match statement {
+        Statement::Let {
+          target,
+          index,
+          value,
+          ..
+        } => {
+          self.token("let");
+          self.space();
+          self.token(&target.name);
+          if let Some(index) = index {
+            self.token("[");
+            self.expression(index);
+            self.token("]");
+          }
+          self.space();
+          self.token("=");
+          self.space();
+          self.expression(value);
+          self.token(";");
+        }
+        Statement::If {
+          condition,
+          body,
+          else_body,
+          ..
+        } => {
+          self.token("if");
+          self.condition(condition);
+          self.block(body);
+          if let Some(else_body) = else_body {
+            self.space();
+            self.token("else");
+            self.block(else_body);
+          }
+        }
+        Statement::While {
+          condition, body, ..
+        } => {
+          self.token("while");
+          self.condition(condition);
+          self.block(body);
+        }
+        Statement::Do { call, .. } => {
+          self.token("do");
+          self.space();
+          self.call(call);
+          self.token(";");
+        }
+        Statement::Return { value, .. } => {
+          self.token("return");
+          if let Some(value) = value {
+            self.space();
+            self.expression(value);
+          }
+          self.token(";");
+        }
+      }
+    }
+  }
+
+  // Condition of an if or while after its keyword.
+  fn condition(&mut self, condition: &Expr) {
+    self.space();
+    self.token("(");
+    self.expression(condition);
+    self.token(")");
+  }
+
+  fn block(&mut self, statements: &[Statement]) {
+    self.open_block();
+    self.statements(statements);
+    self.close_block();
+  }
+
+  fn expression(&mut self, expr: &Expr) {
+    match expr {
+      Expr::Int { value, .. } => self.token(&value.to_string()),
+      Expr::String { value, .. } => self.token(&Token::StringVal(value.clone()).lexeme()),
+      Expr::Keyword { keyword, .. } => self.token(keyword),
+      Expr::Var(name) => self.token(&name.name),
+      Expr::Index { array, index, .. } => {
+        self.token(&array.name);
+        self.token("[");
+        self.expression(index);
+        self.token("]");
+      }
+      Expr::Call(call) => self.call(call),
+      Expr::Unary { op, operand, .. } => {
+        self.token(&op.to_string());
+        self.expression(operand);
+      }
+      Expr::Binary {
+        op, left, right, ..
+      } => {
+        self.expression(left);
+        self.space();
+        self.token(&op.to_string());
+        self.space();
+        self.expression(right);
+      }
+      Expr::Paren { expr, .. } => {
+        self.token("(");
+        self.expression(expr);
+        self.token(")");
+      }
+    }
+  }
+
+  fn call(&mut self, call: &Call) {
+    if let Some(receiver) = &call.receiver {
+      self.token(&receiver.name);
+      self.token(".");
+    }
+    self.token(&call.name.name);
+    self.token("(");
+    for (i, argument) in call.arguments.iter().enumerate() {
+      if i > 0 {
+        self.token(",");
+        self.space();
+      }
+      self.expression(argument);
+    }
+    self.token(")");
+  }
+
+  fn open_block(&mut self) {
+    self.space();
+    self.token("{");
+    self.indent += 1;
+    self.after_open = true;
+  }
+
+  fn close_block(&mut self) {
+    self.begin_line();
+    // Comments at the end of the block stay indented with its statements.
+    if let Some((_, span)) = self.tokens.get(self.next_token) {
+      let span = *span;
+      self.comments_before(span);
+    }
+    self.indent -= 1;
+    self.newline();
+    self.closing = true;
+    self.token("}");
+  }
+
+  // Write the next token of the source, which is `text`, after the comments
+  // that precede it.
+  fn token(&mut self, text: &str) {
+    let (token, span) = self.tokens[self.next_token].clone();
+    debug_assert_eq!(token.lexeme(), text);
+    self.next_token += 1;
+    self.comments_before(span);
+    if self.line_start {
+      let keep_blank = !std::mem::take(&mut self.closing);
+      self.start_line(span.start_line, keep_blank);
+    }
+    self.out += text;
+    self.last_line = span.end_line;
+  }
+
+  fn space(&mut self) {
+    if !self.line_start {
+      self.out.push(' ');
+    }
+  }
+
+  fn comments_before(&mut self, span: Span) {
+    while let Some(comment) = self.comments.get(self.next_comment) {
+      let start = (comment.span.start_line, comment.span.start_column);
+      if start > (span.start_line, span.start_column) {
+        break;
+      }
+      let comment = comment.clone();
+      self.next_comment += 1;
+      self.comment(&comment);
+    }
+  }
+
+  fn comment(&mut self, comment: &Comment) {
+    let text = comment.text.trim_end();
+    if !self.line_start && comment.span.start_line == self.last_line {
+      // Trailing the code on its line.
+      self.trim_line();
+      self.out.push(' ');
+      let column = self.out.len() - self.out.rfind('\n').map_or(0, |i| i + 1);
+      self.out += text;
+      if comment.is_line_comment() {
+        self.last_line = comment.span.end_line;
+        self.comment_run(comment.span.start_column, column);
+        self.newline();
+      } else {
+        self.out.push(' ');
+      }
+    } else {
+      self.newline();
+      self.start_line(comment.span.start_line, true);
+      // Lines of a block comment keep their offset to the first one.
+      let shift = comment.span.start_column - 1;
+      for (i, line) in text.lines().enumerate() {
+        if i > 0 {
+          let skipped = line
+            .chars()
+            .take(shift)
+            .take_while(|c| c.is_whitespace())
+            .count();
+          let line: String = line.chars().skip(skipped).collect();
+          self.out.push('\n');
+          if !line.is_empty() {
+            self.out += &INDENT.repeat(self.indent);
+            self.out += line.trim_end();
+          }
+        } else {
+          self.out += line;
+        }
+      }
+      self.newline();
+    }
+    self.last_line = comment.span.end_line;
+  }
+
+  // Line comments on the following lines that start in the column of a
+  // trailing comment continue it, they stay aligned under it.
+  fn comment_run(&mut self, source_column: usize, column: usize) {
+    let next = self.tokens.get(self.next_token).map(|(_, span)| *span);
+    while let Some(comment) = self.comments.get(self.next_comment) {
+      let before_next = next.is_none_or(|span| {
+        (comment.span.start_line, comment.span.start_column) < (span.start_line, span.start_column)
+      });
+      if !comment.is_line_comment()
+        || comment.span.start_line != self.last_line + 1
+        || comment.span.start_column != source_column
+        || !before_next
+      {
+        break;
+      }
+      self.out.push('\n');
+      self.out += &" ".repeat(column);
+      self.out += comment.text.trim_end();
+      self.last_line = comment.span.end_line;
+      self.next_comment += 1;
+    }
+  }
+
+  // Start a declaration or statement on a new line, the comments that trail
+  // the previous one are written first.
+  fn begin_line(&mut self) {
+    let next = self.tokens.get(self.next_token).map(|(_, span)| *span);
+    while let Some(comment) = self.comments.get(self.next_comment) {
+      let before_next = next.is_none_or(|span| {
+        (comment.span.start_line, comment.span.start_column) < (span.start_line, span.start_column)
+      });
+      if self.line_start || comment.span.start_line != self.last_line || !before_next {
+        break;
+      }
+      let comment = comment.clone();
+      self.next_comment += 1;
+      self.comment(&comment);
+    }
+    self.newline();
+  }
+
+  fn start_line(&mut self, source_line: usize, keep_blank: bool) {
+    let blank =
+      self.blank_line || (keep_blank && !self.after_open && source_line > self.last_line + 1);
+    if blank && !self.out.is_empty() {
+      self.out.push('\n');
+    }
+    self.blank_line = false;
+    self.after_open = false;
+    self.out += &INDENT.repeat(self.indent);
+    self.line_start = false;
+  }
+
+  fn newline(&mut self) {
+    if !self.line_start {
+      self.trim_line();
+      self.out.push('\n');
+      self.line_start = true;
+    }
+  }
+
+  fn trim_line(&mut self) {
+    let len = self.out.trim_end_matches(' ').len();
+    self.out.truncate(len);
+  }
+
+  fn finish(mut self) -> String {
+    self.begin_line();
+    while self.next_comment < self.comments.len() {
+      let comment = self.comments[self.next_comment].clone();
+      self.next_comment += 1;
+      self.comment(&comment);
+    }
+    self.newline();
+    self.out
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::format_source;
+
+  #[test]
+  fn formats_main() {
+    let source = include_str!("../assets/Main.jack");
+    let expected = include_str!("../assets/fmt/Main.jack");
+    assert_eq!(format_source("Main.jack", source).unwrap(), expected);
+    assert_eq!(format_source("Main.jack", expected).unwrap(), expected);
+  }
+}
@@ -1,9 +1,18 @@
+pub mod asm;
+pub mod ast;
 pub mod code_writer;
 pub mod common;
 pub mod compiler;
+pub mod diagnostic;
+pub mod emulator;
+pub mod error;
+pub mod formatter;
 pub mod logger;
-pub mod operation;
+pub mod lsp;
+pub mod os;
 pub mod parser;
+pub mod semantic;
+pub mod sources;
 pub mod symbol_table;
 pub mod token;
 pub mod vm;
@@ -11,3 +20,210 @@ pub mod xml;
 
 #[macro_use]
 extern crate lazy_static;
+
+use crate::ast::Class;
+use crate::code_writer::CodeWriter;
+use crate::common::MemoryOutput;
+use crate::compiler::Compiler;
+use crate::error::CompileError;
+use crate::parser::jack::Parser;
+use crate::semantic::{ProjectIndex, Strictness};
+use crate::vm::source_map::SourceMap;
+use crate::xml::ast_xml_generator::AstXMLGenerator;
+use crate::xml::token_xml_generator::TokenXMLGenerator;
+
+// "Main" and "Main.jack" both name the class Main in file Main.jack.
+fn source_names(name: &str) -> (String, String) {
+  let class_name = name.strip_suffix(".jack").unwrap_or(name);
+  (class_name.to_string(), format!("{}.jack", class_name))
+}
+
+/// Name of the class defined by a jack file, e.g. `Main` for `src/Main.jack`.
+pub fn class_name_of(source: &str) -> String {
+  let file_name = source.rsplit('/').next().unwrap_or(source);
+  file_name
+    .strip_suffix(".jack")
+    .unwrap_or(file_name)
+    .to_string()
+}
+
+/// Parse the class read by `parser` into its syntax tree.
+pub fn parse_class(parser: Parser) -> Result<Class, Vec<CompileError>> {
+  Compiler::new(parser).run()
+}
+
+/// Vm code of a class, or the errors that stopped its compilation.
+pub struct CompiledClass {
+  pub class_name: String,
+  pub result: Result<String, Vec<CompileError>>,
+  // Jack source of each vm line, empty when the class failed to compile.
+  pub source_map: SourceMap,
+}
+
+/// Settings of a compilation.
+#[derive(Debug, Clone, Default)]
+pub struct CompileOptions {
+  pub strictness: Strictness,
+  // Precede the vm commands of every source line with a `// Main.jack:14`
+  // comment.
+  pub source_comments: bool,
+}
+
+/// Compile the classes read by `parsers` as one program.
+/// Every class is parsed and indexed before any is checked, so calls are
+/// validated across classes, the OS classes included.
+pub fn compile_project(parsers: Vec<Parser>, options: &CompileOptions) -> Vec<CompiledClass> {
+  // Classes are kept on errors, as far as they were parsed.
+  let mut parsed: Vec<_> = parsers
+    .into_iter()
+    .map(|parser| {
+      let class_name = class_name_of(parser.source());
+      let (class, errors) = Compiler::new(parser).parse();
+      (class_name, class, errors)
+    })
+    .collect();
+  let mut index = ProjectIndex::new();
+  for (_, class, errors) in parsed.iter_mut() {
+    // A class defined twice is only compiled once.
+    if let Some(class) = class {
+      if let Err(e) = index.add_class(class) {
+        errors.push(e);
+      }
+    }
+  }
+  index.add_os_classes();
+  parsed
+    .into_iter()
+    .map(|(class_name, class, errors)| {
+      let result = match class {
+        Some(class) if errors.is_empty() => {
+          let errors = semantic::check_class(&class, &index, options.strictness);
+          if errors.is_empty() {
+            let output = MemoryOutput::new();
+            CodeWriter::new_with_output(output.target(), &class)
+              .with_source_comments(options.source_comments)
+              .generate_vm_code()
+              .map(|source_map| (output.contents(), source_map))
+          } else {
+            Err(errors)
+          }
+        }
+        _ => Err(errors),
+      };
+      let (result, source_map) = match result {
+        Ok((vm_code, source_map)) => (Ok(vm_code), source_map),
+        Err(errors) => (Err(errors), SourceMap::new()),
+      };
+      CompiledClass {
+        class_name,
+        result,
+        source_map,
+      }
+    })
+    .collect()
+}
+
+/// Compile the jack class `source` named `name` (e.g. "Main") to vm code.
+/// Calls may only target the class itself and the OS.
+pub fn compile_source(name: &str, source: &str) -> Result<String, Vec<CompileError>> {
+  let (_, file_name) = source_names(name);
+  let parser = Parser::new_from_source(&file_name, source);
+  compile_project(vec![parser], &CompileOptions::default())
+    .remove(0)
+    .result
+}
+
+/// The jack class `source` named `name` in canonical layout, see
+/// [`formatter::format_class`]. Sources with errors are not formatted.
+pub fn format_source(name: &str, source: &str) -> Result<String, Vec<CompileError>> {
+  let (_, file_name) = source_names(name);
+  let class = parse_class(Parser::new_from_source(&file_name, source))?;
+  Ok(formatter::format_class(&class, source))
+}
+
+/// Parse tree of the jack class `source` as xml.
+pub fn source_to_xml(name: &str, source: &str) -> Result<String, Vec<CompileError>> {
+  let (_, file_name) = source_names(name);
+  let class = parse_class(Parser::new_from_source(&file_name, source))?;
+  let output = MemoryOutput::new();
+  AstXMLGenerator::new_with_output(output.target()).run(&class);
+  Ok(output.contents())
+}
+
+/// Tokens of the jack class `source` as xml.
+pub fn tokenize_source(name: &str, source: &str) -> Result<String, Vec<CompileError>> {
+  let (_, file_name) = source_names(name);
+  let output = MemoryOutput::new();
+  let parser = Parser::new_from_source(&file_name, source);
+  let errors = TokenXMLGenerator::new_with_output(output.target(), parser).run();
+  if !errors.is_empty() {
+    return Err(errors);
+  }
+  Ok(output.contents())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn tokenize_reports_lexer_errors() {
+    let xml = tokenize_source("Main", "let x = 1;").unwrap();
+    assert!(xml.contains("<integerConstant> 1 </integerConstant>"));
+
+    let errors = tokenize_source("Main", "let s = \"a;\n/* b").unwrap_err();
+    let errors: Vec<_> = errors.iter().map(|e| e.to_string()).collect();
+    assert_eq!(
+      errors,
+      vec![
+        "Main.jack:1:9: Unterminated string",
+        "Main.jack:2:1: Unterminated comment",
+      ]
+    );
+  }
+
+  #[test]
+  fn source_map_follows_vm_lines() {
+    let source = "class Main {
+  function int main() {
+    var int x;
+    let x = 1;
+    return x + 2;
+  }
+}";
+    let compile = |source_comments| {
+      let options = CompileOptions {
+        source_comments,
+        ..CompileOptions::default()
+      };
+      let parser = Parser::new_from_source("Main.jack", source);
+      let class = compile_project(vec![parser], &options).remove(0);
+      (class.result.unwrap(), class.source_map)
+    };
+
+    let (_, source_map) = compile(false);
+    assert_eq!(
+      source_map.to_text(),
+      "1 Main.jack:2 Main.main
+2 Main.jack:4 Main.main
+3 Main.jack:4 Main.main
+4 Main.jack:5 Main.main
+5 Main.jack:5 Main.main
+6 Main.jack:5 Main.main
+7 Main.jack:5 Main.main
+"
+    );
+
+    // Comment lines are skipped, every command keeps its source line.
+    let (vm_code, with_comments) = compile(true);
+    let vm_lines: Vec<&str> = vm_code.lines().collect();
+    assert!(vm_lines.contains(&"// Main.jack:4"));
+    assert_eq!(with_comments.entries().len(), source_map.entries().len());
+    for ((vm_line, position), (_, expected)) in
+      with_comments.entries().iter().zip(source_map.entries())
+    {
+      assert_eq!(position, expected);
+      assert!(!vm_lines[vm_line - 1].starts_with("//"));
+    }
+  }
+}
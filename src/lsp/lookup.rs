@@ -0,0 +1,182 @@
+use crate::ast::visit::{self, Visitor};
+use crate::ast::*;
+use crate::compiler::Compiler;
+use crate::parser::jack::Parser;
+
+/// Syntax tree of `text`, as far as it can be read. Declarations are kept
+/// in sources with syntax errors, e.g. unsaved buffers.
+pub fn parse(path: &str, text: &str) -> Option<Class> {
+  Compiler::new(Parser::new_from_source(path, text)).parse().0
+}
+
+/// Declaration as written, e.g. `method int size(int a)`.
+pub fn signature(subroutine: &SubroutineDec) -> String {
+  let parameters: Vec<String> = subroutine
+    .parameters
+    .iter()
+    .map(|parameter| format!("{} {}", parameter.var_type.name, parameter.name.name))
+    .collect();
+  format!(
+    "{} {} {}({})",
+    subroutine.kind,
+    subroutine.return_type.name,
+    subroutine.name.name,
+    parameters.join(", ")
+  )
+}
+
+pub fn subroutine<'a>(class: &'a Class, name: &str) -> Option<&'a SubroutineDec> {
+  class.subroutines.iter().find(|s| s.name.name == name)
+}
+
+/// Subroutine whose declaration holds the position.
+pub fn subroutine_at(class: &Class, line: usize, column: usize) -> Option<&SubroutineDec> {
+  class
+    .subroutines
+    .iter()
+    .find(|s| s.span.contains(line, column))
+}
+
+/// A declared static, field, argument or local.
+pub struct Variable<'a> {
+  pub kind: VarScope,
+  pub var_type: &'a Ident,
+  pub name: &'a Ident,
+}
+
+// The first declaration of `name` in `var_decs`, like in the compiler.
+fn declared<'a>(var_decs: &'a [VarDec], name: &str) -> Option<Variable<'a>> {
+  var_decs.iter().find_map(|var_dec| {
+    let ident = var_dec.names.iter().find(|ident| ident.name == name)?;
+    Some(Variable {
+      kind: var_dec.kind,
+      var_type: &var_dec.var_type,
+      name: ident,
+    })
+  })
+}
+
+/// Variable visible at the position, locals shadow class variables.
+pub fn variable_at<'a>(
+  class: &'a Class,
+  name: &str,
+  line: usize,
+  column: usize,
+) -> Option<Variable<'a>> {
+  let subroutine = subroutine_at(class, line, column);
+  if let Some(subroutine) = subroutine {
+    let parameter = subroutine.parameters.iter().find(|p| p.name.name == name);
+    if let Some(parameter) = parameter {
+      return Some(Variable {
+        kind: VarScope::Argument,
+        var_type: &parameter.var_type,
+        name: &parameter.name,
+      });
+    }
+    if let Some(var) = declared(&subroutine.locals, name) {
+      return Some(var);
+    }
+  }
+  let var = declared(&class.variables, name)?;
+  // Functions have no object to read fields from.
+  let in_function = subroutine.is_some_and(|s| s.kind == SubroutineType::Function);
+  if var.kind == VarScope::Field && in_function {
+    return None;
+  }
+  Some(var)
+}
+
+/// The name at a position, declarations included.
+pub enum Reference {
+  // A variable, or a class when no variable of the name is visible.
+  Name(Ident),
+  // The subroutine of `receiver.name(...)`, or of `name(...)` in the class.
+  Subroutine {
+    receiver: Option<Ident>,
+    name: Ident,
+  },
+}
+
+pub fn reference_at(class: &Class, line: usize, column: usize) -> Option<Reference> {
+  let mut finder = ReferenceFinder {
+    line,
+    column,
+    found: None,
+  };
+  finder.visit_class(class);
+  finder.found
+}
+
+struct ReferenceFinder {
+  line: usize,
+  column: usize,
+  found: Option<Reference>,
+}
+
+impl ReferenceFinder {
+  fn name(&mut self, ident: &Ident) {
+    if ident.span.contains(self.line, self.column) {
+      self.found = Some(Reference::Name(ident.clone()));
+    }
+  }
+
+  fn subroutine(&mut self, receiver: Option<&Ident>, name: &Ident) {
+    if name.span.contains(self.line, self.column) {
+      self.found = Some(Reference::Subroutine {
+        receiver: receiver.cloned(),
+        name: name.clone(),
+      });
+    }
+  }
+}
+
+impl Visitor for ReferenceFinder {
+  fn visit_class(&mut self, class: &Class) {
+    self.name(&class.name);
+    visit::walk_class(self, class);
+  }
+
+  fn visit_var_dec(&mut self, var_dec: &VarDec) {
+    self.name(&var_dec.var_type);
+    for name in &var_dec.names {
+      self.name(name);
+    }
+  }
+
+  fn visit_subroutine(&mut self, subroutine: &SubroutineDec) {
+    if !subroutine.span.contains(self.line, self.column) {
+      return;
+    }
+    self.name(&subroutine.return_type);
+    self.subroutine(None, &subroutine.name);
+    for parameter in &subroutine.parameters {
+      self.name(&parameter.var_type);
+      self.name(&parameter.name);
+    }
+    visit::walk_subroutine(self, subroutine);
+  }
+
+  fn visit_statement(&mut self, statement: &Statement) {
+    if let Statement::Let { target, .. } = statement {
+      self.name(target);
+    }
+    visit::walk_statement(self, statement);
+  }
+
+  fn visit_call(&mut self, call: &Call) {
+    if let Some(receiver) = &call.receiver {
+      self.name(receiver);
+    }
+    self.subroutine(call.receiver.as_ref(), &call.name);
+    visit::walk_call(self, call);
+  }
+
+  fn visit_expr(&mut self, expr: &Expr) {
+    match expr {
+      Expr::Var(ident) => self.name(ident),
+      Expr::Index { array, .. } => self.name(array),
+      _ => (),
+    }
+    visit::walk_expr(self, expr);
+  }
+}
@@ -0,0 +1,6 @@
+//! Language server for Jack, served by the `jack-lsp` binary.
+//! Documents are analysed from their unsaved text, together with the jack
+//! files of their directory.
+
+pub mod lookup;
+pub mod server;
@@ -0,0 +1,692 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+use std::sync::OnceLock;
+
+use serde_json::{json, Value};
+
+use crate::ast::{Class, SubroutineDec, SubroutineType, VarScope};
+use crate::diagnostic::Diagnostic;
+use crate::error::CompileError;
+use crate::lsp::lookup::{self, Reference, Variable};
+use crate::os::{is_os_internal, OS_CLASSES};
+use crate::parser::jack::Parser;
+use crate::token::{Lexer, Span, Token};
+use crate::{compile_project, CompileOptions};
+
+// LSP symbol and completion item kinds.
+const SYMBOL_CLASS: u32 = 5;
+const SYMBOL_METHOD: u32 = 6;
+const SYMBOL_FIELD: u32 = 8;
+const SYMBOL_CONSTRUCTOR: u32 = 9;
+const SYMBOL_FUNCTION: u32 = 12;
+const SYMBOL_VARIABLE: u32 = 13;
+const COMPLETION_METHOD: u32 = 2;
+const COMPLETION_FUNCTION: u32 = 3;
+const COMPLETION_CONSTRUCTOR: u32 = 4;
+
+const METHOD_NOT_FOUND: i64 = -32601;
+
+/// Read one JSON-RPC message framed by a `Content-Length` header, `None` at
+/// the end of the input.
+pub fn read_message<R: BufRead>(reader: &mut R) -> io::Result<Option<Value>> {
+  let mut length = None;
+  loop {
+    let mut line = String::new();
+    if reader.read_line(&mut line)? == 0 {
+      return Ok(None);
+    }
+    let line = line.trim_end();
+    if line.is_empty() {
+      break;
+    }
+    if let Some(value) = line.strip_prefix("Content-Length:") {
+      length = value.trim().parse::<usize>().ok();
+    }
+  }
+  let length = length.ok_or_else(|| invalid_data("missing Content-Length header"))?;
+  let mut body = vec![0; length];
+  reader.read_exact(&mut body)?;
+  serde_json::from_slice(&body)
+    .map(Some)
+    .map_err(|e| invalid_data(&e.to_string()))
+}
+
+pub fn write_message<W: Write>(writer: &mut W, message: &Value) -> io::Result<()> {
+  let body = message.to_string();
+  write!(writer, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+  writer.flush()
+}
+
+fn invalid_data(message: &str) -> io::Error {
+  io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+fn uri_to_path(uri: &str) -> String {
+  let path = uri.strip_prefix("file://").unwrap_or(uri);
+  let bytes = path.as_bytes();
+  let mut decoded = vec![];
+  let mut i = 0;
+  while i < bytes.len() {
+    let hex = path.get(i + 1..i + 3).filter(|_| bytes[i] == b'%');
+    match hex.and_then(|hex| u8::from_str_radix(hex, 16).ok()) {
+      Some(byte) => {
+        decoded.push(byte);
+        i += 3;
+      }
+      None => {
+        decoded.push(bytes[i]);
+        i += 1;
+      }
+    }
+  }
+  String::from_utf8_lossy(&decoded).to_string()
+}
+
+fn path_to_uri(path: &str) -> String {
+  format!("file://{}", path.replace('%', "%25").replace(' ', "%20"))
+}
+
+fn parent_dir(path: &str) -> &str {
+  path.rsplit_once('/').map(|(dir, _)| dir).unwrap_or(".")
+}
+
+// LSP positions are 0-based, spans and locations start at 1. A default span,
+// whose positions are 0, maps to the start of the document.
+fn range(span: Span) -> Value {
+  let index = |position: usize| position.saturating_sub(1);
+  json!({
+    "start": { "line": index(span.start_line), "character": index(span.start_column) },
+    "end": { "line": index(span.end_line), "character": index(span.end_column) },
+  })
+}
+
+fn position(params: &Value) -> Option<(usize, usize)> {
+  let position = &params["position"];
+  let line = position["line"].as_u64()? as usize;
+  let character = position["character"].as_u64()? as usize;
+  Some((line + 1, character + 1))
+}
+
+fn notification(method: &str, params: Value) -> Value {
+  json!({ "jsonrpc": "2.0", "method": method, "params": params })
+}
+
+fn scope_name(kind: VarScope) -> &'static str {
+  match kind {
+    VarScope::Static => "static",
+    VarScope::Field => "field",
+    VarScope::Argument => "argument",
+    VarScope::Variable => "var",
+  }
+}
+
+fn variable_hover(var: &Variable) -> String {
+  format!(
+    "```jack\n({}) {} {}\n```",
+    scope_name(var.kind),
+    var.var_type.name,
+    var.name.name
+  )
+}
+
+fn subroutine_hover(class: &Class, subroutine: &SubroutineDec) -> String {
+  format!(
+    "```jack\n{}\n```\nin class {}",
+    lookup::signature(subroutine),
+    class.name.name
+  )
+}
+
+/// A class of the program of a document, `path` is none for the bundled OS.
+struct ProjectClass {
+  path: Option<String>,
+  class: Cow<'static, Class>,
+}
+
+// The bundled OS classes, parsed once.
+fn os_classes() -> &'static [Class] {
+  static OS_CLASS_ASTS: OnceLock<Vec<Class>> = OnceLock::new();
+  OS_CLASS_ASTS.get_or_init(|| {
+    OS_CLASSES
+      .iter()
+      .filter_map(|(class_name, source)| lookup::parse(&format!("{}.jack", class_name), source))
+      .collect()
+  })
+}
+
+/// Declaration of the identifier under the cursor.
+struct Definition {
+  path: Option<String>,
+  span: Span,
+  hover: String,
+}
+
+/// Language server state, the text of every open document.
+#[derive(Default)]
+pub struct Server {
+  documents: HashMap<String, String>,
+  shutdown: bool,
+}
+
+impl Server {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Whether a shutdown request was received, `exit` succeeds only after it.
+  pub fn is_shutdown(&self) -> bool {
+    self.shutdown
+  }
+
+  /// Handle a request or notification, returns the messages to send back.
+  pub fn handle(&mut self, message: &Value) -> Vec<Value> {
+    let method = match message["method"].as_str() {
+      Some(method) => method,
+      // Responses to requests of the server, which sends none.
+      None => return vec![],
+    };
+    let params = &message["params"];
+    let id = match message.get("id") {
+      Some(id) => id.clone(),
+      None => return self.handle_notification(method, params),
+    };
+    let result = match method {
+      "initialize" => Ok(capabilities()),
+      "shutdown" => {
+        self.shutdown = true;
+        Ok(Value::Null)
+      }
+      "textDocument/definition" => Ok(self.definition(params)),
+      "textDocument/hover" => Ok(self.hover(params)),
+      "textDocument/documentSymbol" => Ok(self.document_symbols(params)),
+      "textDocument/completion" => Ok(self.completion(params)),
+      _ => Err(format!("unsupported method {}", method)),
+    };
+    let response = match result {
+      Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+      Err(message) => json!({
+        "jsonrpc": "2.0",
+        "id": id,
+        "error": { "code": METHOD_NOT_FOUND, "message": message },
+      }),
+    };
+    vec![response]
+  }
+
+  fn handle_notification(&mut self, method: &str, params: &Value) -> Vec<Value> {
+    let uri = match params["textDocument"]["uri"].as_str() {
+      Some(uri) => uri.to_string(),
+      None => return vec![],
+    };
+    match method {
+      "textDocument/didOpen" => {
+        let text = params["textDocument"]["text"].as_str().unwrap_or_default();
+        self.documents.insert(uri.clone(), text.to_string());
+        self.diagnostics(&uri)
+      }
+      "textDocument/didChange" => {
+        // Full document sync, the last change holds the whole text.
+        let changes = params["contentChanges"].as_array();
+        if let Some(text) = changes.and_then(|c| c.last()?["text"].as_str()) {
+          self.documents.insert(uri.clone(), text.to_string());
+        }
+        self.diagnostics(&uri)
+      }
+      "textDocument/didClose" => {
+        self.documents.remove(&uri);
+        let params = json!({ "uri": uri, "diagnostics": [] });
+        vec![notification("textDocument/publishDiagnostics", params)]
+      }
+      _ => vec![],
+    }
+  }
+
+  // (path, text) of the jack files next to `path`, open documents replace
+  // the saved files.
+  fn project_sources(&self, path: &str) -> Vec<(String, String)> {
+    let dir = parent_dir(path);
+    let mut sources = HashMap::new();
+    if let Ok(entries) = std::fs::read_dir(dir) {
+      for entry in entries.flatten() {
+        let file = format!("{}/{}", dir, entry.file_name().to_string_lossy());
+        if file.ends_with(".jack") {
+          if let Ok(text) = std::fs::read_to_string(&file) {
+            sources.insert(file, text);
+          }
+        }
+      }
+    }
+    for (uri, text) in &self.documents {
+      let file = uri_to_path(uri);
+      if parent_dir(&file) == dir {
+        sources.insert(file, text.clone());
+      }
+    }
+    let mut sources: Vec<_> = sources.into_iter().collect();
+    sources.sort();
+    sources
+  }
+
+  // Classes of the program, with the OS classes it does not define.
+  fn project_classes(&self, path: &str) -> Vec<ProjectClass> {
+    let mut classes: Vec<ProjectClass> = self
+      .project_sources(path)
+      .into_iter()
+      .filter_map(|(path, text)| {
+        let class = lookup::parse(&path, &text)?;
+        Some(ProjectClass {
+          path: Some(path),
+          class: Cow::Owned(class),
+        })
+      })
+      .collect();
+    for os_class in os_classes() {
+      if classes
+        .iter()
+        .any(|c| c.class.name.name == os_class.name.name)
+      {
+        continue;
+      }
+      classes.push(ProjectClass {
+        path: None,
+        class: Cow::Borrowed(os_class),
+      });
+    }
+    classes
+  }
+
+  /// Compile the program of the document, returns the diagnostics of each
+  /// of its open documents, empty ones clear fixed errors.
+  fn diagnostics(&self, uri: &str) -> Vec<Value> {
+    let path = uri_to_path(uri);
+    let sources = self.project_sources(&path);
+    let parsers = sources
+      .iter()
+      .map(|(path, text)| Parser::new_from_source(path, text))
+      .collect();
+    let errors: Vec<CompileError> = compile_project(parsers, &CompileOptions::default())
+      .into_iter()
+      .filter_map(|class| class.result.err())
+      .flatten()
+      .collect();
+    let mut ret = vec![];
+    for uri in self.documents.keys() {
+      let file = uri_to_path(uri);
+      if !sources.iter().any(|(path, _)| *path == file) {
+        continue;
+      }
+      let diagnostics: Vec<Value> = errors
+        .iter()
+        .filter(|e| e.location().file == file)
+        .map(diagnostic)
+        .collect();
+      let params = json!({ "uri": uri, "diagnostics": diagnostics });
+      ret.push(notification("textDocument/publishDiagnostics", params));
+    }
+    ret
+  }
+
+  // Declaration of the identifier at the position of a request.
+  fn resolve(&self, params: &Value) -> Option<Definition> {
+    let uri = params["textDocument"]["uri"].as_str()?;
+    let text = self.documents.get(uri)?;
+    let (line, column) = position(params)?;
+    let path = uri_to_path(uri);
+    let current = lookup::parse(&path, text)?;
+    let classes = self.project_classes(&path);
+    let find_class = |name: &str| classes.iter().find(|c| c.class.name.name == name);
+    let name = match lookup::reference_at(&current, line, column)? {
+      // X.name
+      Reference::Subroutine {
+        receiver: Some(receiver),
+        name,
+      } => {
+        let class_name = match lookup::variable_at(&current, &receiver.name, line, column) {
+          Some(var) => var.var_type.name.as_str(),
+          None => receiver.name.as_str(),
+        };
+        let class = find_class(class_name)?;
+        let subroutine = lookup::subroutine(&class.class, &name.name)?;
+        return Some(Definition {
+          path: class.path.clone(),
+          span: subroutine.name.span,
+          hover: subroutine_hover(&class.class, subroutine),
+        });
+      }
+      Reference::Subroutine { name, .. } => {
+        let subroutine = lookup::subroutine(&current, &name.name)?;
+        return Some(Definition {
+          path: Some(path),
+          span: subroutine.name.span,
+          hover: subroutine_hover(&current, subroutine),
+        });
+      }
+      Reference::Name(name) => name,
+    };
+    if let Some(var) = lookup::variable_at(&current, &name.name, line, column) {
+      return Some(Definition {
+        path: Some(path),
+        span: var.name.span,
+        hover: variable_hover(&var),
+      });
+    }
+    let class = find_class(&name.name)?;
+    Some(Definition {
+      path: class.path.clone(),
+      span: class.class.name.span,
+      hover: format!("```jack\nclass {}\n```", class.class.name.name),
+    })
+  }
+
+  fn definition(&self, params: &Value) -> Value {
+    match self.resolve(params) {
+      Some(Definition {
+        path: Some(path),
+        span,
+        ..
+      }) => json!({ "uri": path_to_uri(&path), "range": range(span) }),
+      _ => Value::Null,
+    }
+  }
+
+  fn hover(&self, params: &Value) -> Value {
+    match self.resolve(params) {
+      Some(definition) => json!({
+        "contents": { "kind": "markdown", "value": definition.hover },
+      }),
+      None => Value::Null,
+    }
+  }
+
+  fn document_symbols(&self, params: &Value) -> Value {
+    let uri = params["textDocument"]["uri"].as_str();
+    let text = uri.and_then(|uri| Some((uri_to_path(uri), self.documents.get(uri)?)));
+    let class = match text.and_then(|(path, text)| lookup::parse(&path, text)) {
+      Some(class) => class,
+      None => return json!([]),
+    };
+    let mut children = vec![];
+    for var_dec in &class.variables {
+      let kind = match var_dec.kind {
+        VarScope::Field => SYMBOL_FIELD,
+        _ => SYMBOL_VARIABLE,
+      };
+      for name in &var_dec.names {
+        children.push(json!({
+          "name": name.name,
+          "detail": format!("{} {}", scope_name(var_dec.kind), var_dec.var_type.name),
+          "kind": kind,
+          "range": range(name.span),
+          "selectionRange": range(name.span),
+        }));
+      }
+    }
+    for subroutine in &class.subroutines {
+      let kind = match subroutine.kind {
+        SubroutineType::Constructor => SYMBOL_CONSTRUCTOR,
+        SubroutineType::Function => SYMBOL_FUNCTION,
+        SubroutineType::Method => SYMBOL_METHOD,
+      };
+      children.push(json!({
+        "name": subroutine.name.name,
+        "detail": lookup::signature(subroutine),
+        "kind": kind,
+        "range": range(subroutine.span),
+        "selectionRange": range(subroutine.name.span),
+      }));
+    }
+    json!([{
+      "name": class.name.name,
+      "kind": SYMBOL_CLASS,
+      "range": range(class.span),
+      "selectionRange": range(class.name.span),
+      "children": children,
+    }])
+  }
+
+  /// Members of `X.` where X is a class or a variable: the functions and
+  /// constructors of a class, the methods of an object.
+  fn completion(&self, params: &Value) -> Value {
+    let items = self.completion_items(params).unwrap_or_default();
+    json!({ "isIncomplete": false, "items": items })
+  }
+
+  fn completion_items(&self, params: &Value) -> Option<Vec<Value>> {
+    let uri = params["textDocument"]["uri"].as_str()?;
+    let text = self.documents.get(uri)?;
+    let (line, column) = position(params)?;
+    // The statement being typed is not in the syntax tree yet, the receiver
+    // is read from the tokens before the cursor, the last may be a partly
+    // typed name.
+    let mut tokens: Vec<Token> = Lexer::new(text.clone())
+      .filter_map(Result::ok)
+      .take_while(|(_, span)| (span.end_line, span.end_column) <= (line, column))
+      .map(|(token, _)| token)
+      .collect();
+    if matches!(tokens.last(), Some(Token::Identifier(_))) {
+      tokens.pop();
+    }
+    if tokens.pop()? != Token::Symbol('.') {
+      return None;
+    }
+    let receiver = match tokens.pop()? {
+      Token::Identifier(receiver) => receiver,
+      _ => return None,
+    };
+    let path = uri_to_path(uri);
+    let current = lookup::parse(&path, text)?;
+    let (class_name, with_object) = match lookup::variable_at(&current, &receiver, line, column) {
+      Some(var) => (var.var_type.name.clone(), true),
+      None => (receiver, false),
+    };
+    let classes = self.project_classes(&path);
+    let class = classes.iter().find(|c| c.class.name.name == class_name)?;
+    let items = class
+      .class
+      .subroutines
+      .iter()
+      .filter(|s| (s.kind == SubroutineType::Method) == with_object)
+      // Helpers of the bundled OS are hidden from other classes.
+      .filter(|s| class.path.is_some() || !is_os_internal(&class_name, &s.name.name))
+      .map(|s| {
+        let kind = match s.kind {
+          SubroutineType::Constructor => COMPLETION_CONSTRUCTOR,
+          SubroutineType::Function => COMPLETION_FUNCTION,
+          SubroutineType::Method => COMPLETION_METHOD,
+        };
+        json!({ "label": s.name.name, "kind": kind, "detail": lookup::signature(s) })
+      })
+      .collect();
+    Some(items)
+  }
+}
+
+fn capabilities() -> Value {
+  json!({
+    "capabilities": {
+      // Full text on every change.
+      "textDocumentSync": 1,
+      "definitionProvider": true,
+      "hoverProvider": true,
+      "documentSymbolProvider": true,
+      "completionProvider": { "triggerCharacters": ["."] },
+    },
+    "serverInfo": { "name": "jack-lsp", "version": env!("CARGO_PKG_VERSION") },
+  })
+}
+
+fn diagnostic(error: &CompileError) -> Value {
+  let diagnostic = Diagnostic::from(error);
+  let mut message = diagnostic.message.clone();
+  for note in &diagnostic.notes {
+    message += &format!("\nnote: {}", note);
+  }
+  json!({
+    "range": range(diagnostic.location.span),
+    // Error
+    "severity": 1,
+    "code": diagnostic.code,
+    "source": "jack",
+    "message": message,
+  })
+}
+
+/// Serve the protocol on stdin and stdout, returns the exit code.
+pub fn run_stdio() -> i32 {
+  let stdin = io::stdin();
+  let mut reader = stdin.lock();
+  let stdout = io::stdout();
+  let mut writer = stdout.lock();
+  let mut server = Server::new();
+  loop {
+    let message = match read_message(&mut reader) {
+      Ok(Some(message)) => message,
+      Ok(None) => return 1,
+      Err(e) => {
+        eprintln!("jack-lsp: {}", e);
+        return 1;
+      }
+    };
+    if message["method"] == "exit" {
+      return if server.is_shutdown() { 0 } else { 1 };
+    }
+    for reply in server.handle(&message) {
+      if let Err(e) = write_message(&mut writer, &reply) {
+        eprintln!("jack-lsp: {}", e);
+        return 1;
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  // Documents of a directory missing on disk, only the open ones count.
+  const MAIN: &str = "file:///missing/Main.jack";
+  const SQUARE: &str = "file:///missing/Square.jack";
+
+  fn open(server: &mut Server, uri: &str, text: &str) -> Vec<Value> {
+    server.handle(&json!({
+      "jsonrpc": "2.0",
+      "method": "textDocument/didOpen",
+      "params": { "textDocument": { "uri": uri, "text": text } },
+    }))
+  }
+
+  // Result of a request at the 0-based `line` and `character` of Main.
+  fn request(server: &mut Server, method: &str, line: u32, character: u32) -> Value {
+    let mut replies = server.handle(&json!({
+      "jsonrpc": "2.0",
+      "id": 1,
+      "method": method,
+      "params": {
+        "textDocument": { "uri": MAIN },
+        "position": { "line": line, "character": character },
+      },
+    }));
+    replies.remove(0)["result"].take()
+  }
+
+  fn server() -> Server {
+    let mut server = Server::new();
+    open(
+      &mut server,
+      SQUARE,
+      "class Square {
+  constructor Square new() { return this; }
+  method void draw(int size) { return; }
+}",
+    );
+    open(
+      &mut server,
+      MAIN,
+      "class Main {
+  function void main() {
+    var Square square;
+    let square = Square.new();
+    do square.draw(1);
+    do Math.
+  }
+}",
+    );
+    server
+  }
+
+  #[test]
+  fn open_publishes_diagnostics() {
+    let mut server = Server::new();
+    let replies = open(
+      &mut server,
+      MAIN,
+      "class Main {\n  function void main() { return 1 }\n}",
+    );
+    assert_eq!(replies.len(), 1);
+    let params = &replies[0]["params"];
+    assert_eq!(params["uri"], MAIN);
+    assert_eq!(params["diagnostics"][0]["code"], "E0001");
+    assert_eq!(
+      params["diagnostics"][0]["range"]["start"],
+      json!({ "line": 1, "character": 34 })
+    );
+  }
+
+  #[test]
+  fn definition_across_documents() {
+    let mut server = server();
+    // square in `do square.draw(1)`
+    assert_eq!(
+      request(&mut server, "textDocument/definition", 4, 7),
+      json!({ "uri": MAIN, "range": range(Span::new(3, 16, 3, 22)) })
+    );
+    // draw, a method of Square
+    assert_eq!(
+      request(&mut server, "textDocument/definition", 4, 15),
+      json!({ "uri": SQUARE, "range": range(Span::new(3, 15, 3, 19)) })
+    );
+    // The bundled OS has no document.
+    assert_eq!(
+      request(&mut server, "textDocument/definition", 5, 7),
+      Value::Null
+    );
+  }
+
+  #[test]
+  fn hover_shows_declarations() {
+    let mut server = server();
+    let hover = |server: &mut Server, line, character| {
+      let result = request(server, "textDocument/hover", line, character);
+      result["contents"]["value"].as_str().map(str::to_string)
+    };
+    assert_eq!(
+      hover(&mut server, 3, 9).unwrap(),
+      "```jack\n(var) Square square\n```"
+    );
+    assert_eq!(
+      hover(&mut server, 3, 25).unwrap(),
+      "```jack\nconstructor Square new()\n```\nin class Square"
+    );
+    assert_eq!(hover(&mut server, 0, 0), None);
+  }
+
+  #[test]
+  fn completion_of_members() {
+    let mut server = server();
+    let labels = |result: Value| -> Vec<String> {
+      let items = result["items"].as_array().unwrap().clone();
+      items
+        .iter()
+        .map(|item| item["label"].as_str().unwrap().to_string())
+        .collect()
+    };
+    // `do Math.` lists the functions of the OS API only.
+    let math = labels(request(&mut server, "textDocument/completion", 5, 12));
+    assert!(math.contains(&"multiply".to_string()));
+    assert!(!math.contains(&"bit".to_string()));
+    // `square.` lists the methods of Square.
+    let square = labels(request(&mut server, "textDocument/completion", 4, 14));
+    assert_eq!(square, vec!["draw"]);
+  }
+}
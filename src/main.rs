@@ -1,145 +1,796 @@
-use std::cell::RefCell;
-use std::rc::Rc;
-
-use clap::Parser;
+use clap::{Args, Parser, Subcommand};
 use log::{debug, error};
 
-use jack_compiler::code_writer::CodeWriter;
-use jack_compiler::common::{new_output, panic_writer, OutputTarget};
-use jack_compiler::compiler::Compiler;
+use jack_compiler::asm::assembler::Assembler;
+use jack_compiler::diagnostic::{Diagnostic, MessageFormat};
+use jack_compiler::emulator::cpu::Cpu;
+use jack_compiler::emulator::native_os::NativeOs;
+use jack_compiler::emulator::vm::VmEmulator;
+use jack_compiler::emulator::{parse_addresses, RunResult};
+use jack_compiler::error::CompileError;
 use jack_compiler::logger;
-use jack_compiler::operation::tree::OperationTree;
-use jack_compiler::vm::vm_translator::AssembleCodeGenerator;
-use jack_compiler::xml::token_xml_generator::TokenXMLGenerator;
-
-fn tokenize_one_file(file: &str, token_xml: bool, vm_xml: bool) {
-  let parser = jack_compiler::parser::jack::Parser::new(file);
-  if token_xml {
-    let out_file = String::from(file.strip_suffix(".jack").unwrap()) + "T.xml";
-    let generator = TokenXMLGenerator::new(out_file.as_str(), parser);
-    generator.run();
-  } else if vm_xml {
-    let out_file = String::from(file.strip_suffix(".jack").unwrap()) + ".xml";
-    let compiler = Compiler::new(out_file.as_str(), parser);
-    if let Some(r) = compiler.run() {
-      error!("compile failed {}", r);
+use jack_compiler::os::OS_CLASSES;
+use jack_compiler::semantic::Strictness;
+use jack_compiler::sources::{check_unique_names, find_sources, read_file_list, SourceFilter};
+use jack_compiler::vm::commands::{Command, CommandType};
+use jack_compiler::vm::vm_translator::{AssembleCodeGenerator, Bootstrap};
+use jack_compiler::{
+  class_name_of, compile_project, source_to_xml, tokenize_source, CompileOptions, CompiledClass,
+};
+
+// Content of a source file, the linked OS classes are not on disk.
+fn source_text(file: &str) -> Option<String> {
+  if let Ok(content) = std::fs::read_to_string(file) {
+    return Some(content);
+  }
+  let class_name = class_name_of(file);
+  OS_CLASSES
+    .iter()
+    .find(|(name, _)| *name == class_name)
+    .map(|(_, source)| source.to_string())
+}
+
+fn report_errors(errors: &[CompileError], message_format: MessageFormat) {
+  for e in errors {
+    let source = source_text(&e.location().file);
+    let diagnostic = Diagnostic::from(e);
+    match message_format {
+      MessageFormat::Human => eprintln!("{}", diagnostic.render(source.as_deref())),
+      MessageFormat::Json => println!("{}", diagnostic.to_json()),
     }
-  } else {
-    let base_file_name = file.strip_suffix(".jack").unwrap();
-    let vm_file_name = format!("{}.vm", base_file_name);
-    let class_name = String::from(base_file_name.rsplit("/").next().unwrap());
-    let op_tree = Rc::new(RefCell::new(OperationTree::new(class_name)));
-    let compiler = Compiler::new_with_generator(op_tree.clone(), parser);
-    if let Some(r) = compiler.run() {
-      error!("compile failed {}", r);
+  }
+  if message_format == MessageFormat::Human {
+    let plural = if errors.len() == 1 { "" } else { "s" };
+    eprintln!(
+      "error: compile failed with {} error{}",
+      errors.len(),
+      plural
+    );
+  }
+}
+
+// The bundled OS classes that the program does not define itself.
+fn os_parsers(user_classes: &[String]) -> Vec<jack_compiler::parser::jack::Parser> {
+  let mut ret = vec![];
+  for (class_name, source) in OS_CLASSES {
+    if user_classes.iter().any(|c| c == class_name) {
+      continue;
+    }
+    debug!("linking OS class {}", class_name);
+    let source_name = format!("{}.jack", class_name);
+    ret.push(jack_compiler::parser::jack::Parser::new_from_source(
+      &source_name,
+      source,
+    ));
+  }
+  ret
+}
+
+/// Source files of the command line inputs.
+#[derive(Args, Debug)]
+struct SourceArgs {
+  /// Files or directories to take the sources of.
+  inputs: Vec<String>,
+
+  /// Also take the sources of subdirectories of a directory input.
+  #[clap(long)]
+  recursive: bool,
+
+  /// Only take the sources matching one of these globs, relative to the
+  /// directory input, e.g. --include 'lib/*.jack'.
+  #[clap(long, multiple_occurrences = true)]
+  include: Vec<String>,
+
+  /// Skip the sources matching one of these globs.
+  #[clap(long, multiple_occurrences = true)]
+  exclude: Vec<String>,
+
+  /// File listing sources to take, one path relative to the directory of the
+  /// list per line, in addition to the inputs.
+  #[clap(long)]
+  files_from: Option<String>,
+}
+
+impl SourceArgs {
+  // Files ending with `extension` of every input, sorted.
+  fn find(&self, extension: &str) -> Result<Vec<String>, String> {
+    let filter = SourceFilter::new(self.recursive, &self.include, &self.exclude)?;
+    let mut files = vec![];
+    for input in &self.inputs {
+      files.append(&mut find_sources(input, extension, &filter)?);
+    }
+    if let Some(list) = &self.files_from {
+      let root = list.rsplit_once('/').map_or(".", |(dir, _)| dir);
+      files.append(&mut read_file_list(root, list, &filter)?);
+    }
+    files.sort();
+    files.dedup();
+    if files.is_empty() {
+      return Err(format!("no {} files in the inputs", extension));
+    }
+    check_unique_names(&files)?;
+    for file in &files {
+      debug!("reading file {}", file);
+    }
+    Ok(files)
+  }
+
+  // Name of the program built from the inputs: the first input without its
+  // extension, or the name of its directory.
+  fn program_name(&self) -> String {
+    let input = self.inputs.first().map_or(".", |input| input.as_str());
+    let path = std::fs::canonicalize(input).unwrap_or_else(|_| input.into());
+    let name = match path.file_stem() {
+      Some(stem) if !path.is_dir() => stem,
+      _ => path.file_name().unwrap_or_default(),
+    };
+    name.to_string_lossy().to_string()
+  }
+}
+
+/// Where produced files go, those written are listed in the summary.
+#[derive(Args, Debug)]
+struct OutputArgs {
+  /// Directory to write to instead of next to every source.
+  #[clap(short, long)]
+  out_dir: Option<String>,
+
+  /// Print the produced files on stdout instead of writing them.
+  #[clap(long)]
+  stdout: bool,
+}
+
+#[derive(Default)]
+struct Outputs {
+  out_dir: Option<String>,
+  stdout: bool,
+  written: Vec<String>,
+}
+
+impl Outputs {
+  fn new(args: OutputArgs) -> Self {
+    Self {
+      out_dir: args.out_dir,
+      stdout: args.stdout,
+      written: vec![],
+    }
+  }
+
+  // `file_name` in the output directory, next to `source` otherwise.
+  fn path(&self, source: Option<&str>, file_name: &str) -> String {
+    let dir = match &self.out_dir {
+      Some(out_dir) => Some(out_dir.trim_end_matches('/')),
+      None => source.and_then(|source| source.rsplit_once('/').map(|(dir, _)| dir)),
+    };
+    match dir {
+      Some(dir) => format!("{}/{}", dir, file_name),
+      None => file_name.to_string(),
+    }
+  }
+
+  // Returns whether `content` could be written to `path`.
+  fn write(&mut self, path: &str, content: &str) -> bool {
+    if self.stdout {
+      print!("{}", content);
+      return true;
+    }
+    if let Some((dir, _)) = path.rsplit_once('/') {
+      if let Err(e) = std::fs::create_dir_all(dir) {
+        error!("create {}: {}", dir, e);
+        return false;
+      }
+    }
+    match std::fs::write(path, content) {
+      Ok(()) => {
+        self.written.push(path.to_string());
+        true
+      }
+      Err(e) => {
+        error!("write {}: {}", path, e);
+        false
+      }
+    }
+  }
+
+  fn summary(&self) {
+    if self.written.is_empty() {
+      return;
+    }
+    let plural = if self.written.len() == 1 { "" } else { "s" };
+    eprintln!("wrote {} file{}:", self.written.len(), plural);
+    for file in &self.written {
+      eprintln!("  {}", file);
+    }
+  }
+}
+
+// Lines as the content of a file.
+fn lines_text(lines: &[String]) -> String {
+  lines.iter().map(|line| format!("{}\n", line)).collect()
+}
+
+/// Settings of the jack compilation.
+#[derive(Args, Debug)]
+struct CompileArgs {
+  /// Compile the bundled Jack OS classes the program does not define.
+  #[clap(long)]
+  link_os: bool,
+
+  /// Require matching types instead of Jack's usual int/char/boolean/Array mixing.
+  #[clap(long)]
+  strict_types: bool,
+
+  /// Write the Jack source of every vm line to <Class>.vm.map.
+  #[clap(long)]
+  source_map: bool,
+
+  /// Precede the vm commands of every Jack line with a `// Main.jack:14` comment.
+  #[clap(long)]
+  source_comments: bool,
+
+  /// How compile errors are printed: human or json, one object per line.
+  #[clap(long, default_value = "human")]
+  message_format: MessageFormat,
+}
+
+impl CompileArgs {
+  fn options(&self) -> CompileOptions {
+    let strictness = if self.strict_types {
+      Strictness::Strict
     } else {
-      // println!("{}", op_tree.borrow());
-      let code_writer = CodeWriter::new(&vm_file_name[..], op_tree.take());
-      code_writer.generate_vm_code();
+      Strictness::Permissive
+    };
+    CompileOptions {
+      strictness,
+      source_comments: self.source_comments,
     }
   }
 }
 
-fn handle_jack(file: String, token_xml: bool, vm_xml: bool) {
-  if file.ends_with(".jack") {
-    tokenize_one_file(&file, token_xml, vm_xml);
-  } else {
-    match std::fs::read_dir(file) {
-      Ok(dir) => {
-        for path in dir {
-          let path = path.expect("Failed to read file in directory");
-          let path = format!("{}", path.path().display());
-          if path.ends_with(".jack") {
-            debug!("DEBUG: reading file {}", path);
-            tokenize_one_file(path.as_str(), token_xml, vm_xml);
-          }
-        }
+// Compile the classes of `files` as one program, with the OS classes they do
+// not define when `with_os`. Errors are reported, nothing is returned then.
+fn compile_files(
+  files: &[String],
+  with_os: bool,
+  options: &CompileOptions,
+  message_format: MessageFormat,
+) -> Option<Vec<CompiledClass>> {
+  let mut parsers: Vec<_> = files
+    .iter()
+    .map(|path| jack_compiler::parser::jack::Parser::new(path))
+    .collect();
+  if with_os {
+    let user_classes: Vec<_> = files.iter().map(|path| class_name_of(path)).collect();
+    parsers.append(&mut os_parsers(&user_classes));
+  }
+  let classes = compile_project(parsers, options);
+  let errors: Vec<_> = classes
+    .iter()
+    .filter_map(|class| class.result.as_ref().err())
+    .flatten()
+    .cloned()
+    .collect();
+  if !errors.is_empty() {
+    report_errors(&errors, message_format);
+    return None;
+  }
+  Some(classes)
+}
+
+// Returns whether every file was tokenized and written.
+fn handle_tokenize(files: &[String], outputs: &mut Outputs, message_format: MessageFormat) -> bool {
+  let mut success = true;
+  for file in files {
+    let source = match std::fs::read_to_string(file) {
+      Ok(source) => source,
+      Err(e) => {
+        error!("read {}: {}", file, e);
+        success = false;
+        continue;
+      }
+    };
+    match tokenize_source(file, &source) {
+      Ok(xml) => {
+        let out_file = outputs.path(Some(file), &format!("{}T.xml", class_name_of(file)));
+        success &= outputs.write(&out_file, &xml);
       }
+      Err(errors) => {
+        report_errors(&errors, message_format);
+        success = false;
+      }
+    }
+  }
+  success
+}
+
+// Returns whether every file parsed and was written.
+fn handle_parse(files: &[String], outputs: &mut Outputs, message_format: MessageFormat) -> bool {
+  let mut success = true;
+  for file in files {
+    let source = match std::fs::read_to_string(file) {
+      Ok(source) => source,
       Err(e) => {
-        panic!("Read input directory: {}", e);
+        error!("read {}: {}", file, e);
+        success = false;
+        continue;
+      }
+    };
+    match source_to_xml(file, &source) {
+      Ok(xml) => {
+        let out_file = outputs.path(Some(file), &format!("{}.xml", class_name_of(file)));
+        success &= outputs.write(&out_file, &xml);
+      }
+      Err(errors) => {
+        report_errors(&errors, message_format);
+        success = false;
       }
     }
   }
+  success
 }
 
-fn write_commands(output: OutputTarget, cmds: Vec<String>) {
-  for command in cmds {
-    panic_writer(command, output.clone().borrow_mut());
-    panic_writer("\n".to_string(), output.clone().borrow_mut());
+// Write the vm code of a compiled class, with its source map when `source_map`.
+fn write_vm_file(
+  class: CompiledClass,
+  vm_file: &str,
+  source_map: bool,
+  outputs: &mut Outputs,
+) -> bool {
+  let mut success = true;
+  if source_map {
+    success &= outputs.write(&format!("{}.map", vm_file), &class.source_map.to_text());
   }
+  success && outputs.write(vm_file, &class.result.unwrap())
 }
-fn translate_one_file(file: String, out_file: OutputTarget, writer: &mut AssembleCodeGenerator) {
-  debug!("handle file {}", file);
-  let mut parser = jack_compiler::parser::hack::Parser::new(&file);
-  while let Some(cmd) = parser.next() {
-    let translate = writer.get_asm(cmd);
-    write_commands(out_file.clone(), translate);
+
+// Returns whether every class compiled. No vm file is written otherwise.
+fn handle_compile(files: &[String], args: &CompileArgs, outputs: &mut Outputs) -> bool {
+  let classes = match compile_files(files, args.link_os, &args.options(), args.message_format) {
+    Some(classes) => classes,
+    None => return false,
+  };
+  let mut success = true;
+  for class in classes {
+    // Next to the source, linked OS classes go next to the first source.
+    let source = files
+      .iter()
+      .find(|path| class_name_of(path) == class.class_name)
+      .unwrap_or(&files[0]);
+    let vm_file = outputs.path(Some(source), &format!("{}.vm", class.class_name));
+    success &= write_vm_file(class, &vm_file, args.source_map, outputs);
   }
-  writer.finish_one_file();
+  success
 }
 
-fn handle_vm(file: String) {
-  let mut writer = AssembleCodeGenerator::new();
-  if file.ends_with(".vm") {
-    let out_file = String::from(file.strip_suffix(".vm").unwrap()) + ".asm";
-    let output = new_output(&out_file[..]);
-    // write_commands(output.clone(), AssembleCodeGenerator::init_env());
-    translate_one_file(file, output, &mut writer);
-  } else {
-    // Treat file as directory
-    match std::fs::read_dir(file.clone()) {
-      Ok(dir) => {
-        let dirname = file.rsplit('/').next();
-        let out_file = format!("{}/{}.asm", file.clone(), dirname.unwrap());
-        let output = new_output(&out_file[..]);
-        write_commands(output.clone(), AssembleCodeGenerator::init_env());
-        write_commands(output.clone(), AssembleCodeGenerator::bootstrap());
-        for path in dir {
-          let path = path.expect("Failed to read file in directory");
-          let path = format!("{}", path.path().display());
-          if path.ends_with(".vm") {
-            debug!("reading file {}", path);
-            translate_one_file(format!("{}", path), output.clone(), &mut writer);
-          }
-        }
+// Commands of every file, with the file name without extension.
+fn read_vm_files(files: &[String]) -> Vec<(String, Vec<Command>)> {
+  let mut ret = vec![];
+  for path in files {
+    debug!("handle file {}", path);
+    let name = path.rsplit('/').next().unwrap().strip_suffix(".vm").unwrap();
+    let commands = jack_compiler::parser::hack::Parser::new(path).collect();
+    ret.push((name.to_string(), commands));
+  }
+  ret
+}
+
+fn translate_files(files: &[String], bootstrap: Bootstrap, shared_routines: bool) -> Vec<String> {
+  let sources = read_vm_files(files);
+  let with_bootstrap = match bootstrap {
+    Bootstrap::Always => true,
+    Bootstrap::Never => false,
+    Bootstrap::Auto => sources
+      .iter()
+      .flat_map(|(_, commands)| commands)
+      .any(|cmd| {
+        cmd.cmd_type() == CommandType::Function && cmd.arg1().as_deref() == Some("Sys.init")
+      }),
+  };
+  let mut writer = AssembleCodeGenerator::new().with_shared_routines(shared_routines);
+  let mut ret = vec![];
+  if with_bootstrap {
+    ret.append(&mut writer.bootstrap());
+  }
+  for (name, commands) in sources {
+    writer.start_file(&name);
+    for cmd in commands {
+      ret.append(&mut writer.get_asm(cmd));
+    }
+  }
+  ret.append(&mut writer.finish());
+  ret
+}
+
+// Returns whether the translation was written.
+fn handle_translate(
+  files: &[String],
+  out_file: &str,
+  bootstrap: Bootstrap,
+  opt_level: u8,
+  outputs: &mut Outputs,
+) -> bool {
+  let asm = translate_files(files, bootstrap, opt_level > 0);
+  outputs.write(out_file, &lines_text(&asm))
+}
+
+// Hack code of the asm file.
+fn assemble_file(file: &str) -> Result<Vec<String>, String> {
+  let mut instructions = vec![];
+  for instruction in jack_compiler::parser::asm::Parser::new(file) {
+    instructions.push(instruction.map_err(|e| e.to_string())?);
+  }
+  Assembler::new()
+    .assemble(instructions)
+    .map_err(|e| format!("{}: {}", file, e))
+}
+
+fn handle_assemble(files: &[String], outputs: &mut Outputs) -> bool {
+  let mut success = true;
+  for file in files {
+    match assemble_file(file) {
+      Ok(codes) => {
+        let name = file
+          .rsplit('/')
+          .next()
+          .unwrap()
+          .strip_suffix(".asm")
+          .unwrap();
+        let out_file = outputs.path(Some(file), &format!("{}.hack", name));
+        success &= outputs.write(&out_file, &lines_text(&codes));
       }
       Err(e) => {
-        panic!("Read input directory: {}", e);
+        error!("assemble failed {}", e);
+        success = false;
       }
     }
   }
+  success
 }
 
-#[derive(Parser, Debug)]
-#[clap(about, version, author)]
-struct Args {
-  // File or Directory to compile.
-  #[clap(short, long)]
-  path: String,
+// Compile the program, translate it to `<name>.asm` and assemble that to
+// `<name>.hack`, all in the output directory.
+fn handle_build(
+  files: &[String],
+  name: &str,
+  args: &CompileArgs,
+  bootstrap: Bootstrap,
+  opt_level: u8,
+  outputs: &mut Outputs,
+) -> bool {
+  let classes = match compile_files(files, args.link_os, &args.options(), args.message_format) {
+    Some(classes) => classes,
+    None => return false,
+  };
+  let mut vm_files = vec![];
+  for class in classes {
+    let vm_file = outputs.path(None, &format!("{}.vm", class.class_name));
+    if !write_vm_file(class, &vm_file, args.source_map, outputs) {
+      return false;
+    }
+    vm_files.push(vm_file);
+  }
+  vm_files.sort();
+
+  let asm = translate_files(&vm_files, bootstrap, opt_level > 0);
+  let asm_file = outputs.path(None, &format!("{}.asm", name));
+  if !outputs.write(&asm_file, &lines_text(&asm)) {
+    return false;
+  }
+  match Assembler::new().assemble_lines(&asm) {
+    Ok(codes) => {
+      let hack_file = outputs.path(None, &format!("{}.hack", name));
+      outputs.write(&hack_file, &lines_text(&codes))
+    }
+    Err(e) => {
+      error!("assemble failed {}: {}", asm_file, e);
+      false
+    }
+  }
+}
+
+fn report_run(result: RunResult, dump: &[(usize, i16)]) {
+  match result {
+    RunResult::Halted(cycles) => println!("halted after {} cycles", cycles),
+    RunResult::ProgramEnd(cycles) => println!("program ended after {} cycles", cycles),
+    RunResult::CycleLimit(cycles) => println!("stopped at cycle limit {}", cycles),
+  }
+  for (address, value) in dump {
+    println!("RAM[{}] = {}", address, value);
+  }
+}
+
+/// Settings of the emulator.
+#[derive(Args, Debug)]
+struct RunOptions {
+  /// Cycle budget of the run.
+  #[clap(long, default_value = "1000000")]
+  cycles: u64,
 
+  /// RAM addresses to print after the run, e.g. 0,256-260.
+  #[clap(long, default_value = "0")]
+  dump: String,
+
+  /// Also run the asm translation of the vm files and report differing RAM.
   #[clap(long)]
-  debug_token: bool,
+  compare_cpu: bool,
 
+  /// Serve OS calls missing from the vm files with native implementations.
   #[clap(long)]
-  debug_vm: bool,
+  native_os: bool,
 
+  /// Text typed on the keyboard for the native OS.
   #[clap(long)]
-  translate_vm: bool,
+  input_file: Option<String>,
+}
 
-  #[clap(long, default_value = "info")]
-  log_level: String,
+fn run_vm(files: &[String], addresses: &[usize], options: &RunOptions) -> Result<(), String> {
+  let cycles = options.cycles;
+  let mut vm = VmEmulator::new(read_vm_files(files))?;
+  if options.native_os {
+    let input = match &options.input_file {
+      Some(input_file) => {
+        std::fs::read_to_string(input_file).map_err(|e| format!("Read {}: {}", input_file, e))?
+      }
+      None => String::new(),
+    };
+    vm.enable_native_os(NativeOs::new(&input));
+  }
+  vm.bootstrap()?;
+  let result = vm.run(cycles)?;
+  let dump: Vec<_> = addresses.iter().map(|&a| (a, vm.ram(a))).collect();
+  report_run(result, &dump);
+  if let Some(native_os) = vm.native_os() {
+    println!("output:\n{}", native_os.output());
+  }
+  if options.compare_cpu {
+    let mut cpu = Cpu::new();
+    cpu.load_asm(&translate_files(files, Bootstrap::Auto, true))?;
+    let result = cpu.run(cycles);
+    println!("cpu-level translation:");
+    let cpu_dump: Vec<_> = addresses.iter().map(|&a| (a, cpu.ram(a))).collect();
+    report_run(result, &cpu_dump);
+    for ((address, vm_value), (_, cpu_value)) in dump.iter().zip(cpu_dump.iter()) {
+      if vm_value != cpu_value {
+        println!(
+          "RAM[{}] differs: vm = {}, cpu = {}",
+          address, vm_value, cpu_value
+        );
+      }
+    }
+  }
+  Ok(())
 }
 
-fn main() {
-  let args = Args::parse();
-  match logger::setup(&args.log_level[..]) {
-    Err(_) => return,
-    Ok(_) => {}
+fn run_cpu(file: &str, cycles: u64, addresses: &[usize]) -> Result<(), String> {
+  let lines: Vec<String> = match std::fs::read_to_string(file) {
+    Ok(content) => content.lines().map(String::from).collect(),
+    Err(e) => return Err(format!("Read {}: {}", file, e)),
   };
-  let file = args.path;
-  if args.translate_vm {
-    handle_vm(file);
+  let mut cpu = Cpu::new();
+  if file.ends_with(".hack") {
+    cpu.load_hack(&lines)?;
   } else {
-    handle_jack(file, args.debug_token, args.debug_vm);
+    cpu.load_asm(&lines)?;
+  }
+  let result = cpu.run(cycles);
+  let dump: Vec<_> = addresses.iter().map(|&a| (a, cpu.ram(a))).collect();
+  report_run(result, &dump);
+  Ok(())
+}
+
+// Runs a single .hack or .asm file on the cpu, vm files on the vm emulator.
+fn handle_run(sources: &SourceArgs, options: &RunOptions) -> Result<(), String> {
+  let addresses = parse_addresses(&options.dump)?;
+  match sources.inputs.as_slice() {
+    [file] if file.ends_with(".hack") || file.ends_with(".asm") => {
+      run_cpu(file, options.cycles, &addresses)
+    }
+    _ => run_vm(&sources.find(".vm")?, &addresses, options),
+  }
+}
+
+#[derive(Parser, Debug)]
+#[clap(about, version, author)]
+struct Cli {
+  /// Level of the printed log messages: error, warn, info, debug or trace.
+  #[clap(long, default_value = "info", global = true)]
+  log_level: String,
+
+  #[clap(subcommand)]
+  command: Commands,
+}
+
+#[derive(Subcommand, Debug)]
+enum Commands {
+  /// Write the tokens of every jack file to <Class>T.xml.
+  Tokenize {
+    #[clap(flatten)]
+    sources: SourceArgs,
+    #[clap(flatten)]
+    output: OutputArgs,
+    /// How lexer errors are printed: human or json, one object per line.
+    #[clap(long, default_value = "human")]
+    message_format: MessageFormat,
+  },
+  /// Write the parse tree of every jack file to <Class>.xml.
+  Parse {
+    #[clap(flatten)]
+    sources: SourceArgs,
+    #[clap(flatten)]
+    output: OutputArgs,
+    /// How parse errors are printed: human or json, one object per line.
+    #[clap(long, default_value = "human")]
+    message_format: MessageFormat,
+  },
+  /// Compile the jack files as one program to <Class>.vm.
+  Compile {
+    #[clap(flatten)]
+    sources: SourceArgs,
+    #[clap(flatten)]
+    output: OutputArgs,
+    #[clap(flatten)]
+    compile: CompileArgs,
+  },
+  /// Translate the vm files to a single asm file.
+  Translate {
+    #[clap(flatten)]
+    sources: SourceArgs,
+    /// Asm file to write, <name>.asm next to the first input by default.
+    #[clap(short, long)]
+    output: Option<String>,
+    /// Print the asm code on stdout instead of writing it.
+    #[clap(long)]
+    stdout: bool,
+    /// Start the translation with SP=256 and a call to Sys.init: auto when a
+    /// file defines Sys.init, always or never.
+    #[clap(long, default_value = "auto")]
+    bootstrap: Bootstrap,
+    /// Optimization level: 0 writes the code of every call, return and
+    /// comparison where it is used, 1 shares it.
+    #[clap(short = 'O', long, default_value = "1", possible_values = &["0", "1"])]
+    opt_level: u8,
+  },
+  /// Assemble every asm file to <name>.hack.
+  Assemble {
+    #[clap(flatten)]
+    sources: SourceArgs,
+    #[clap(flatten)]
+    output: OutputArgs,
+  },
+  /// Execute vm files, or a single .hack or .asm file, on the emulator.
+  Run {
+    #[clap(flatten)]
+    sources: SourceArgs,
+    #[clap(flatten)]
+    options: RunOptions,
+  },
+  /// Compile the jack program, translate and assemble it into --out-dir.
+  Build {
+    #[clap(flatten)]
+    sources: SourceArgs,
+    /// Directory of the .vm, .asm and .hack files.
+    #[clap(short, long, default_value = "build")]
+    out_dir: String,
+    #[clap(flatten)]
+    compile: CompileArgs,
+    /// Start with SP=256 and a call to Sys.init: auto when a file defines
+    /// Sys.init, always or never.
+    #[clap(long, default_value = "auto")]
+    bootstrap: Bootstrap,
+    /// Optimization level: 0 writes the code of every call, return and
+    /// comparison where it is used, 1 shares it.
+    #[clap(short = 'O', long, default_value = "1", possible_values = &["0", "1"])]
+    opt_level: u8,
+  },
+}
+
+// Runs the command, returns whether it succeeded.
+fn execute(command: Commands, outputs: &mut Outputs) -> Result<bool, String> {
+  Ok(match command {
+    Commands::Tokenize {
+      sources,
+      output,
+      message_format,
+    } => {
+      *outputs = Outputs::new(output);
+      handle_tokenize(&sources.find(".jack")?, outputs, message_format)
+    }
+    Commands::Parse {
+      sources,
+      output,
+      message_format,
+    } => {
+      *outputs = Outputs::new(output);
+      handle_parse(&sources.find(".jack")?, outputs, message_format)
+    }
+    Commands::Compile {
+      sources,
+      output,
+      compile,
+    } => {
+      if compile.source_map && output.stdout {
+        return Err("--source-map writes files, it cannot be used with --stdout".to_string());
+      }
+      *outputs = Outputs::new(output);
+      handle_compile(&sources.find(".jack")?, &compile, outputs)
+    }
+    Commands::Translate {
+      sources,
+      output,
+      stdout,
+      bootstrap,
+      opt_level,
+    } => {
+      let files = sources.find(".vm")?;
+      let out_file = match output {
+        Some(output) => output,
+        None => {
+          // Next to a file input, in a directory input.
+          let first = sources.inputs.first().map_or(".", |input| input.as_str());
+          let dir = if first.ends_with(".vm") {
+            first.rsplit_once('/').map(|(dir, _)| dir.to_string())
+          } else {
+            Some(first.trim_end_matches('/').to_string())
+          };
+          let file_name = format!("{}.asm", sources.program_name());
+          match dir {
+            Some(dir) => format!("{}/{}", dir, file_name),
+            None => file_name,
+          }
+        }
+      };
+      *outputs = Outputs::new(OutputArgs {
+        out_dir: None,
+        stdout,
+      });
+      handle_translate(&files, &out_file, bootstrap, opt_level, outputs)
+    }
+    Commands::Assemble { sources, output } => {
+      *outputs = Outputs::new(output);
+      handle_assemble(&sources.find(".asm")?, outputs)
+    }
+    Commands::Run { sources, options } => {
+      handle_run(&sources, &options)?;
+      true
+    }
+    Commands::Build {
+      sources,
+      out_dir,
+      compile,
+      bootstrap,
+      opt_level,
+    } => {
+      *outputs = Outputs::new(OutputArgs {
+        out_dir: Some(out_dir),
+        stdout: false,
+      });
+      let files = sources.find(".jack")?;
+      handle_build(
+        &files,
+        &sources.program_name(),
+        &compile,
+        bootstrap,
+        opt_level,
+        outputs,
+      )
+    }
+  })
+}
+
+fn main() {
+  let cli = Cli::parse();
+  if logger::setup(&cli.log_level[..]).is_err() {
+    return;
+  }
+  let mut outputs = Outputs::default();
+  let result = execute(cli.command, &mut outputs);
+  outputs.summary();
+  match result {
+    Ok(true) => {}
+    Ok(false) => std::process::exit(1),
+    Err(e) => {
+      error!("{}", e);
+      std::process::exit(1);
+    }
   }
 }
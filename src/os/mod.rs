@@ -0,0 +1,38 @@
+/// Jack sources of the OS standard library, embedded as (class name, source).
+pub static OS_CLASSES: &[(&str, &str)] = &[
+  ("Array", include_str!("Array.jack")),
+  ("Keyboard", include_str!("Keyboard.jack")),
+  ("Math", include_str!("Math.jack")),
+  ("Memory", include_str!("Memory.jack")),
+  ("Output", include_str!("Output.jack")),
+  ("Screen", include_str!("Screen.jack")),
+  ("String", include_str!("String.jack")),
+  ("Sys", include_str!("Sys.jack")),
+];
+
+// Helpers of the OS classes outside the published API, only their own class
+// may call them.
+static OS_INTERNALS: &[(&str, &str)] = &[
+  ("Math", "bit"),
+  ("Math", "dividePositive"),
+  ("Output", "initMap"),
+  ("Output", "create"),
+  ("Output", "getMap"),
+  ("Output", "drawChar"),
+  ("String", "appendDigits"),
+];
+
+pub fn is_os_internal(class_name: &str, subroutine_name: &str) -> bool {
+  OS_INTERNALS.contains(&(class_name, subroutine_name))
+}
+
+pub fn is_os_class(class_name: &str) -> bool {
+  OS_CLASSES.iter().any(|(name, _)| *name == class_name)
+}
+
+pub fn os_class_source(class_name: &str) -> Option<&'static str> {
+  OS_CLASSES
+    .iter()
+    .find(|(name, _)| *name == class_name)
+    .map(|(_, source)| *source)
+}
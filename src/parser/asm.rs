@@ -0,0 +1,37 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::iter::Iterator;
+
+use crate::asm::instruction::Instruction;
+
+pub struct Parser {
+  reader: BufReader<File>,
+  source: String,
+  cur_line: usize,
+}
+
+impl Parser {
+  pub fn new(source: &str) -> Self {
+    let file = File::open(source).unwrap_or_else(|_| panic!("{} file open failed", source));
+    let reader = BufReader::new(file);
+    Self {
+      reader,
+      source: source.to_string(),
+      cur_line: 0,
+    }
+  }
+}
+
+impl Iterator for Parser {
+  type Item = Result<Instruction, String>;
+
+  fn next(&mut self) -> Option<Self::Item> {
+    let mut buf = String::new();
+    let len = self.reader.read_line(&mut buf).expect("read file failed");
+    if len == 0 {
+      return None;
+    }
+    self.cur_line += 1;
+    Some(Instruction::parse(&buf).map_err(|e| format!("{}:{}: {}", self.source, self.cur_line, e)))
+  }
+}
@@ -1,85 +1,75 @@
-use std::fs::File;
-use std::io::{BufRead, BufReader};
+use std::io::Read;
 use std::iter::Iterator;
 
-use crate::token::{Token, TokenDescriptor};
+use crate::error::{CompileError, Location};
+use crate::token::{Lexer, Span, Token, TokenDescriptor};
 
 pub struct Parser {
-  reader: BufReader<File>,
+  lexer: Lexer,
   source: String,
-  end: bool,
-  token_buf: Vec<Token>,
-  token_buf_idx: usize,
-  cur_line: usize,
+  peeked: Option<(Token, Span)>,
   last_token_descriptor: Option<TokenDescriptor>,
+  // Span of the last token taken, peeking does not move it.
+  last_span: Span,
+  // Lexer errors, the offending text is skipped.
+  errors: Vec<CompileError>,
 }
 
 impl Parser {
   pub fn new(source: &str) -> Self {
-    let file = File::open(source).expect(format!("{} file open failed", source).as_str());
-    let reader = BufReader::new(file);
-    Self {
-      reader,
-      source: source.to_string(),
-      end: false,
-      token_buf: vec![],
-      token_buf_idx: 0,
-      cur_line: 0,
-      last_token_descriptor: None,
-    }
+    let content =
+      std::fs::read_to_string(source).unwrap_or_else(|_| panic!("{} file open failed", source));
+    Parser::new_from_source(source, &content)
   }
 
-  fn update_token_descriptor(&mut self, token: &Token) {
-    // TODO: Extract position of token in line.
-    let dec = TokenDescriptor::new(token.clone(), self.source.clone(), self.cur_line, 0);
-    self.last_token_descriptor = Some(dec);
+  /// Tokenize jack code read from `reader` up front.
+  pub fn from_reader<R: Read>(source: &str, mut reader: R) -> std::io::Result<Self> {
+    let mut content = String::new();
+    reader.read_to_string(&mut content)?;
+    Ok(Parser::new_from_source(source, &content))
   }
 
-  fn return_token_from_buf(&mut self, take: bool) -> Option<Token> {
-    let r = self.token_buf[self.token_buf_idx].clone();
-    self.update_token_descriptor(&r);
-    if take {
-      self.token_buf_idx += 1;
+  /// Tokenize jack code held in memory, `source` names it in token descriptors.
+  pub fn new_from_source(source: &str, content: &str) -> Self {
+    Self {
+      lexer: Lexer::new(content.to_string()),
+      source: source.to_string(),
+      peeked: None,
+      last_token_descriptor: None,
+      last_span: Span::default(),
+      errors: vec![],
     }
-    return Some(r);
   }
 
-  fn read_line(&mut self, buf: &mut String) -> bool {
-    let len = self.reader.read_line(buf).expect("read file failed");
-    self.cur_line += 1;
-    if len == 0 {
-      self.end = true;
-      return false;
-    }
-    return true;
+  fn update_token_descriptor(&mut self, token: &Token, span: Span) {
+    let dec = TokenDescriptor::new(token.clone(), self.source.clone(), span);
+    self.last_token_descriptor = Some(dec);
   }
 
   fn forward(&mut self, take: bool) -> Option<Token> {
-    if self.token_buf.len() > self.token_buf_idx {
-      return self.return_token_from_buf(take);
-    }
-    if self.end {
-      return None;
-    }
-    let mut buf = String::new();
-    let mut is_multiline_comment = false;
-    loop {
-      buf.clear();
-      if !self.read_line(&mut buf) {
-        return None;
-      }
-      let line = buf.trim();
-      let tokens = Token::from_line(line, &mut is_multiline_comment);
-      if tokens.is_err() {
-        panic!("{}", tokens.unwrap_err());
+    while self.peeked.is_none() {
+      match self.lexer.next()? {
+        Ok(token) => self.peeked = Some(token),
+        Err(e) => {
+          let location = Location::new(self.source.clone(), e.span);
+          self
+            .errors
+            .push(CompileError::lexical(&e.message, location));
+          if let Some(token) = e.token {
+            self.peeked = Some((token, e.span));
+          }
+        }
       }
-      self.token_buf = tokens.unwrap();
-      if self.token_buf.len() == 0 {
-        continue;
-      }
-      self.token_buf_idx = 0;
-      return self.return_token_from_buf(take);
     }
+    let (token, span) = if take {
+      let peeked = self.peeked.take()?;
+      self.last_span = peeked.1;
+      peeked
+    } else {
+      self.peeked.clone()?
+    };
+    self.update_token_descriptor(&token, span);
+    Some(token)
   }
 
   pub fn peek(&mut self) -> Option<Token> {
@@ -99,8 +89,21 @@ impl Parser {
     None
   }
 
-  pub fn get_last_token_descriptor(&self) -> TokenDescriptor {
-    self.last_token_descriptor.clone().unwrap()
+  pub fn get_last_token_descriptor(&self) -> Option<TokenDescriptor> {
+    self.last_token_descriptor.clone()
+  }
+
+  pub fn last_span(&self) -> Span {
+    self.last_span
+  }
+
+  /// Lexer errors met so far.
+  pub fn take_errors(&mut self) -> Vec<CompileError> {
+    std::mem::take(&mut self.errors)
+  }
+
+  pub fn source(&self) -> &String {
+    &self.source
   }
 }
 
@@ -1,2 +1,3 @@
+pub mod asm;
 pub mod hack;
 pub mod jack;
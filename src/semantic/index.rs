@@ -0,0 +1,159 @@
+use std::collections::HashMap;
+use std::sync::OnceLock;
+
+use crate::ast::{Class, SubroutineType, VarScope};
+use crate::error::CompileError;
+use crate::os::{is_os_internal, OS_CLASSES};
+use crate::parser::jack::Parser;
+use crate::symbol_table::{SymbolTable, VariableSymbolItem};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SubroutineSignature {
+  pub kind: SubroutineType,
+  // "void" for subroutines without a return value.
+  pub return_type: String,
+  // (type, name) of each parameter.
+  pub parameters: Vec<(String, String)>,
+  // A helper of a bundled OS class, only callable from that class.
+  pub internal: bool,
+}
+
+/// Declarations of a class visible to other classes.
+#[derive(Clone)]
+pub struct ClassInfo {
+  name: String,
+  // Source file the class is defined in.
+  file: String,
+  variables: SymbolTable,
+  subroutines: HashMap<String, SubroutineSignature>,
+}
+
+impl ClassInfo {
+  pub fn name(&self) -> &String {
+    &self.name
+  }
+
+  /// Static or field variable of the class.
+  pub fn variable(&self, name: &String) -> Option<&VariableSymbolItem> {
+    self.variables.find_item_by_name(name)
+  }
+
+  pub fn subroutine(&self, name: &str) -> Option<&SubroutineSignature> {
+    self.subroutines.get(name)
+  }
+
+  pub fn field_count(&self) -> usize {
+    self.variables.scope_item_count(VarScope::Field)
+  }
+
+  fn from_class(class: &Class) -> Self {
+    let mut info = ClassInfo {
+      name: class.name.name.clone(),
+      file: class.file.clone(),
+      variables: SymbolTable::new(),
+      subroutines: HashMap::new(),
+    };
+    for var_dec in &class.variables {
+      for name in &var_dec.names {
+        info.variables.push_item(
+          name.name.clone(),
+          var_dec.var_type.name.clone(),
+          var_dec.kind,
+        );
+      }
+    }
+    for subroutine in &class.subroutines {
+      let parameters = subroutine
+        .parameters
+        .iter()
+        .map(|parameter| (parameter.var_type.name.clone(), parameter.name.name.clone()))
+        .collect();
+      let signature = SubroutineSignature {
+        kind: subroutine.kind,
+        return_type: subroutine.return_type.name.clone(),
+        parameters,
+        internal: false,
+      };
+      info
+        .subroutines
+        .insert(subroutine.name.name.clone(), signature);
+    }
+    info
+  }
+
+  // Hide the helpers of a bundled OS class from other classes.
+  fn mark_os_internals(&mut self) {
+    for (name, signature) in self.subroutines.iter_mut() {
+      signature.internal = is_os_internal(&self.name, name);
+    }
+  }
+}
+
+/// Declarations of every class of a program, OS classes included.
+#[derive(Clone, Default)]
+pub struct ProjectIndex {
+  classes: HashMap<String, ClassInfo>,
+}
+
+impl ProjectIndex {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Index `class`. A class of the same name indexed before is kept and the
+  /// duplicate reported at the name of `class`.
+  pub fn add_class(&mut self, class: &Class) -> Result<(), CompileError> {
+    if let Some(first) = self.classes.get(&class.name.name) {
+      let message = format!("duplicate declaration of class '{}'", class.name.name);
+      let note = format!("first declared in {}", first.file);
+      let location = class.location(class.name.span);
+      return Err(CompileError::semantic(&message, location).with_note(&note));
+    }
+    let mut info = ClassInfo::from_class(class);
+    // An OS class linked from source declares what the bundled one does.
+    let os_class = os_index().class(&info.name);
+    let same_subroutines = |os_class: &ClassInfo| {
+      os_class.subroutines.len() == info.subroutines.len()
+        && os_class
+          .subroutines
+          .keys()
+          .all(|name| info.subroutines.contains_key(name))
+    };
+    if os_class.is_some_and(same_subroutines) {
+      info.mark_os_internals();
+    }
+    self.classes.insert(info.name.clone(), info);
+    Ok(())
+  }
+
+  /// Index the bundled OS classes the program does not define itself.
+  pub fn add_os_classes(&mut self) {
+    for (class_name, info) in &os_index().classes {
+      if !self.classes.contains_key(class_name) {
+        self.classes.insert(class_name.clone(), info.clone());
+      }
+    }
+  }
+
+  pub fn class(&self, name: &str) -> Option<&ClassInfo> {
+    self.classes.get(name)
+  }
+}
+
+// The bundled OS classes, parsed once for every program.
+fn os_index() -> &'static ProjectIndex {
+  static OS_INDEX: OnceLock<ProjectIndex> = OnceLock::new();
+  OS_INDEX.get_or_init(|| {
+    let mut index = ProjectIndex::new();
+    for (class_name, source) in OS_CLASSES {
+      let parser = Parser::new_from_source(&format!("{}.jack", class_name), source);
+      // The bundled sources always parse.
+      if let Ok(class) = crate::parse_class(parser) {
+        let mut info = ClassInfo::from_class(&class);
+        info.mark_os_internals();
+        index.classes.insert(info.name.clone(), info);
+      }
+    }
+    index
+  })
+}
@@ -0,0 +1,716 @@
+pub mod index;
+pub mod types;
+
+use std::collections::HashSet;
+
+use crate::ast::*;
+use crate::error::CompileError;
+use crate::symbol_table::{SymbolTable, VariableSymbolItem};
+use crate::token::Span;
+
+pub use index::{ClassInfo, ProjectIndex, SubroutineSignature};
+pub use types::{JackType, Strictness};
+
+fn is_primitive_type(type_name: &str) -> bool {
+  matches!(type_name, "int" | "char" | "boolean")
+}
+
+/// Checks a parsed class against the declarations of the whole program.
+struct Checker<'a> {
+  class_ast: &'a Class,
+  index: &'a ProjectIndex,
+  class: Option<&'a ClassInfo>,
+  strictness: Strictness,
+  // Arguments and locals of the current subroutine.
+  locals: SymbolTable,
+  subroutine_type: SubroutineType,
+  subroutine_name: String,
+  return_type: JackType,
+  errors: Vec<CompileError>,
+}
+
+impl<'a> Checker<'a> {
+  fn error(&mut self, span: Span, message: String) {
+    let location = self.class_ast.location(span);
+    self.errors.push(CompileError::semantic(&message, location));
+  }
+
+  fn error_with_note(&mut self, span: Span, message: String, note: &str) {
+    let location = self.class_ast.location(span);
+    let error = CompileError::semantic(&message, location).with_note(note);
+    self.errors.push(error);
+  }
+
+  fn get_variable(&self, name: &String) -> Option<&VariableSymbolItem> {
+    if let Some(var) = self.locals.find_item_by_name(name) {
+      return Some(var);
+    }
+    let var = self.class?.variable(name)?;
+    // Functions have no object to read fields from.
+    if var.get_kind() == VarScope::Field && self.subroutine_type == SubroutineType::Function {
+      return None;
+    }
+    Some(var)
+  }
+
+  // Field read from a function, which has no object.
+  fn is_hidden_field(&self, name: &String) -> bool {
+    let var = self.class.and_then(|class| class.variable(name));
+    matches!(var, Some(var) if var.get_kind() == VarScope::Field)
+      && self.locals.find_item_by_name(name).is_none()
+      && self.subroutine_type == SubroutineType::Function
+  }
+
+  fn hidden_field_error(&mut self, span: Span, name: &str) {
+    let message = format!(
+      "field '{}' can not be used in function '{}'",
+      name, self.subroutine_name
+    );
+    let note = format!(
+      "functions have no object, declare '{}' as a method or '{}' as static",
+      self.subroutine_name, name
+    );
+    self.error_with_note(span, message, &note);
+  }
+
+  // Type of the variable `name`, reports undefined variables.
+  fn check_variable(&mut self, name: &Ident) -> JackType {
+    if let Some(var) = self.get_variable(&name.name) {
+      return JackType::from_name(var.get_type());
+    }
+    if self.is_hidden_field(&name.name) {
+      self.hidden_field_error(name.span, &name.name);
+    } else {
+      self.error(name.span, format!("undefined variable '{}'", name.name));
+    }
+    JackType::Unknown
+  }
+
+  fn declare_local(&mut self, name: &Ident, var_type: &str, scope: VarScope) {
+    if self.locals.find_item_by_name(&name.name).is_some() {
+      let message = format!(
+        "duplicate declaration of '{}' in '{}'",
+        name.name, self.subroutine_name
+      );
+      return self.error(name.span, message);
+    }
+    self
+      .locals
+      .push_item(name.name.clone(), var_type.to_string(), scope);
+  }
+
+  // Types name a primitive or a class of the program.
+  fn check_type(&mut self, var_type: &Ident) {
+    let name = &var_type.name;
+    if is_primitive_type(name) || name == "void" || self.index.class(name).is_some() {
+      return;
+    }
+    self.error(var_type.span, format!("unknown class '{}'", name));
+  }
+
+  fn check_class(&mut self) {
+    let class = self.class_ast;
+    let mut variables = HashSet::new();
+    for var_dec in &class.variables {
+      self.check_type(&var_dec.var_type);
+      for name in &var_dec.names {
+        if !variables.insert(&name.name) {
+          self.error(
+            name.span,
+            format!("duplicate declaration of '{}'", name.name),
+          );
+        }
+      }
+    }
+    let mut subroutines = HashSet::new();
+    for subroutine in &class.subroutines {
+      self.check_subroutine(subroutine);
+      if !subroutines.insert(&subroutine.name.name) {
+        let message = format!("duplicate declaration of '{}'", subroutine.name.name);
+        self.error(subroutine.name.span, message);
+      }
+    }
+  }
+
+  /**
+   *  subroutine
+   *    return type, name, parameter list, body
+   */
+  fn check_subroutine(&mut self, subroutine: &SubroutineDec) {
+    self.locals.clear();
+    self.subroutine_type = subroutine.kind;
+    self.subroutine_name = subroutine.name.name.clone();
+    self.return_type = JackType::from_name(&subroutine.return_type.name);
+    self.check_type(&subroutine.return_type);
+    if self.subroutine_type == SubroutineType::Method {
+      self.locals.push_item(
+        String::from("this"),
+        String::from("this"),
+        VarScope::Argument,
+      );
+    }
+    for parameter in &subroutine.parameters {
+      self.check_type(&parameter.var_type);
+      self.declare_local(
+        &parameter.name,
+        &parameter.var_type.name,
+        VarScope::Argument,
+      );
+    }
+    for var_dec in &subroutine.locals {
+      self.check_type(&var_dec.var_type);
+      for name in &var_dec.names {
+        self.declare_local(name, &var_dec.var_type.name, VarScope::Variable);
+      }
+    }
+    self.check_statements(&subroutine.statements);
+  }
+
+  fn check_statements(&mut self, statements: &[Statement]) {
+    for statement in statements {
+      match statement {
+        Statement::Let {
+          target,
+          index,
+          value,
+          ..
+        } => self.check_let_statement(target, index.as_ref(), value),
+        Statement::If {
+          condition,
+          body,
+          else_body,
+          ..
+        } => {
+          self.check_condition(condition);
+          self.check_statements(body);
+          if let Some(else_body) = else_body {
+            self.check_statements(else_body);
+          }
+        }
+        Statement::While {
+          condition, body, ..
+        } => {
+          self.check_condition(condition);
+          self.check_statements(body);
+        }
+        Statement::Do { call, .. } => {
+          self.check_call(call);
+        }
+        Statement::Return { value, span } => self.check_return_statement(value.as_ref(), *span),
+      }
+    }
+  }
+
+  /**
+   *  let statement
+   *    1. let var = expression;
+   *    2. let var[index] = expression;
+   */
+  fn check_let_statement(&mut self, target: &Ident, index: Option<&Expr>, value: &Expr) {
+    let var_type = self.check_variable(target);
+    match index {
+      Some(index) => {
+        self.check_index(target, &var_type, index);
+        self.check_expression(value);
+      }
+      None => {
+        let value_type = self.check_expression(value);
+        if !value_type.is_assignable_to(&var_type, self.strictness) {
+          let message = format!(
+            "cannot assign {} to variable '{}' of type {}",
+            value_type, target.name, var_type
+          );
+          self.error(value.span(), message);
+        }
+      }
+    }
+  }
+
+  fn check_index(&mut self, array: &Ident, var_type: &JackType, index: &Expr) {
+    let array_type = JackType::Class("Array".to_string());
+    if !var_type.is_assignable_to(&array_type, self.strictness) {
+      let message = format!(
+        "'{}' has type {} and can not be indexed",
+        array.name, var_type
+      );
+      self.error(array.span, message);
+    }
+    let index_type = self.check_expression(index);
+    if !index_type.is_numeric(self.strictness) {
+      self.error(index.span(), format!("array index has type {}", index_type));
+    }
+  }
+
+  /**
+   *  if / while statement
+   *    ( condition ) { statements } [ else { statements } ]
+   */
+  fn check_condition(&mut self, condition: &Expr) {
+    let condition_type = self.check_expression(condition);
+    if !condition_type.is_condition(self.strictness) {
+      let message = format!("condition has type {}, expected boolean", condition_type);
+      self.error(condition.span(), message);
+    }
+  }
+
+  fn check_return_statement(&mut self, value: Option<&Expr>, span: Span) {
+    let return_type = self.return_type.clone();
+    match value {
+      Some(value) => {
+        let value_type = self.check_expression(value);
+        if return_type == JackType::Void {
+          let message = format!(
+            "'{}' is void and cannot return a value",
+            self.subroutine_name
+          );
+          self.error(value.span(), message);
+        } else if !value_type.is_assignable_to(&return_type, self.strictness) {
+          let message = format!(
+            "'{}' returns {}, found {}",
+            self.subroutine_name, return_type, value_type
+          );
+          self.error(value.span(), message);
+        }
+      }
+      None if return_type != JackType::Void => {
+        let message = format!(
+          "'{}' must return a value of type {}",
+          self.subroutine_name, return_type
+        );
+        self.error(span, message);
+      }
+      None => (),
+    }
+  }
+
+  fn check_binary(&mut self, span: Span, op: char, left: JackType, right: JackType) -> JackType {
+    let strictness = self.strictness;
+    let (valid, result) = match op {
+      '+' | '-' | '*' | '/' => (
+        left.is_numeric(strictness) && right.is_numeric(strictness),
+        JackType::Int,
+      ),
+      '<' | '>' => (
+        left.is_numeric(strictness) && right.is_numeric(strictness),
+        JackType::Boolean,
+      ),
+      '&' | '|' => {
+        // Bitwise on ints, logical on booleans, never both at once when strict.
+        let mixed = left != right && left != JackType::Unknown && right != JackType::Unknown;
+        let valid = left.is_logical(strictness)
+          && right.is_logical(strictness)
+          && !(mixed && strictness == Strictness::Strict);
+        if left == JackType::Boolean && right == JackType::Boolean {
+          (valid, JackType::Boolean)
+        } else {
+          (valid, JackType::Int)
+        }
+      }
+      '=' => (
+        left.is_assignable_to(&right, strictness) || right.is_assignable_to(&left, strictness),
+        JackType::Boolean,
+      ),
+      _ => (true, JackType::Unknown),
+    };
+    if !valid {
+      let message = format!(
+        "operator '{}' can not be applied to {} and {}",
+        op, left, right
+      );
+      self.error(span, message);
+    }
+    result
+  }
+
+  /**
+   *  expression
+   *    1. constant
+   *    2. var name [ [ index ] ]
+   *    3. unary op term
+   *    4. ( expression )
+   *    5. subroutine call
+   *    6. expression op term, evaluated from left to right
+   */
+  fn check_expression(&mut self, expr: &Expr) -> JackType {
+    match expr {
+      Expr::Int { .. } => JackType::Int,
+      Expr::String { .. } => JackType::Class("String".to_string()),
+      Expr::Keyword { keyword, .. } => match keyword.as_str() {
+        "true" | "false" => JackType::Boolean,
+        "null" => JackType::Null,
+        // this
+        _ => match self.class {
+          Some(class) => JackType::Class(class.name().clone()),
+          None => JackType::Unknown,
+        },
+      },
+      Expr::Var(name) => self.check_variable(name),
+      Expr::Index { array, index, .. } => {
+        let var_type = self.check_variable(array);
+        self.check_index(array, &var_type, index);
+        // Arrays are untyped.
+        JackType::Unknown
+      }
+      Expr::Unary { op, operand, span } => {
+        let operand = self.check_expression(operand);
+        let valid = match op {
+          '~' => operand.is_logical(self.strictness),
+          _ => operand.is_numeric(self.strictness),
+        };
+        if !valid {
+          let message = format!("operator '{}' can not be applied to {}", op, operand);
+          self.error(*span, message);
+        }
+        match op {
+          '~' => operand,
+          _ => JackType::Int,
+        }
+      }
+      Expr::Binary {
+        op,
+        op_span,
+        left,
+        right,
+      } => {
+        let left = self.check_expression(left);
+        let right = self.check_expression(right);
+        self.check_binary(*op_span, *op, left, right)
+      }
+      Expr::Paren { expr, .. } => self.check_expression(expr),
+      Expr::Call(call) => match self.check_call(call) {
+        JackType::Void => {
+          self.error(call.span, "void subroutine used as a value".to_string());
+          JackType::Unknown
+        }
+        return_type => return_type,
+      },
+    }
+  }
+
+  /// Check a subroutine call and its arguments, returns the type of its value.
+  fn check_call(&mut self, call: &Call) -> JackType {
+    let argument_types: Vec<JackType> = call
+      .arguments
+      .iter()
+      .map(|argument| self.check_expression(argument))
+      .collect();
+    let (class_name, name, signature) = match self.resolve_call(call) {
+      Some(callee) => callee,
+      None => return JackType::Unknown,
+    };
+    let expected = signature.parameters.len();
+    if expected != call.arguments.len() {
+      let plural = if expected == 1 { "" } else { "s" };
+      let message = format!(
+        "'{}.{}' expects {} argument{}, found {}",
+        class_name,
+        name,
+        expected,
+        plural,
+        call.arguments.len()
+      );
+      self.error(call.span, message);
+    } else {
+      for (i, argument_type) in argument_types.iter().enumerate() {
+        let parameter_type = JackType::from_name(&signature.parameters[i].0);
+        if !argument_type.is_assignable_to(&parameter_type, self.strictness) {
+          let message = format!(
+            "argument {} of '{}.{}' expects {}, found {}",
+            i + 1,
+            class_name,
+            name,
+            parameter_type,
+            argument_type
+          );
+          self.error(call.arguments[i].span(), message);
+        }
+      }
+    }
+    JackType::from_name(&signature.return_type)
+  }
+
+  /**
+   *  subroutine call
+   *    1. var name.method name
+   *    2. class name.function name
+   *    3. subroutine name, of the current class
+   */
+  // Returns the class name, subroutine name and signature of the callee.
+  fn resolve_call(&mut self, call: &Call) -> Option<(String, String, &'a SubroutineSignature)> {
+    let first_name = call.receiver.as_ref().map(|receiver| &receiver.name);
+    let name = &call.name.name;
+    let index = self.index;
+    let (class_name, with_object) = match first_name {
+      Some(first_name) => match self.get_variable(first_name) {
+        Some(var) => {
+          let type_name = var.get_type().clone();
+          if is_primitive_type(&type_name) {
+            let message = format!(
+              "'{}' has type {} and no subroutine '{}'",
+              first_name, type_name, name
+            );
+            self.error(call.span, message);
+            return None;
+          }
+          (type_name, true)
+        }
+        None if self.is_hidden_field(first_name) => {
+          self.hidden_field_error(call.span, first_name);
+          return None;
+        }
+        None if index.class(first_name).is_none() => {
+          let message = format!("unknown class or variable '{}'", first_name);
+          self.error(call.span, message);
+          return None;
+        }
+        None => (first_name.clone(), false),
+      },
+      None => (
+        self.class?.name().clone(),
+        self.subroutine_type != SubroutineType::Function,
+      ),
+    };
+    let class = match index.class(&class_name) {
+      Some(class) => class,
+      None => {
+        self.error(call.span, format!("unknown class '{}'", class_name));
+        return None;
+      }
+    };
+    let signature = match class.subroutine(name) {
+      Some(signature) => signature,
+      None => {
+        let message = format!("class {} has no subroutine '{}'", class_name, name);
+        self.error(call.span, message);
+        return None;
+      }
+    };
+    if signature.internal && self.class.map(|class| class.name()) != Some(&class_name) {
+      let message = format!("'{}.{}' is not part of the OS API", class_name, name);
+      let note = format!("it is a helper only {} may call", class_name);
+      self.error_with_note(call.span, message, &note);
+      return None;
+    }
+    let is_method = signature.kind == SubroutineType::Method;
+    if is_method && !with_object {
+      let message = match first_name {
+        Some(_) => format!("method '{}.{}' called without an object", class_name, name),
+        None => format!("method '{}' called from a function without an object", name),
+      };
+      let note = format!(
+        "call it on an object of class {}, e.g. obj.{}()",
+        class_name, name
+      );
+      self.error_with_note(call.span, message, &note);
+      return None;
+    }
+    // Unqualified calls may target any subroutine of the current class.
+    if !is_method && with_object && first_name.is_some() {
+      let message = format!(
+        "'{}.{}' is a {}, call it as {}.{}",
+        class_name, name, signature.kind, class_name, name
+      );
+      self.error(call.span, message);
+      return None;
+    }
+    Some((class_name, name.clone(), signature))
+  }
+}
+
+/// Validate the calls, assignments, returns and operators of `class` against
+/// `index`, which must hold the class itself.
+pub fn check_class(
+  class: &Class,
+  index: &ProjectIndex,
+  strictness: Strictness,
+) -> Vec<CompileError> {
+  let mut checker = Checker {
+    class_ast: class,
+    index,
+    class: index.class(&class.name.name),
+    strictness,
+    locals: SymbolTable::new(),
+    subroutine_type: SubroutineType::Function,
+    subroutine_name: String::new(),
+    return_type: JackType::Void,
+    errors: vec![],
+  };
+  checker.check_class();
+  let mut errors = checker.errors;
+  errors.sort_by_key(|e| (e.location().line(), e.location().column()));
+  errors
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::parser::jack::Parser;
+
+  // Errors of the classes `sources`, checked as one program with the OS.
+  fn check(sources: &[&str], strictness: Strictness) -> Vec<String> {
+    let classes: Vec<Class> = sources
+      .iter()
+      .map(|source| {
+        let name = source.split_whitespace().nth(1).unwrap();
+        let parser = Parser::new_from_source(&format!("{}.jack", name), source);
+        crate::parse_class(parser).unwrap()
+      })
+      .collect();
+    let mut index = ProjectIndex::new();
+    for class in &classes {
+      index.add_class(class).unwrap();
+    }
+    index.add_os_classes();
+    classes
+      .iter()
+      .flat_map(|class| check_class(class, &index, strictness))
+      .map(|e| e.to_string())
+      .collect()
+  }
+
+  fn check_main(body: &str, strictness: Strictness) -> Vec<String> {
+    let main = format!(
+      "class Main {{
+  field int size;
+  static boolean flag;
+{}
+}}",
+      body
+    );
+    check(&[&main], strictness)
+  }
+
+  #[test]
+  fn unknown_classes_in_declarations() {
+    let errors = check_main(
+      "  field Foo foo;
+  function Bar f(Baz baz) {
+    var Qux qux;
+    var String s;
+    return null;
+  }",
+      Strictness::Permissive,
+    );
+    assert_eq!(
+      errors,
+      vec![
+        "Main.jack:4:9: unknown class 'Foo'",
+        "Main.jack:5:12: unknown class 'Bar'",
+        "Main.jack:5:18: unknown class 'Baz'",
+        "Main.jack:6:9: unknown class 'Qux'",
+      ]
+    );
+  }
+
+  #[test]
+  fn calls_across_classes() {
+    let square = "class Square {
+  constructor Square new(int size) { return this; }
+  method void draw() { return; }
+}";
+    let main = "class Main {
+  function void main() {
+    var Square square;
+    let square = Square.new();
+    do square.draw(1);
+    do Square.draw();
+    do square.erase();
+    do Output.printInt(Math.bit(1, 2));
+    return;
+  }
+}";
+    assert_eq!(
+      check(&[square, main], Strictness::Permissive),
+      vec![
+        "Main.jack:4:18: 'Square.new' expects 1 argument, found 0",
+        "Main.jack:5:8: 'Square.draw' expects 0 arguments, found 1",
+        "Main.jack:6:8: method 'Square.draw' called without an object",
+        "Main.jack:7:8: class Square has no subroutine 'erase'",
+        "Main.jack:8:24: 'Math.bit' is not part of the OS API",
+      ]
+    );
+  }
+
+  #[test]
+  fn os_helpers_are_callable_from_their_class() {
+    let math = crate::os::os_class_source("Math").unwrap();
+    assert_eq!(check(&[math], Strictness::Permissive), Vec::<String>::new());
+  }
+
+  #[test]
+  fn type_mismatch_by_strictness() {
+    let body = "  function void main() {
+    var boolean b;
+    var char c;
+    var String s;
+    let b = 1;
+    let c = b + 1;
+    let s = 5;
+    return;
+  }";
+    assert_eq!(
+      check_main(body, Strictness::Permissive),
+      vec!["Main.jack:10:13: cannot assign int to variable 's' of type String"]
+    );
+    assert_eq!(
+      check_main(body, Strictness::Strict),
+      vec![
+        "Main.jack:8:13: cannot assign int to variable 'b' of type boolean",
+        "Main.jack:9:13: cannot assign int to variable 'c' of type char",
+        "Main.jack:9:15: operator '+' can not be applied to boolean and int",
+        "Main.jack:10:13: cannot assign int to variable 's' of type String",
+      ]
+    );
+  }
+
+  #[test]
+  fn unqualified_calls_resolve_in_the_class() {
+    let errors = check_main(
+      "  function int helper() { return 1; }
+  method void draw() { do helper(); do draw(); return; }
+  function void main() {
+    do helper();
+    do draw();
+    do missing();
+    return;
+  }",
+      Strictness::Permissive,
+    );
+    assert_eq!(
+      errors,
+      vec![
+        "Main.jack:8:8: method 'draw' called from a function without an object",
+        "Main.jack:9:8: class Main has no subroutine 'missing'",
+      ]
+    );
+  }
+
+  #[test]
+  fn undefined_duplicate_and_hidden_variables() {
+    let errors = check_main(
+      "  field int size;
+  method void resize(int width) {
+    var int width, height;
+    let size = width + depth;
+    return;
+  }
+  function void main() {
+    let size = 1;
+    let flag = true;
+    return;
+  }
+  function void main() { return; }",
+      Strictness::Permissive,
+    );
+    assert_eq!(
+      errors,
+      vec![
+        "Main.jack:4:13: duplicate declaration of 'size'",
+        "Main.jack:6:13: duplicate declaration of 'width' in 'resize'",
+        "Main.jack:7:24: undefined variable 'depth'",
+        "Main.jack:11:9: field 'size' can not be used in function 'main'",
+        "Main.jack:15:17: duplicate declaration of 'main'",
+      ]
+    );
+  }
+}
@@ -0,0 +1,104 @@
+use std::fmt::{Display, Formatter};
+
+/// How strictly types are checked.
+/// Jack is weakly typed, the permissive default allows the mixing that
+/// idiomatic programs rely on: int, char and boolean are interchangeable and an
+/// Array stands for any pointer.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Strictness {
+  #[default]
+  Permissive,
+  // Types must match, only null converts to a class type.
+  Strict,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum JackType {
+  Int,
+  Char,
+  Boolean,
+  Void,
+  Null,
+  Class(String),
+  // Type that could not be determined, e.g. of an array element.
+  Unknown,
+}
+
+impl JackType {
+  pub fn from_name(type_name: &str) -> Self {
+    match type_name {
+      "int" => JackType::Int,
+      "char" => JackType::Char,
+      "boolean" => JackType::Boolean,
+      "void" => JackType::Void,
+      class_name => JackType::Class(class_name.to_string()),
+    }
+  }
+
+  fn is_primitive(&self) -> bool {
+    matches!(self, JackType::Int | JackType::Char | JackType::Boolean)
+  }
+
+  fn is_array(&self) -> bool {
+    *self == JackType::Class("Array".to_string())
+  }
+
+  /// Whether a value of this type may be stored where `to` is expected.
+  pub fn is_assignable_to(&self, to: &JackType, strictness: Strictness) -> bool {
+    if *self == JackType::Void || *to == JackType::Void {
+      return false;
+    }
+    if *self == JackType::Unknown || *to == JackType::Unknown || self == to {
+      return true;
+    }
+    match strictness {
+      Strictness::Permissive => {
+        *self == JackType::Null
+          || (self.is_primitive() && to.is_primitive())
+          || self.is_array()
+          || to.is_array()
+      }
+      Strictness::Strict => *self == JackType::Null && matches!(to, JackType::Class(_)),
+    }
+  }
+
+  /// Whether the type can be an operand of `+ - * / < >` and unary `-`.
+  pub fn is_numeric(&self, strictness: Strictness) -> bool {
+    match self {
+      JackType::Int | JackType::Char | JackType::Unknown => true,
+      JackType::Boolean | JackType::Null => strictness == Strictness::Permissive,
+      JackType::Class(_) => strictness == Strictness::Permissive && self.is_array(),
+      JackType::Void => false,
+    }
+  }
+
+  /// Whether the type can be an operand of `& |` and `~`.
+  pub fn is_logical(&self, strictness: Strictness) -> bool {
+    match self {
+      JackType::Int | JackType::Boolean | JackType::Unknown => true,
+      _ => self.is_numeric(strictness) && strictness == Strictness::Permissive,
+    }
+  }
+
+  /// Whether the type can be the condition of `if` and `while`.
+  pub fn is_condition(&self, strictness: Strictness) -> bool {
+    match strictness {
+      Strictness::Permissive => *self != JackType::Void,
+      Strictness::Strict => matches!(self, JackType::Boolean | JackType::Unknown),
+    }
+  }
+}
+
+impl Display for JackType {
+  fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    match self {
+      JackType::Int => write!(f, "int"),
+      JackType::Char => write!(f, "char"),
+      JackType::Boolean => write!(f, "boolean"),
+      JackType::Void => write!(f, "void"),
+      JackType::Null => write!(f, "null"),
+      JackType::Class(class_name) => write!(f, "{}", class_name),
+      JackType::Unknown => write!(f, "unknown"),
+    }
+  }
+}
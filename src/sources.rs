@@ -0,0 +1,248 @@
+//! Source files of a build. Files are always returned sorted by path, so the
+//! output of a build does not depend on the order the file system lists
+//! directories in.
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use glob::Pattern;
+
+/// Which files of a directory take part in a build.
+#[derive(Debug, Clone, Default)]
+pub struct SourceFilter {
+  // Also take the files of subdirectories.
+  pub recursive: bool,
+  // Globs matched against the path relative to the directory, e.g.
+  // `lib/*.jack`. Every file is included when there are none.
+  pub include: Vec<Pattern>,
+  pub exclude: Vec<Pattern>,
+}
+
+impl SourceFilter {
+  /// Parse include and exclude globs, `*` also matches `/`.
+  pub fn new(recursive: bool, include: &[String], exclude: &[String]) -> Result<Self, String> {
+    let patterns = |globs: &[String]| -> Result<Vec<Pattern>, String> {
+      globs
+        .iter()
+        .map(|glob| Pattern::new(glob).map_err(|e| format!("invalid pattern '{}': {}", glob, e)))
+        .collect()
+    };
+    Ok(Self {
+      recursive,
+      include: patterns(include)?,
+      exclude: patterns(exclude)?,
+    })
+  }
+
+  fn accepts(&self, relative: &str) -> bool {
+    let included = self.include.is_empty() || self.include.iter().any(|p| p.matches(relative));
+    included && !self.exclude.iter().any(|p| p.matches(relative))
+  }
+}
+
+/// `path` itself when it is a file ending with `extension`, otherwise the
+/// files of the directory `path` ending with `extension` and accepted by
+/// `filter`.
+pub fn find_sources(
+  path: &str,
+  extension: &str,
+  filter: &SourceFilter,
+) -> Result<Vec<String>, String> {
+  if path.ends_with(extension) && !Path::new(path).is_dir() {
+    return Ok(vec![path.to_string()]);
+  }
+  let root = path.trim_end_matches('/');
+  let mut ret = vec![];
+  let mut dirs = vec![String::new()];
+  while let Some(dir) = dirs.pop() {
+    let full_dir = if dir.is_empty() {
+      root.to_string()
+    } else {
+      format!("{}/{}", root, dir)
+    };
+    let entries = std::fs::read_dir(&full_dir).map_err(|e| format!("{}: {}", full_dir, e))?;
+    for entry in entries {
+      let entry = entry.map_err(|e| format!("{}: {}", full_dir, e))?;
+      let name = entry.file_name().to_string_lossy().to_string();
+      let relative = if dir.is_empty() {
+        name
+      } else {
+        format!("{}/{}", dir, name)
+      };
+      if entry.path().is_dir() {
+        if filter.recursive {
+          dirs.push(relative);
+        }
+      } else if relative.ends_with(extension) && filter.accepts(&relative) {
+        ret.push(format!("{}/{}", root, relative));
+      }
+    }
+  }
+  ret.sort();
+  Ok(ret)
+}
+
+/// Files named in the file `list`, one path relative to the directory `root`
+/// per line. Empty lines and lines starting with `#` are skipped.
+pub fn read_file_list(
+  root: &str,
+  list: &str,
+  filter: &SourceFilter,
+) -> Result<Vec<String>, String> {
+  let content = std::fs::read_to_string(list).map_err(|e| format!("{}: {}", list, e))?;
+  let root = root.trim_end_matches('/');
+  let mut ret: Vec<String> = content
+    .lines()
+    .map(str::trim)
+    .filter(|line| !line.is_empty() && !line.starts_with('#'))
+    .filter(|line| filter.accepts(line))
+    .map(|line| format!("{}/{}", root, line))
+    .collect();
+  ret.sort();
+  ret.dedup();
+  Ok(ret)
+}
+
+/// Fail when two of `files` have the same name in different directories,
+/// e.g. `a/Foo.vm` and `b/Foo.vm`: both would be the class Foo, their
+/// functions and `Foo.0` statics would clash.
+pub fn check_unique_names(files: &[String]) -> Result<(), String> {
+  let mut names: HashMap<&str, &str> = HashMap::new();
+  for file in files {
+    let name = file.rsplit('/').next().unwrap_or(file);
+    let stem = name.rsplit_once('.').map_or(name, |(stem, _)| stem);
+    if let Some(other) = names.insert(stem, file) {
+      return Err(format!("{} and {} both define {}", other, file, stem));
+    }
+  }
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  // A fresh directory holding `files`, named after the test using it.
+  fn tree(test: &str, files: &[&str]) -> String {
+    let root = std::env::temp_dir().join(format!("jack-sources-{}-{}", std::process::id(), test));
+    let _ = std::fs::remove_dir_all(&root);
+    for file in files {
+      let path = root.join(file);
+      std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+      std::fs::write(path, "").unwrap();
+    }
+    root.to_string_lossy().to_string()
+  }
+
+  fn relative(root: &str, files: Vec<String>) -> Vec<String> {
+    files
+      .iter()
+      .map(|file| {
+        file
+          .strip_prefix(&format!("{}/", root))
+          .unwrap()
+          .to_string()
+      })
+      .collect()
+  }
+
+  fn filter(recursive: bool, include: &[&str], exclude: &[&str]) -> SourceFilter {
+    let globs = |globs: &[&str]| {
+      globs
+        .iter()
+        .map(|glob| glob.to_string())
+        .collect::<Vec<_>>()
+    };
+    SourceFilter::new(recursive, &globs(include), &globs(exclude)).unwrap()
+  }
+
+  const FILES: &[&str] = &[
+    "Main.jack",
+    "Board.jack",
+    "notes.txt",
+    "lib/Util.jack",
+    "lib/test/Mock.jack",
+  ];
+
+  #[test]
+  fn sorted_and_recursive() {
+    let root = tree("recursive", FILES);
+    let found = find_sources(&root, ".jack", &filter(false, &[], &[])).unwrap();
+    assert_eq!(relative(&root, found), vec!["Board.jack", "Main.jack"]);
+
+    let found = find_sources(&root, ".jack", &filter(true, &[], &[])).unwrap();
+    assert_eq!(
+      relative(&root, found),
+      vec![
+        "Board.jack",
+        "Main.jack",
+        "lib/Util.jack",
+        "lib/test/Mock.jack"
+      ]
+    );
+
+    // A file is taken as is, filters apply to directories only.
+    let main = format!("{}/Main.jack", root);
+    let found = find_sources(&main, ".jack", &filter(false, &["lib/*"], &[])).unwrap();
+    assert_eq!(found, vec![main]);
+    std::fs::remove_dir_all(&root).unwrap();
+  }
+
+  #[test]
+  fn include_and_exclude() {
+    let root = tree("patterns", FILES);
+    let found = find_sources(&root, ".jack", &filter(true, &["lib/*"], &[])).unwrap();
+    assert_eq!(
+      relative(&root, found),
+      vec!["lib/Util.jack", "lib/test/Mock.jack"]
+    );
+
+    let found = find_sources(&root, ".jack", &filter(true, &[], &["*/test/*", "B*"])).unwrap();
+    assert_eq!(relative(&root, found), vec!["Main.jack", "lib/Util.jack"]);
+
+    assert!(SourceFilter::new(false, &["[".to_string()], &[]).is_err());
+    assert!(find_sources(
+      &format!("{}/missing", root),
+      ".jack",
+      &filter(false, &[], &[])
+    )
+    .is_err());
+    std::fs::remove_dir_all(&root).unwrap();
+  }
+
+  #[test]
+  fn file_list() {
+    let root = tree("list", &[]);
+    std::fs::create_dir_all(&root).unwrap();
+    let list = format!("{}/sources.txt", root);
+    std::fs::write(
+      &list,
+      "# sources\nMain.jack\n\n  lib/Util.jack \nBoard.jack\nMain.jack\n",
+    )
+    .unwrap();
+    let found = read_file_list(&root, &list, &filter(false, &[], &["Board*"])).unwrap();
+    assert_eq!(relative(&root, found), vec!["Main.jack", "lib/Util.jack"]);
+    assert!(read_file_list(
+      &root,
+      &format!("{}/missing.txt", root),
+      &filter(false, &[], &[])
+    )
+    .is_err());
+    std::fs::remove_dir_all(&root).unwrap();
+  }
+
+  #[test]
+  fn names_are_unique() {
+    let files = |files: &[&str]| {
+      files
+        .iter()
+        .map(|file| file.to_string())
+        .collect::<Vec<_>>()
+    };
+    assert!(check_unique_names(&files(&["a/Foo.vm", "a/Bar.vm", "Baz.vm"])).is_ok());
+    assert_eq!(
+      check_unique_names(&files(&["a/Foo.vm", "b/Bar.vm", "b/Foo.vm"])),
+      Err("a/Foo.vm and b/Foo.vm both define Foo".to_string())
+    );
+  }
+}
@@ -1,4 +1,4 @@
-use crate::operation::VarScope;
+use crate::ast::VarScope;
 
 #[derive(Debug, Clone)]
 pub struct VariableSymbolItem {
@@ -18,6 +18,10 @@ impl VariableSymbolItem {
     }
   }
 
+  pub fn get_name(&self) -> &String {
+    &self.name
+  }
+
   pub fn get_kind(&self) -> VarScope {
     self.kind
   }
@@ -31,6 +35,7 @@ impl VariableSymbolItem {
   }
 }
 
+#[derive(Clone, Default)]
 pub struct SymbolTable(Vec<VariableSymbolItem>, bool);
 
 impl SymbolTable {
@@ -79,4 +84,9 @@ impl SymbolTable {
   pub fn clear(&mut self) {
     self.0.clear();
   }
+
+  /// Items in declaration order.
+  pub fn iter(&self) -> std::slice::Iter<'_, VariableSymbolItem> {
+    self.0.iter()
+  }
 }
@@ -1,6 +1,4 @@
-use log::debug;
-
-static KEYWORDS: &'static [&str] = &[
+static KEYWORDS: &[&str] = &[
   "class",
   "constructor",
   "function",
@@ -35,256 +33,442 @@ pub enum Token {
 }
 
 fn is_digit(c: char) -> bool {
-  return c <= '9' && c >= '0';
+  c.is_ascii_digit()
 }
 
 fn is_alphabet(c: char) -> bool {
-  return (c <= 'z' && c >= 'a') || (c <= 'Z' && c >= 'A');
+  c.is_ascii_alphabetic()
 }
 
 fn is_identifier_char(c: char) -> bool {
   is_digit(c) || is_alphabet(c) || c == '_'
 }
 
-pub fn is_keyword_constant(keyword: &String) -> bool {
-  static KEYWORD_CONSTANTS: &'static [&str] = &["true", "false", "null", "this"];
-  return KEYWORD_CONSTANTS.iter().find(|&c| c == keyword).is_some();
+pub fn is_keyword_constant(keyword: &str) -> bool {
+  static KEYWORD_CONSTANTS: &[&str] = &["true", "false", "null", "this"];
+  KEYWORD_CONSTANTS.contains(&keyword)
 }
 
 pub fn is_unary_operation(op: char) -> bool {
-  static UNARY_OPERATIONS: &'static [char] = &['-', '~'];
-  return UNARY_OPERATIONS.iter().find(|&&c| c == op).is_some();
+  static UNARY_OPERATIONS: &[char] = &['-', '~'];
+  UNARY_OPERATIONS.contains(&op)
 }
 
-type ResultType = Result<Vec<Token>, String>;
+// Largest integer constant, negative values are negated constants.
+const MAX_INT: u16 = 32767;
 
-impl Token {
-  pub fn from_line(input_line: &str, is_multiline_comment: &mut bool) -> Result<Vec<Self>, String> {
-    if input_line.len() == 0 {
-      return Ok(vec![]);
-    }
-    if *is_multiline_comment {
-      if let Some(comment_end) = input_line.find("*/") {
-        *is_multiline_comment = false;
-        return Token::from_line(input_line.split_at(comment_end + 2).1, is_multiline_comment);
-      } else {
-        return Ok(vec![]);
-      }
-    }
-    let comment_start = input_line.find("//");
-    let multiline_comment_start = input_line.find("/*");
-    if comment_start.is_some() || multiline_comment_start.is_some() {
-      return Token::handle_comment(
-        input_line,
-        is_multiline_comment,
-        comment_start,
-        multiline_comment_start,
-      );
-    }
-    Token::from_line_without_comment(input_line)
-  }
-
-  fn handle_comment(
-    input_line: &str,
-    is_multiline_comment: &mut bool,
-    comment_start: Option<usize>,
-    multiline_comment_start: Option<usize>,
-  ) -> Result<Vec<Self>, String> {
-    // println!("DEBUG handle comment");
-    if comment_start.is_some() && multiline_comment_start.is_some() {
-      let comment_start = comment_start.unwrap();
-      let multiline_comment_start = multiline_comment_start.unwrap();
-      if comment_start < multiline_comment_start {
-        return Token::from_line_without_comment(input_line.split_at(comment_start).0);
-      } else {
-        let (left, right) = input_line.split_at(multiline_comment_start);
-        // println!("DEBUG multiline comment {}\t{}", left, right);
+const SYMBOLS: &[char] = &[
+  '(', ')', '{', '}', '[', ']', '.', ',', ';', '+', '-', '*', '/', '&', '|', '<', '>', '=', '~',
+];
 
-        let left = Token::from_line(left, is_multiline_comment);
-        *is_multiline_comment = true;
-        let right = Token::from_line(right, is_multiline_comment);
+#[derive(Debug, Clone, PartialEq)]
+pub struct LexError {
+  pub message: String,
+  pub span: Span,
+  // Token standing in for the offending text, so parsing goes on as if it
+  // was valid, e.g. an integer constant out of range.
+  pub token: Option<Token>,
+}
 
-        return Token::merge_result(left, right);
-      }
-    } else if comment_start.is_some() {
-      Token::from_line(
-        input_line.split_at(comment_start.unwrap()).0,
-        is_multiline_comment,
-      )
-    } else {
-      let (left, right) = input_line.split_at(multiline_comment_start.unwrap());
+impl std::fmt::Display for LexError {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(
+      f,
+      "{}:{}: {}",
+      self.span.start_line, self.span.start_column, self.message
+    )
+  }
+}
 
-      // println!("DEBUG multiline comment {}\t{}", left, right);
-      let left = Token::from_line(left, is_multiline_comment);
+/// A `//`, `/* */` or `/** */` comment with its markers.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Comment {
+  pub text: String,
+  pub span: Span,
+}
 
-      *is_multiline_comment = true;
-      let right = Token::from_line(right, is_multiline_comment);
+impl Comment {
+  /// Whether the comment runs to the end of its line.
+  pub fn is_line_comment(&self) -> bool {
+    self.text.starts_with("//")
+  }
+}
+
+/// Single pass lexer over a whole jack source.
+/// Whitespace and both comment styles are skipped between tokens, so strings
+/// may hold comment markers and comments may start anywhere on a line. The
+/// skipped comments are kept for tools that re-emit the source.
+pub struct Lexer {
+  source: String,
+  // Byte offset of the next char.
+  pos: usize,
+  line: usize,
+  column: usize,
+  comments: Vec<Comment>,
+}
 
-      Token::merge_result(left, right)
+impl Lexer {
+  pub fn new(source: String) -> Self {
+    Self {
+      source,
+      pos: 0,
+      line: 1,
+      column: 1,
+      comments: vec![],
     }
   }
 
-  fn from_line_without_comment(input_line: &str) -> Result<Vec<Self>, String> {
-    if let Some(str_start_pos) = input_line.find('\"') {
-      let (left, right) = input_line.split_at(str_start_pos);
-      // println!("DEBUG left of \": {} right of \": {}", left, right);
-      if let Some(str_end_pos) = right[1..].find('\"') {
-        let left_res = Token::from_line_without_str(left);
-        if left_res.is_err() {
-          return left_res;
-        }
-        let (mid, right) = right.split_at(str_end_pos + 2);
-        // println!("DEBUG mid {}, right {}", mid, right);
-        assert!(mid.len() >= 2);
-        let right_res = Token::from_line_without_comment(right);
-        if right_res.is_err() {
-          return right_res;
-        }
-        let mut left_res = left_res.unwrap();
-        left_res.push(Token::StringVal(mid[1..mid.len() - 1].to_string()));
-        left_res.append(&mut right_res.unwrap());
-        return Ok(left_res);
-      }
-      return Err("Invalid string match".to_string());
+  /// Comments skipped so far, in source order.
+  pub fn comments(&self) -> &[Comment] {
+    &self.comments
+  }
+
+  fn peek_char(&self) -> Option<char> {
+    self.source[self.pos..].chars().next()
+  }
+
+  fn peek_second_char(&self) -> Option<char> {
+    self.source[self.pos..].chars().nth(1)
+  }
+
+  fn bump(&mut self) -> Option<char> {
+    let c = self.peek_char()?;
+    self.pos += c.len_utf8();
+    if c == '\n' {
+      self.line += 1;
+      self.column = 1;
+    } else {
+      self.column += 1;
     }
-    Token::from_line_without_str(input_line)
+    Some(c)
   }
 
-  fn from_line_without_str(input_line: &str) -> Result<Vec<Self>, String> {
-    let mut res = vec![];
-    for sub_token in input_line.split(' ') {
-      debug!("sub token {}", sub_token);
-      let token = Token::from_word(sub_token.trim());
-      if token.is_err() {
-        return token;
-      }
-      res.append(&mut token.unwrap());
+  fn bump_while<P>(&mut self, predicate: P) -> &str
+  where
+    P: Fn(char) -> bool,
+  {
+    let start = self.pos;
+    while self.peek_char().is_some_and(&predicate) {
+      self.bump();
     }
-    Ok(res)
+    &self.source[start..self.pos]
   }
 
-  fn from_int(input_word: &str) -> Result<Self, String> {
-    let r = input_word.parse::<u16>();
-    match r {
-      Ok(r) => Ok(Token::IntVal(r)),
-      Err(e) => Err(e.to_string()),
+  fn error(&self, message: String, start: (usize, usize)) -> LexError {
+    LexError {
+      message,
+      span: Span::new(start.0, start.1, self.line, self.column),
+      token: None,
     }
   }
 
-  fn from_symbol(input_symbol: char) -> Result<Self, String> {
-    static SYMBOLS: &'static [char] = &[
-      '(', ')', '{', '}', '[', ']', '.', ',', ';', '+', '-', '*', '/', '&', '|', '<', '>', '=', '~',
-    ];
-    for s in SYMBOLS {
-      if *s == input_symbol {
-        return Ok(Token::Symbol(*s));
+  fn push_comment(&mut self, text: String, start: (usize, usize)) {
+    let span = Span::new(start.0, start.1, self.line, self.column);
+    self.comments.push(Comment { text, span });
+  }
+
+  // Skip whitespace and comments up to the next token.
+  fn skip_trivia(&mut self) -> Result<(), LexError> {
+    loop {
+      match (self.peek_char(), self.peek_second_char()) {
+        (Some(c), _) if c.is_whitespace() => {
+          self.bump();
+        }
+        (Some('/'), Some('/')) => {
+          let start = (self.line, self.column);
+          let text = self.bump_while(|c| c != '\n').to_string();
+          self.push_comment(text, start);
+        }
+        (Some('/'), Some('*')) => {
+          let start = (self.line, self.column);
+          let start_pos = self.pos;
+          self.bump();
+          self.bump();
+          loop {
+            match self.bump() {
+              Some('*') if self.peek_char() == Some('/') => {
+                self.bump();
+                break;
+              }
+              Some(_) => (),
+              None => return Err(self.error("Unterminated comment".to_string(), start)),
+            }
+          }
+          let text = self.source[start_pos..self.pos].to_string();
+          self.push_comment(text, start);
+        }
+        _ => return Ok(()),
       }
     }
-    return Err(format!("Invalid symbol: {}", input_symbol));
   }
 
-  fn from_word(input_word: &str) -> Result<Vec<Self>, String> {
-    let mut res = vec![];
-    if input_word.len() == 0 {
-      return Ok(res);
+  fn next_token(&mut self) -> Option<Result<(Token, Span), LexError>> {
+    if let Err(e) = self.skip_trivia() {
+      return Some(Err(e));
     }
-    let mut chars = input_word.chars();
-    let mut current_char = chars.next();
-    let first_char = current_char.unwrap();
-    let mut idx = 0;
-    if is_digit(first_char) {
-      while current_char.is_some() && is_digit(current_char.unwrap()) {
-        current_char = chars.next();
-        idx += 1;
-      }
-      let token = Token::from_int(&input_word[0..idx]);
-      if token.is_err() {
-        return Err(token.unwrap_err());
+    let start = (self.line, self.column);
+    let first_char = self.peek_char()?;
+    let token = if is_digit(first_char) {
+      let digits = self.bump_while(is_digit);
+      match digits.parse::<u16>() {
+        Ok(v) if v <= MAX_INT => Ok(Token::IntVal(v)),
+        _ => {
+          let message = format!("Integer constant out of range: {}", digits);
+          return Some(Err(LexError {
+            token: Some(Token::IntVal(MAX_INT)),
+            ..self.error(message, start)
+          }));
+        }
       }
-      res.push(token.unwrap());
     } else if is_identifier_char(first_char) {
-      let mut found = false;
-      for s in KEYWORDS {
-        if input_word.len() >= s.len() && *s == &input_word[0..s.len()] {
-          if input_word.len() > s.len() {
-            let next_char = input_word.chars().skip(s.len()).next();
-            if is_identifier_char(next_char.unwrap()) {
-              continue;
-            }
-          }
-          res.push(Token::KeyWord(s.to_string()));
-          found = true;
-          idx = s.len();
-          break;
-        }
+      let word = self.bump_while(is_identifier_char);
+      if KEYWORDS.contains(&word) {
+        Ok(Token::KeyWord(word.to_string()))
+      } else {
+        Ok(Token::Identifier(word.to_string()))
       }
-      if !found {
-        while current_char.is_some() && is_identifier_char(current_char.unwrap()) {
-          current_char = chars.next();
-          idx += 1;
-        }
-        res.push(Token::Identifier(input_word[0..idx].to_string()));
+    } else if first_char == '"' {
+      self.bump();
+      let content = self.bump_while(|c| c != '"' && c != '\n').to_string();
+      // The error ends the line, the newline is left to skip_trivia.
+      if self.peek_char() == Some('"') {
+        self.bump();
+        Ok(Token::StringVal(content))
+      } else {
+        Err("Unterminated string".to_string())
       }
     } else {
-      let token = Token::from_symbol(first_char);
-      if token.is_err() {
-        return Err(token.unwrap_err());
+      self.bump();
+      if SYMBOLS.contains(&first_char) {
+        Ok(Token::Symbol(first_char))
+      } else {
+        Err(format!("Invalid symbol: {}", first_char))
       }
-      idx = 1;
-      res.push(token.unwrap());
-    }
+    };
+    Some(match token {
+      Ok(token) => Ok((token, Span::new(start.0, start.1, self.line, self.column))),
+      Err(message) => Err(self.error(message, start)),
+    })
+  }
+}
 
-    if idx < input_word.len() {
-      let tokens = Token::from_word(&input_word[idx..]);
-      if tokens.is_err() {
-        return Err(tokens.unwrap_err());
-      }
-      res.append(&mut tokens.unwrap());
-    }
-    return Ok(res);
+impl Iterator for Lexer {
+  type Item = Result<(Token, Span), LexError>;
+
+  fn next(&mut self) -> Option<Self::Item> {
+    self.next_token()
   }
+}
 
-  fn merge_result(r1: ResultType, r2: ResultType) -> ResultType {
-    if r1.is_err() {
-      return r1;
-    }
-    if r2.is_err() {
-      return r2;
+impl Token {
+  /// Source text of the token.
+  pub fn lexeme(&self) -> String {
+    match self {
+      Token::KeyWord(k) => k.clone(),
+      Token::Symbol(c) => c.to_string(),
+      Token::Identifier(i) => i.clone(),
+      Token::IntVal(i) => i.to_string(),
+      Token::StringVal(s) => format!("\"{}\"", s),
+      Token::None => "".to_string(),
     }
-    let mut ret = r1.unwrap();
-    ret.append(&mut r2.unwrap());
-    Ok(ret)
   }
 }
 
-impl ToString for Token {
-  fn to_string(&self) -> String {
-    match self {
-      Token::KeyWord(_) => "keyword".to_string(),
-      Token::Symbol(_) => "symbol".to_string(),
-      Token::Identifier(_) => "identifier".to_string(),
-      Token::IntVal(_) => "integerConstant".to_string(),
-      Token::StringVal(_) => "stringConstant".to_string(),
-      _ => "Dump".to_string(),
+impl std::fmt::Display for Token {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    let kind = match self {
+      Token::KeyWord(_) => "keyword",
+      Token::Symbol(_) => "symbol",
+      Token::Identifier(_) => "identifier",
+      Token::IntVal(_) => "integerConstant",
+      Token::StringVal(_) => "stringConstant",
+      _ => "Dump",
+    };
+    write!(f, "{}", kind)
+  }
+}
+
+/// Where a token sits in its source, lines and columns start at 1 and the end
+/// column is one past the last character.
+#[derive(std::cmp::PartialEq, Debug, Clone, Copy, Default)]
+pub struct Span {
+  pub start_line: usize,
+  pub start_column: usize,
+  pub end_line: usize,
+  pub end_column: usize,
+}
+
+impl Span {
+  pub fn new(start_line: usize, start_column: usize, end_line: usize, end_column: usize) -> Self {
+    Self {
+      start_line,
+      start_column,
+      end_line,
+      end_column,
     }
   }
+
+  /// Span of a token on a single line.
+  pub fn on_line(line: usize, start_column: usize, end_column: usize) -> Self {
+    Span::new(line, start_column, line, end_column)
+  }
+
+  /// From the start of this span to the end of `end`.
+  pub fn to(&self, end: Span) -> Span {
+    Span::new(
+      self.start_line,
+      self.start_column,
+      end.end_line,
+      end.end_column,
+    )
+  }
+
+  /// Whether the (line, column) position is inside the span.
+  pub fn contains(&self, line: usize, column: usize) -> bool {
+    (line, column) >= (self.start_line, self.start_column)
+      && (line, column) < (self.end_line, self.end_column)
+  }
 }
 
 #[derive(std::cmp::PartialEq, Debug, Clone)]
 pub struct TokenDescriptor {
   token: Token,
   file: String,
-  line: usize,
-  position: usize,
+  span: Span,
 }
 
 impl TokenDescriptor {
-  pub fn new(token: Token, file: String, line: usize, position: usize) -> Self {
-    Self {
-      token,
-      file,
-      line,
-      position,
+  pub fn new(token: Token, file: String, span: Span) -> Self {
+    Self { token, file, span }
+  }
+
+  pub fn token(&self) -> &Token {
+    &self.token
+  }
+
+  pub fn file(&self) -> &String {
+    &self.file
+  }
+
+  pub fn span(&self) -> Span {
+    self.span
+  }
+
+  pub fn line(&self) -> usize {
+    self.span.start_line
+  }
+
+  pub fn column(&self) -> usize {
+    self.span.start_column
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn tokens(source: &str) -> Vec<Result<(Token, Span), LexError>> {
+    Lexer::new(source.to_string()).collect()
+  }
+
+  fn error(message: &str, span: Span) -> LexError {
+    LexError {
+      message: message.to_string(),
+      span,
+      token: None,
     }
   }
+
+  #[test]
+  fn integer_constants_up_to_32767() {
+    assert_eq!(
+      tokens("32767"),
+      vec![Ok((Token::IntVal(32767), Span::on_line(1, 1, 6)))]
+    );
+    for digits in ["32768", "40000", "123456"] {
+      let error = LexError {
+        token: Some(Token::IntVal(32767)),
+        ..error(
+          &format!("Integer constant out of range: {}", digits),
+          Span::on_line(1, 3, 3 + digits.len()),
+        )
+      };
+      assert_eq!(tokens(&format!("x={};", digits))[2], Err(error));
+    }
+  }
+
+  #[test]
+  fn strings_keep_comment_markers() {
+    assert_eq!(
+      tokens("\"a // b /* c */\""),
+      vec![Ok((
+        Token::StringVal("a // b /* c */".to_string()),
+        Span::on_line(1, 1, 17)
+      ))]
+    );
+    let error = error("Unterminated string", Span::on_line(1, 1, 5));
+    assert_eq!(
+      tokens("\"abc\nx"),
+      vec![
+        Err(error),
+        Ok((Token::Identifier("x".to_string()), Span::on_line(2, 1, 2)))
+      ]
+    );
+  }
+
+  #[test]
+  fn comments_are_skipped_and_kept() {
+    let mut lexer = Lexer::new("let/* a\n b */x // c\n/** d */;".to_string());
+    let found: Vec<_> = lexer.by_ref().map(|t| t.unwrap()).collect();
+    assert_eq!(
+      found,
+      vec![
+        (Token::KeyWord("let".to_string()), Span::on_line(1, 1, 4)),
+        (Token::Identifier("x".to_string()), Span::on_line(2, 6, 7)),
+        (Token::Symbol(';'), Span::on_line(3, 9, 10)),
+      ]
+    );
+    let comments: Vec<_> = lexer
+      .comments()
+      .iter()
+      .map(|c| (&c.text[..], c.span))
+      .collect();
+    assert_eq!(
+      comments,
+      vec![
+        ("/* a\n b */", Span::new(1, 4, 2, 6)),
+        ("// c", Span::on_line(2, 8, 12)),
+        ("/** d */", Span::on_line(3, 1, 9)),
+      ]
+    );
+
+    let error = error("Unterminated comment", Span::new(1, 3, 2, 2));
+    assert_eq!(
+      tokens("x /* y\nz"),
+      vec![
+        Ok((Token::Identifier("x".to_string()), Span::on_line(1, 1, 2))),
+        Err(error)
+      ]
+    );
+  }
+
+  #[test]
+  fn tabs_count_as_one_column() {
+    assert_eq!(
+      tokens("\tdo\tf();"),
+      vec![
+        Ok((Token::KeyWord("do".to_string()), Span::on_line(1, 2, 4))),
+        Ok((Token::Identifier("f".to_string()), Span::on_line(1, 5, 6))),
+        Ok((Token::Symbol('('), Span::on_line(1, 6, 7))),
+        Ok((Token::Symbol(')'), Span::on_line(1, 7, 8))),
+        Ok((Token::Symbol(';'), Span::on_line(1, 8, 9))),
+      ]
+    );
+  }
+
+  #[test]
+  fn invalid_symbols() {
+    let error = error("Invalid symbol: #", Span::on_line(1, 3, 4));
+    assert_eq!(tokens("a #")[1], Err(error));
+  }
 }
@@ -39,7 +39,7 @@ impl CommandType {
   }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Command {
   cmd_type: CommandType,
   arg1: Option<String>,
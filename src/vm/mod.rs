@@ -1,4 +1,5 @@
 pub mod commands;
 pub mod segment_type;
+pub mod source_map;
 pub mod vm_translator;
 pub mod vm_writer;
@@ -0,0 +1,48 @@
+use std::fmt;
+
+/// Jack source a vm command was generated from.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SourcePosition {
+  // File name of the class, e.g. "Main.jack".
+  pub file: String,
+  pub line: usize,
+  // Vm name of the subroutine, e.g. "Main.main".
+  pub subroutine: String,
+}
+
+impl fmt::Display for SourcePosition {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{}:{}", self.file, self.line)
+  }
+}
+
+/// Jack source of every command of a vm file, by vm line. Written next to the
+/// vm file as `<Class>.vm.map`, one `<vm line> <file>:<line> <subroutine>`
+/// line per command.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SourceMap {
+  entries: Vec<(usize, SourcePosition)>,
+}
+
+impl SourceMap {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Record the source of the command at `vm_line`, counted from 1.
+  pub fn push(&mut self, vm_line: usize, position: SourcePosition) {
+    self.entries.push((vm_line, position));
+  }
+
+  pub fn entries(&self) -> &[(usize, SourcePosition)] {
+    &self.entries
+  }
+
+  pub fn to_text(&self) -> String {
+    self
+      .entries
+      .iter()
+      .map(|(vm_line, position)| format!("{} {} {}\n", vm_line, position, position.subroutine))
+      .collect()
+  }
+}
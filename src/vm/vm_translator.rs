@@ -1,28 +1,87 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::str::FromStr;
 
 use crate::vm::commands::{Command, CommandType, OperandNum};
 use crate::vm::segment_type::SegmentType;
 
+/// When a translation starts with the bootstrap code.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Bootstrap {
+  // When one of the translated functions is Sys.init.
+  #[default]
+  Auto,
+  Always,
+  Never,
+}
+
+impl FromStr for Bootstrap {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s {
+      "auto" => Ok(Bootstrap::Auto),
+      "always" => Ok(Bootstrap::Always),
+      "never" => Ok(Bootstrap::Never),
+      _ => Err(format!(
+        "unknown bootstrap '{}', use auto, always or never",
+        s
+      )),
+    }
+  }
+}
+
+// Routines shared by every call site, in the order `finish` writes them.
+const CALL_ROUTINE: &str = "VM$CALL";
+const RETURN_ROUTINE: &str = "VM$RETURN";
+const COMPARE_ROUTINES: [(&str, &str, &str); 3] = [
+  ("eq", "VM$EQ", "D;JEQ"),
+  ("gt", "VM$GT", "D;JGT"),
+  ("lt", "VM$LT", "D;JLT"),
+];
+
 pub struct AssembleCodeGenerator {
   cmp_counter: usize,
-  current_file_static_count: usize,
-  static_offset: usize,
-  function_called_time: HashMap<String, usize>,
+  // File name without extension, statics are named `File.i`.
+  current_file: String,
+  // Calls made by each function, numbering its return labels.
+  calls_made: HashMap<String, usize>,
+  // Function being translated, labels are scoped to it.
+  current_function: String,
+  // Calls, returns and comparisons jump to one shared routine instead of
+  // repeating its code, which keeps OS-linked programs in the ROM.
+  shared_routines: bool,
+  used_routines: HashSet<&'static str>,
 }
 
 impl AssembleCodeGenerator {
   pub fn new() -> Self {
     Self {
       cmp_counter: 0,
-      current_file_static_count: 0,
-      static_offset: 0,
-      function_called_time: HashMap::default(),
+      current_file: String::new(),
+      calls_made: HashMap::default(),
+      current_function: String::new(),
+      shared_routines: true,
+      used_routines: HashSet::new(),
     }
   }
 
-  pub fn finish_one_file(&mut self) {
-    self.static_offset += self.current_file_static_count;
-    self.current_file_static_count = 0;
+  /// Whether calls, returns and comparisons jump to shared routines, `false`
+  /// repeats their code at every use.
+  pub fn with_shared_routines(mut self, shared_routines: bool) -> Self {
+    self.shared_routines = shared_routines;
+    self
+  }
+
+  /// Commands translated next come from `file_name`, e.g. `Main` for
+  /// `Main.vm`.
+  pub fn start_file(&mut self, file_name: &str) {
+    self.current_file = file_name.to_string();
+    self.current_function.clear();
+  }
+
+  // Statics are only visible in their file, the assembler allocates them.
+  fn static_symbol(&self, index: i16) -> String {
+    format!("@{}.{}", self.current_file, index)
   }
 
   fn get_current_cmp_str(&mut self) -> (String, String) {
@@ -33,48 +92,92 @@ impl AssembleCodeGenerator {
     self.cmp_counter += 1;
     ret
   }
-  // static mut CMP_COUNTER: usize = 0;
-
-  // fn get_current_cmp_str() -> (String, String) {
-  //   unsafe {
-  //     let ret = (
-  //       format!("CMPSTART{}", CMP_COUNTER),
-  //       format!("CMPEND{}", CMP_COUNTER),
-  //     );
-  //     CMP_COUNTER += 1;
-  //     ret
-  //   }
-  // }
 
-  pub fn init_env() -> Vec<String> {
-    vec![
-      String::from("@261"),
-      // String::from("@256"),
+  /// SP = 256, then `call Sys.init 0` with a frame like any other call. The
+  /// program halts if Sys.init ever returns.
+  pub fn bootstrap(&mut self) -> Vec<String> {
+    let mut ret = vec![
+      String::from("@256"),
       String::from("D=A"),
       String::from("@R0"),
       String::from("M=D"),
-      // String::from("@-1"),
-      // String::from("D=A"),
-      // String::from("@R1"),
-      // String::from("M=D"),
-      // String::from("@-2"),
-      // String::from("D=A"),
-      // String::from("@R2"),
-      // String::from("M=D"),
-      // String::from("@-3"),
-      // String::from("D=A"),
-      // String::from("@R3"),
-      // String::from("M=D"),
-      // String::from("@-4"),
-      // String::from("D=A"),
-      // String::from("@R4"),
-      // String::from("M=D"),
-    ]
+    ];
+    // Its return label is BOOTSTRAP$ret.1, like the halt loop below.
+    self.current_function = String::from("BOOTSTRAP");
+    ret.append(&mut self.handle_call(Command::from_str("call Sys.init 0")));
+    self.current_function.clear();
+    ret.append(&mut vec![
+      String::from("(BOOTSTRAP$HALT)"),
+      String::from("@BOOTSTRAP$HALT"),
+      String::from("0;JMP"),
+    ]);
+    ret
+  }
+
+  /// The shared routines the translated commands jump to, written after the
+  /// last file. The program halts before them if it runs past its end.
+  pub fn finish(&mut self) -> Vec<String> {
+    if self.used_routines.is_empty() {
+      return vec![];
+    }
+    let mut ret = vec![
+      String::from("(VM$END)"),
+      String::from("@VM$END"),
+      String::from("0;JMP"),
+    ];
+    if self.used_routines.contains(CALL_ROUTINE) {
+      ret.push(format!("({})", CALL_ROUTINE));
+      ret.append(&mut AssembleCodeGenerator::call_frame());
+      ret.append(&mut vec![
+        String::from("@R14"),
+        String::from("A=M"),
+        String::from("0;JMP"),
+      ]);
+    }
+    if self.used_routines.contains(RETURN_ROUTINE) {
+      ret.push(format!("({})", RETURN_ROUTINE));
+      ret.append(&mut AssembleCodeGenerator::return_frame());
+    }
+    for (_, routine, jump) in COMPARE_ROUTINES {
+      if !self.used_routines.contains(routine) {
+        continue;
+      }
+      // Replace x by true, and by false unless x - y passes the jump.
+      ret.append(&mut vec![
+        format!("({})", routine),
+        String::from("@R0"),
+        String::from("AM=M-1"),
+        String::from("D=M"),
+        String::from("A=A-1"),
+        String::from("D=M-D"),
+        String::from("M=-1"),
+        format!("@{}$TRUE", routine),
+        String::from(jump),
+        String::from("@R0"),
+        String::from("A=M-1"),
+        String::from("M=0"),
+        format!("({}$TRUE)", routine),
+        String::from("@R15"),
+        String::from("A=M"),
+        String::from("0;JMP"),
+      ]);
+    }
+    ret
   }
 
-  pub fn bootstrap() -> Vec<String> {
-    // Start with Sys.init function.
-    vec![String::from("@Sys.init"), String::from("0;JMP")]
+  // Jump to a shared routine, which jumps back to R15.
+  fn jump_to_routine(&mut self, routine: &'static str) -> Vec<String> {
+    self.used_routines.insert(routine);
+    let (back, _) = self.get_current_cmp_str();
+    vec![
+      format!("@{}", back),
+      String::from("D=A"),
+      String::from("@R15"),
+      String::from("M=D"),
+      format!("@{}", routine),
+      String::from("0;JMP"),
+      format!("({})", back),
+    ]
   }
 
   pub fn get_asm(&mut self, cmd: Command) -> Vec<String> {
@@ -87,10 +190,7 @@ impl AssembleCodeGenerator {
       CommandType::Function => self.handle_function(cmd),
       CommandType::Return => self.handle_return(cmd),
       CommandType::Call => self.handle_call(cmd),
-      _ => {
-        // panic!("unsupported command type {:?}", cmd.cmd_type());
-        vec![]
-      }
+      _ => vec![],
     }
   }
 
@@ -171,11 +271,7 @@ impl AssembleCodeGenerator {
         }
         // data = static.i (variable)
         SegmentType::Static => {
-          self.current_file_static_count += 1;
-          vec![
-            format!("@static{}", arg2 as usize + self.static_offset),
-            String::from("D=M"),
-          ]
+          vec![self.static_symbol(arg2), String::from("D=M")]
         }
         _ => vec![],
       };
@@ -215,10 +311,7 @@ impl AssembleCodeGenerator {
         }
         // data = static.i (variable)
         SegmentType::Static => {
-          vec![
-            format!("@static{}", arg2 as usize + self.static_offset),
-            String::from("D=A"),
-          ]
+          vec![self.static_symbol(arg2), String::from("D=A")]
         }
         _ => vec![],
       };
@@ -244,19 +337,23 @@ impl AssembleCodeGenerator {
   }
 
   fn handle_arithmetic(&mut self, cmd: Command) -> Vec<String> {
-    // assert!(CommandType::Arithmetic == cmd.cmd_type());
-    let mut ret = AssembleCodeGenerator::load_sp_to_d();
     let operand_num = match cmd.cmd_type() {
       CommandType::Arithmetic(operand_num) => operand_num,
       _ => return vec![],
     };
-    // let CommandType::Arithmetic(operand_num) = cmd.cmd_type();
+    let op = cmd.arg1().unwrap();
+    if let Some(&(_, routine, _)) = COMPARE_ROUTINES.iter().find(|(name, _, _)| *name == op) {
+      if self.shared_routines {
+        return self.jump_to_routine(routine);
+      }
+    }
+    let mut ret = AssembleCodeGenerator::load_sp_to_d();
     ret.append(&mut vec![String::from("@R13"), String::from("M=D")]);
     if operand_num == OperandNum::TwoOperand {
       ret.append(&mut AssembleCodeGenerator::load_sp_to_d());
       ret.append(&mut vec![String::from("@R13")]);
     }
-    match &cmd.arg1().unwrap()[..] {
+    match &op[..] {
       "add" => {
         ret.push(String::from("D=D+M"));
       }
@@ -316,9 +413,6 @@ impl AssembleCodeGenerator {
       _ => (),
     };
     ret.append(&mut vec![
-      // save d = r13
-      // String::from("@R13"),
-      // String::from("M=D"),
       String::from("@R0"),
       String::from("A=M"),
       String::from("M=D"),
@@ -327,55 +421,86 @@ impl AssembleCodeGenerator {
     ret
   }
 
+  // `Function$label`, as labels are only visible in their function.
+  fn function_label(&self, label: &str) -> String {
+    if self.current_function.is_empty() {
+      label.to_string()
+    } else {
+      format!("{}${}", self.current_function, label)
+    }
+  }
+
   fn handle_goto(&self, cmd: Command) -> Vec<String> {
     assert_eq!(cmd.cmd_type(), CommandType::Goto);
-    let label = cmd.arg1().unwrap().to_uppercase();
+    let label = self.function_label(&cmd.arg1().unwrap());
     vec![format!("@{}", label), String::from("0;JMP")]
   }
 
   fn handle_label(&self, cmd: Command) -> Vec<String> {
     assert_eq!(cmd.cmd_type(), CommandType::Label);
-    let label = cmd.arg1().unwrap().to_uppercase();
+    let label = self.function_label(&cmd.arg1().unwrap());
     vec![format!("({})", label)]
   }
 
   fn handle_condition_goto(&self, cmd: Command) -> Vec<String> {
     assert_eq!(cmd.cmd_type(), CommandType::If);
-    let label = cmd.arg1().unwrap().to_uppercase();
+    let label = self.function_label(&cmd.arg1().unwrap());
     let mut ret = AssembleCodeGenerator::load_sp_to_d();
-    ret.append(&mut vec![
-      // String::from("D=D+1"),
-      format!("@{}", label),
-      String::from("D;JNE"),
-    ]);
+    ret.append(&mut vec![format!("@{}", label), String::from("D;JNE")]);
     ret
   }
 
-  fn generate_return_addr_for_call(&mut self, function_name: &String) -> String {
-    // TODO: global store return addr
-    let new_time = match &self.function_called_time.get(function_name) {
-      None => {
-        self.function_called_time.insert(function_name.clone(), 1);
-        1
-      }
-      Some(time) => {
-        let new_time = 1 + **time;
-        self
-          .function_called_time
-          .insert(function_name.clone(), new_time);
-        new_time
-      }
-    };
-    format!("{}_{}", function_name.clone(), new_time)
+  // `Caller$ret.n` for the n-th call made by the function being translated.
+  fn return_label(&mut self) -> String {
+    let calls = self
+      .calls_made
+      .entry(self.current_function.clone())
+      .or_insert(0);
+    *calls += 1;
+    let label = format!("ret.{}", calls);
+    self.function_label(&label)
   }
 
   fn handle_call(&mut self, cmd: Command) -> Vec<String> {
     assert_eq!(cmd.cmd_type(), CommandType::Call);
-    let ret_addr = self.generate_return_addr_for_call(&cmd.arg1().unwrap());
-    // let ret_addr = unsafe { generate_return_addr_for_call(&cmd.arg1().unwrap()) };
+    let ret_addr = self.return_label();
     let n = cmd.arg2();
-    let mut ret = vec![format!("@{}", ret_addr), String::from("D=A")];
-    ret.append(&mut AssembleCodeGenerator::set_d_to_sp());
+    let mut ret = vec![
+      format!("@{}", n),
+      String::from("D=A"),
+      String::from("@R13"),
+      String::from("M=D"),
+    ];
+    if self.shared_routines {
+      // R13 = n, R14 = f, D = return address
+      self.used_routines.insert(CALL_ROUTINE);
+      ret.append(&mut vec![
+        format!("@{}", cmd.arg1().unwrap()),
+        String::from("D=A"),
+        String::from("@R14"),
+        String::from("M=D"),
+        format!("@{}", ret_addr),
+        String::from("D=A"),
+        format!("@{}", CALL_ROUTINE),
+        String::from("0;JMP"),
+        format!("({})", ret_addr),
+      ]);
+      return ret;
+    }
+    ret.append(&mut vec![format!("@{}", ret_addr), String::from("D=A")]);
+    ret.append(&mut AssembleCodeGenerator::call_frame());
+    ret.append(&mut vec![
+      format!("@{}", cmd.arg1().unwrap()),
+      String::from("0;JMP"),     // goto f
+      format!("({})", ret_addr), // (ret-addr)
+    ]);
+    ret
+  }
+
+  // Push the return address in D and the caller's segments, ARG = SP-R13-5
+  // and LCL = SP.
+  fn call_frame() -> Vec<String> {
+    let mut ret = AssembleCodeGenerator::set_d_to_sp();
     for i in 1..5 {
       ret.append(&mut vec![format!("@{}", i), String::from("D=M")]);
       ret.append(&mut AssembleCodeGenerator::set_d_to_sp());
@@ -383,8 +508,8 @@ impl AssembleCodeGenerator {
     ret.append(&mut vec![
       String::from("@0"),
       String::from("D=M"),
-      format!("@{}", n),
-      String::from("D=D-A"),
+      String::from("@R13"),
+      String::from("D=D-M"),
       String::from("@5"),
       String::from("D=D-A"),
       String::from("@2"),
@@ -393,15 +518,13 @@ impl AssembleCodeGenerator {
       String::from("D=M"),
       String::from("@1"),
       String::from("M=D"), // LCL = SP
-      format!("@{}", cmd.arg1().unwrap()),
-      String::from("0;JMP"),     // goto f
-      format!("({})", ret_addr), // (ret-addr)
     ]);
     ret
   }
 
-  fn handle_function(&self, cmd: Command) -> Vec<String> {
+  fn handle_function(&mut self, cmd: Command) -> Vec<String> {
     assert_eq!(cmd.cmd_type(), CommandType::Function);
+    self.current_function = cmd.arg1().unwrap();
     let mut ret = vec![format!("({})", cmd.arg1().unwrap())];
     for _ in 0..cmd.arg2() {
       ret.append(&mut AssembleCodeGenerator::set_constant_to_sp(0));
@@ -409,8 +532,16 @@ impl AssembleCodeGenerator {
     ret
   }
 
-  fn handle_return(&self, cmd: Command) -> Vec<String> {
+  fn handle_return(&mut self, cmd: Command) -> Vec<String> {
     assert_eq!(cmd.cmd_type(), CommandType::Return);
+    if self.shared_routines {
+      self.used_routines.insert(RETURN_ROUTINE);
+      return vec![format!("@{}", RETURN_ROUTINE), String::from("0;JMP")];
+    }
+    AssembleCodeGenerator::return_frame()
+  }
+
+  fn return_frame() -> Vec<String> {
     let mut ret = vec![
       String::from("@1"),
       String::from("D=M"),
@@ -418,8 +549,6 @@ impl AssembleCodeGenerator {
       String::from("M=D"), // FRAME = LCL,
       String::from("@5"),
       String::from("D=D-A"),
-      // String::from("@R14"),
-      // String::from("M=D"),
       String::from("A=D"),
       String::from("D=M"),
       String::from("@R14"),
@@ -462,3 +591,176 @@ impl AssembleCodeGenerator {
     ret
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::asm::assembler::Assembler;
+  use crate::emulator::cpu::Cpu;
+  use crate::emulator::RunResult;
+  use crate::os::OS_CLASSES;
+  use crate::parser::jack::Parser;
+  use crate::{compile_project, CompileOptions};
+
+  fn translate(files: &[(&str, &str)]) -> Vec<String> {
+    let mut writer = AssembleCodeGenerator::new();
+    let mut asm = writer.bootstrap();
+    for (file_name, source) in files {
+      writer.start_file(file_name);
+      for line in source.lines() {
+        asm.append(&mut writer.get_asm(Command::from_str(line)));
+      }
+    }
+    asm.append(&mut writer.finish());
+    asm
+  }
+
+  #[test]
+  fn statics_are_per_file() {
+    // Foo only pops its static, which once left it sharing slots with the
+    // statics of the next file.
+    let foo = "function Foo.set 0
+      push argument 0
+      pop static 0
+      push constant 0
+      return";
+    let sys = "function Sys.init 0
+      push constant 5
+      pop static 0
+      push constant 7
+      call Foo.set 1
+      pop temp 0
+      push static 0
+      pop temp 1
+      label END
+      goto END";
+    let asm = translate(&[("Foo", foo), ("Sys", sys)]);
+    assert!(asm.contains(&"@Foo.0".to_string()));
+    assert!(asm.contains(&"@Sys.0".to_string()));
+
+    let mut cpu = Cpu::new();
+    cpu.load_asm(&asm).unwrap();
+    cpu.run(10_000);
+    // temp 1
+    assert_eq!(cpu.ram(6), 5);
+  }
+
+  #[test]
+  fn os_linked_program_fits_and_runs() {
+    let main = "class Main {
+      function void main() {
+        do Output.printInt(Math.multiply(123, 45));
+        do Memory.poke(8000, Math.divide(1000, 7) + Math.sqrt(144));
+        return;
+      }
+    }";
+    let mut parsers = vec![Parser::new_from_source("Main.jack", main)];
+    for (class_name, source) in OS_CLASSES {
+      parsers.push(Parser::new_from_source(
+        &format!("{}.jack", class_name),
+        source,
+      ));
+    }
+    let classes: Vec<_> = compile_project(parsers, &CompileOptions::default())
+      .into_iter()
+      .map(|class| (class.class_name, class.result.unwrap()))
+      .collect();
+    let files: Vec<_> = classes
+      .iter()
+      .map(|(name, vm)| (name.as_str(), vm.as_str()))
+      .collect();
+    let asm = translate(&files);
+    assert!(Assembler::new().assemble_lines(&asm).is_ok());
+
+    let mut cpu = Cpu::new();
+    cpu.load_asm(&asm).unwrap();
+    assert!(matches!(cpu.run(5_000_000), RunResult::Halted(_)));
+    assert_eq!(cpu.ram(8000), 142 + 12);
+  }
+
+  #[test]
+  fn labels_are_per_function() {
+    // Both functions loop on LOOP, each must jump to its own.
+    let sys = "function Sys.init 0
+      push constant 3
+      call Sys.count 1
+      pop temp 1
+      push constant 5
+      call Main.double 1
+      pop temp 2
+      label LOOP
+      goto LOOP
+      function Sys.count 1
+      label LOOP
+      push local 0
+      push constant 1
+      add
+      pop local 0
+      push argument 0
+      push constant 1
+      sub
+      pop argument 0
+      push argument 0
+      if-goto LOOP
+      push local 0
+      return
+      function Main.double 0
+      push constant 0
+      label LOOP
+      push constant 2
+      add
+      push argument 0
+      push constant 1
+      sub
+      pop argument 0
+      push argument 0
+      if-goto LOOP
+      return";
+    let asm = translate(&[("Sys", sys)]);
+    assert!(asm.contains(&"(Sys.count$LOOP)".to_string()));
+    assert!(asm.contains(&"(Main.double$LOOP)".to_string()));
+
+    let mut cpu = Cpu::new();
+    cpu.load_asm(&asm).unwrap();
+    cpu.run(10_000);
+    assert_eq!(cpu.ram(6), 3);
+    assert_eq!(cpu.ram(7), 10);
+  }
+
+  #[test]
+  fn return_labels_name_the_caller() {
+    // Return labels once were the callee and a count, the first call of
+    // Foo.bar returned to the label of the function Foo.bar_1.
+    let foo = "function Foo.bar 0
+      push constant 1
+      return
+      function Foo.bar_1 0
+      call Foo.bar 0
+      push constant 10
+      add
+      return";
+    let sys = "function Sys.init 0
+      call Foo.bar 0
+      pop temp 0
+      call Foo.bar_1 0
+      pop temp 1
+      label END
+      goto END";
+    let asm = translate(&[("Foo", foo), ("Sys", sys)]);
+    for label in [
+      "BOOTSTRAP$ret.1",
+      "Foo.bar_1$ret.1",
+      "Sys.init$ret.1",
+      "Sys.init$ret.2",
+    ] {
+      assert!(asm.contains(&format!("({})", label)), "{}", label);
+    }
+    assert!(Assembler::new().assemble_lines(&asm).is_ok());
+
+    let mut cpu = Cpu::new();
+    cpu.load_asm(&asm).unwrap();
+    cpu.run(10_000);
+    assert_eq!(cpu.ram(5), 1);
+    assert_eq!(cpu.ram(6), 11);
+  }
+}
@@ -1,30 +1,84 @@
 use crate::common::{new_output, panic_writer, OutputTarget};
 
 use crate::vm::segment_type::SegmentType;
+use crate::vm::source_map::{SourceMap, SourcePosition};
 
 pub struct VmWriter {
   output: OutputTarget,
+  // Lines written so far.
+  line: usize,
+  // Source of the commands written next.
+  position: Option<SourcePosition>,
+  source_map: SourceMap,
+  // Write a `// Main.jack:14` comment whenever the source line changes.
+  source_comments: bool,
+  last_comment: Option<(String, usize)>,
 }
 
 impl VmWriter {
   pub fn new(source: &str) -> Self {
+    VmWriter::new_with_output(new_output(source))
+  }
+
+  pub fn new_with_output(output: OutputTarget) -> Self {
     Self {
-      output: new_output(source),
+      output,
+      line: 0,
+      position: None,
+      source_map: SourceMap::new(),
+      source_comments: false,
+      last_comment: None,
+    }
+  }
+
+  pub fn with_source_comments(mut self, enabled: bool) -> Self {
+    self.source_comments = enabled;
+    self
+  }
+
+  /// Commands written next come from `line` of `subroutine` in `file`.
+  pub fn set_subroutine(&mut self, file: &str, subroutine: &str, line: usize) {
+    self.position = Some(SourcePosition {
+      file: file.to_string(),
+      line,
+      subroutine: subroutine.to_string(),
+    });
+  }
+
+  /// Commands written next come from `line` of the current subroutine.
+  pub fn set_line(&mut self, line: usize) {
+    if let Some(position) = &mut self.position {
+      position.line = line;
+    }
+  }
+
+  pub fn source_map(&self) -> &SourceMap {
+    &self.source_map
+  }
+
+  fn write(&mut self, command: String) {
+    if let Some(position) = &self.position {
+      let source = (position.file.clone(), position.line);
+      if self.source_comments && self.last_comment.as_ref() != Some(&source) {
+        panic_writer(
+          format!("// {}\n", position),
+          self.output.clone().borrow_mut(),
+        );
+        self.line += 1;
+        self.last_comment = Some(source);
+      }
+      self.source_map.push(self.line + 1, position.clone());
     }
+    panic_writer(command + "\n", self.output.clone().borrow_mut());
+    self.line += 1;
   }
 
   pub fn write_push(&mut self, seg_t: SegmentType, idx: usize) {
-    panic_writer(
-      format!("push {} {}\n", seg_t.to_vm_string(), idx),
-      self.output.clone().borrow_mut(),
-    );
+    self.write(format!("push {} {}", seg_t.to_vm_string(), idx));
   }
 
   pub fn write_pop(&mut self, seg_t: SegmentType, idx: usize) {
-    panic_writer(
-      format!("pop {} {}\n", seg_t.to_vm_string(), idx),
-      self.output.clone().borrow_mut(),
-    );
+    self.write(format!("pop {} {}", seg_t.to_vm_string(), idx));
   }
 
   pub fn write_arithmetic(&mut self, op: char) {
@@ -44,52 +98,32 @@ impl VmWriter {
       '/' => "call Math.divide 2",
       _ => "",
     };
-    panic_writer(cmd.to_string() + "\n", self.output.clone().borrow_mut());
+    self.write(cmd.to_string());
   }
 
   pub fn write_label(&mut self, label: String) {
-    panic_writer(
-      format!("{} {}\n", "label", label),
-      self.output.clone().borrow_mut(),
-    );
+    self.write(format!("{} {}", "label", label));
   }
   pub fn write_goto(&mut self, label: String) {
-    panic_writer(
-      format!("{} {}\n", "goto", label),
-      self.output.clone().borrow_mut(),
-    );
+    self.write(format!("{} {}", "goto", label));
   }
 
   pub fn write_if(&mut self, label: String) {
-    panic_writer(
-      format!("{} {}\n", "if-goto", label),
-      self.output.clone().borrow_mut(),
-    );
+    self.write(format!("{} {}", "if-goto", label));
   }
 
   pub fn write_call(&mut self, name: String, argc: usize) {
-    panic_writer(
-      format!("call {} {}\n", name, argc),
-      self.output.clone().borrow_mut(),
-    );
+    self.write(format!("call {} {}", name, argc));
   }
 
   ///
   /// argc: Local variable count.
   pub fn write_func(&mut self, name: String, argc: usize) {
-    panic_writer(
-      format!("function {} {}\n", name, argc),
-      self.output.clone().borrow_mut(),
-    );
+    self.write(format!("function {} {}", name, argc));
   }
 
   pub fn write_return(&mut self) {
-    panic_writer("return\n".to_string(), self.output.clone().borrow_mut());
-  }
-
-  pub fn generate_return_this(&mut self) {
-    self.write_push(SegmentType::Pointer, 0);
-    self.write_return();
+    self.write("return".to_string());
   }
 
   pub fn generate_alloc_this(&mut self, filed_count: usize) {
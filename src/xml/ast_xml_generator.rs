@@ -0,0 +1,280 @@
+use crate::ast::visit::Visitor;
+use crate::ast::*;
+use crate::common::{new_output, OutputTarget};
+use crate::xml::*;
+
+/// Parse tree xml of a class, in the layout of the nand2tetris compare files.
+pub struct AstXMLGenerator {
+  writer: OutputTarget,
+  cur_indent: usize,
+}
+
+impl AstXMLGenerator {
+  pub fn new(source: &str) -> Self {
+    AstXMLGenerator::new_with_output(new_output(source))
+  }
+
+  pub fn new_with_output(writer: OutputTarget) -> Self {
+    Self {
+      writer,
+      cur_indent: 0,
+    }
+  }
+
+  pub fn run(mut self, class: &Class) {
+    self.visit_class(class);
+  }
+
+  fn open(&mut self, tag: &str) {
+    self.indent_write(&format!("<{}>\n", tag));
+    self.cur_indent += 2;
+  }
+
+  fn close(&mut self, tag: &str) {
+    self.cur_indent -= 2;
+    self.indent_write(&format!("</{}>\n", tag));
+  }
+
+  fn indent_write(&self, data: &str) {
+    panic_writer_with_indent(data.to_string(), self.cur_indent, self.writer.clone());
+  }
+
+  fn tag_indent_write(&self, tag: &str, data: &str) {
+    panic_tag_content_with_indent(tag, data, self.cur_indent, self.writer.clone());
+  }
+
+  fn keyword(&self, keyword: &str) {
+    self.tag_indent_write("keyword", keyword);
+  }
+
+  fn symbol(&self, symbol: char) {
+    self.tag_indent_write("symbol", &translate(symbol));
+  }
+
+  fn identifier(&self, ident: &Ident) {
+    self.tag_indent_write("identifier", &ident.name);
+  }
+
+  fn type_name(&self, var_type: &Ident) {
+    if var_type.is_keyword_type() {
+      self.keyword(&var_type.name);
+    } else {
+      self.identifier(var_type);
+    }
+  }
+
+  fn statements(&mut self, statements: &[Statement]) {
+    self.open("statements");
+    for statement in statements {
+      self.visit_statement(statement);
+    }
+    self.close("statements");
+  }
+
+  fn block(&mut self, statements: &[Statement]) {
+    self.symbol('{');
+    self.statements(statements);
+    self.symbol('}');
+  }
+
+  fn condition(&mut self, condition: &Expr) {
+    self.symbol('(');
+    self.visit_expr(condition);
+    self.symbol(')');
+  }
+
+  fn term(&mut self, expr: &Expr) {
+    self.open("term");
+    match expr {
+      Expr::Int { value, .. } => self.tag_indent_write("integerConstant", &value.to_string()),
+      Expr::String { value, .. } => self.tag_indent_write("stringConstant", value),
+      Expr::Keyword { keyword, .. } => self.keyword(keyword),
+      Expr::Var(name) => self.identifier(name),
+      Expr::Index { array, index, .. } => {
+        self.identifier(array);
+        self.symbol('[');
+        self.visit_expr(index);
+        self.symbol(']');
+      }
+      Expr::Call(call) => self.visit_call(call),
+      Expr::Unary { op, operand, .. } => {
+        self.symbol(*op);
+        self.term(operand);
+      }
+      Expr::Paren { expr, .. } => {
+        self.symbol('(');
+        self.visit_expr(expr);
+        self.symbol(')');
+      }
+      Expr::Binary { .. } => unreachable!("binary expressions are written by terms()"),
+    }
+    self.close("term");
+  }
+
+  // term (op term)*, the left operand of a binary expression holds the terms
+  // before its operator.
+  fn terms(&mut self, expr: &Expr) {
+    match expr {
+      Expr::Binary {
+        op, left, right, ..
+      } => {
+        self.terms(left);
+        self.symbol(*op);
+        self.term(right);
+      }
+      _ => self.term(expr),
+    }
+  }
+}
+
+impl Visitor for AstXMLGenerator {
+  fn visit_class(&mut self, class: &Class) {
+    self.open("class");
+    self.keyword("class");
+    self.identifier(&class.name);
+    self.symbol('{');
+    for var_dec in &class.variables {
+      self.visit_var_dec(var_dec);
+    }
+    for subroutine in &class.subroutines {
+      self.visit_subroutine(subroutine);
+    }
+    self.symbol('}');
+    self.close("class");
+  }
+
+  fn visit_var_dec(&mut self, var_dec: &VarDec) {
+    let (tag, keyword) = match var_dec.kind {
+      VarScope::Static => ("classVarDec", "static"),
+      VarScope::Field => ("classVarDec", "field"),
+      _ => ("varDec", "var"),
+    };
+    self.open(tag);
+    self.keyword(keyword);
+    self.type_name(&var_dec.var_type);
+    for (i, name) in var_dec.names.iter().enumerate() {
+      if i > 0 {
+        self.symbol(',');
+      }
+      self.identifier(name);
+    }
+    self.symbol(';');
+    self.close(tag);
+  }
+
+  fn visit_subroutine(&mut self, subroutine: &SubroutineDec) {
+    self.open("subroutineDec");
+    self.keyword(&subroutine.kind.to_string());
+    self.type_name(&subroutine.return_type);
+    self.identifier(&subroutine.name);
+    self.symbol('(');
+    self.open("parameterList");
+    for (i, parameter) in subroutine.parameters.iter().enumerate() {
+      if i > 0 {
+        self.symbol(',');
+      }
+      self.type_name(&parameter.var_type);
+      self.identifier(&parameter.name);
+    }
+    self.close("parameterList");
+    self.symbol(')');
+    self.open("subroutineBody");
+    self.symbol('{');
+    for var_dec in &subroutine.locals {
+      self.visit_var_dec(var_dec);
+    }
+    self.statements(&subroutine.statements);
+    self.symbol('}');
+    self.close("subroutineBody");
+    self.close("subroutineDec");
+  }
+
+  fn visit_statement(&mut self, statement: &Statement) {
+    match statement {
+      Statement::Let {
+        target,
+        index,
+        value,
+        ..
+      } => {
+        self.open("letStatement");
+        self.keyword("let");
+        self.identifier(target);
+        if let Some(index) = index {
+          self.symbol('[');
+          self.visit_expr(index);
+          self.symbol(']');
+        }
+        self.symbol('=');
+        self.visit_expr(value);
+        self.symbol(';');
+        self.close("letStatement");
+      }
+      Statement::If {
+        condition,
+        body,
+        else_body,
+        ..
+      } => {
+        self.open("ifStatement");
+        self.keyword("if");
+        self.condition(condition);
+        self.block(body);
+        if let Some(else_body) = else_body {
+          self.keyword("else");
+          self.block(else_body);
+        }
+        self.close("ifStatement");
+      }
+      Statement::While {
+        condition, body, ..
+      } => {
+        self.open("whileStatement");
+        self.keyword("while");
+        self.condition(condition);
+        self.block(body);
+        self.close("whileStatement");
+      }
+      Statement::Do { call, .. } => {
+        self.open("doStatement");
+        self.keyword("do");
+        self.visit_call(call);
+        self.symbol(';');
+        self.close("doStatement");
+      }
+      Statement::Return { value, .. } => {
+        self.open("returnStatement");
+        self.keyword("return");
+        if let Some(value) = value {
+          self.visit_expr(value);
+        }
+        self.symbol(';');
+        self.close("returnStatement");
+      }
+    }
+  }
+
+  fn visit_call(&mut self, call: &Call) {
+    if let Some(receiver) = &call.receiver {
+      self.identifier(receiver);
+      self.symbol('.');
+    }
+    self.identifier(&call.name);
+    self.symbol('(');
+    self.open("expressionList");
+    for (i, argument) in call.arguments.iter().enumerate() {
+      if i > 0 {
+        self.symbol(',');
+      }
+      self.visit_expr(argument);
+    }
+    self.close("expressionList");
+    self.symbol(')');
+  }
+
+  fn visit_expr(&mut self, expr: &Expr) {
+    self.open("expression");
+    self.terms(expr);
+    self.close("expression");
+  }
+}
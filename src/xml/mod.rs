@@ -1,4 +1,4 @@
-pub mod operation_xml_generator;
+pub mod ast_xml_generator;
 pub mod token_xml_generator;
 
 use std::collections::HashMap;
@@ -1,4 +1,5 @@
 use crate::common::{new_output, panic_writer, OutputTarget};
+use crate::error::CompileError;
 use crate::parser::jack::Parser;
 use crate::token::Token;
 use crate::xml::{translate, RAIIWriter};
@@ -10,13 +11,15 @@ pub struct TokenXMLGenerator {
 
 impl TokenXMLGenerator {
   pub fn new(source: &str, parser: Parser) -> Self {
-    Self {
-      writer: new_output(source),
-      parser,
-    }
+    TokenXMLGenerator::new_with_output(new_output(source), parser)
+  }
+
+  pub fn new_with_output(writer: OutputTarget, parser: Parser) -> Self {
+    Self { writer, parser }
   }
 
-  pub fn run(mut self) {
+  /// Write every token, returns the lexer errors met on the way.
+  pub fn run(mut self) -> Vec<CompileError> {
     let _v = RAIIWriter::new_with_newline("tokens".to_string(), self.writer.clone());
     while let Some(token) = self.parser.next() {
       let _v = RAIIWriter::new(token.to_string(), self.writer.clone());
@@ -30,5 +33,6 @@ impl TokenXMLGenerator {
       };
       panic_writer(format!(" {} ", content), self.writer.clone().borrow_mut());
     }
+    self.parser.take_errors()
   }
 }